@@ -0,0 +1,507 @@
+use std::{cmp, str::FromStr};
+
+use anyhow::anyhow;
+
+use crate::{
+    game::{Player, Rules, State},
+    rng::Rng,
+};
+
+// larger than any heuristic score so a forced win or loss always dominates
+const WIN_SCORE: i64 = 1 << 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    /// Never loses on 3x3, which is searched to the end. Bigger boards are too
+    /// big to solve, there it plays the best move a depth limited search finds.
+    Perfect,
+}
+
+impl Difficulty {
+    /// Plies to search, capped by what the board size allows
+    fn depth(&self, rules: &Rules) -> u32 {
+        let max_depth = search_depth(rules);
+        match self {
+            Difficulty::Easy => 1,
+            Difficulty::Medium => cmp::min(2, max_depth),
+            Difficulty::Hard | Difficulty::Perfect => max_depth,
+        }
+    }
+
+    /// Fixed Elo rating of the level, playing it moves a human's rating like playing
+    /// someone of that strength would
+    pub fn rating(&self) -> u16 {
+        match self {
+            Difficulty::Easy => 800,
+            Difficulty::Medium => 1200,
+            Difficulty::Hard => 1600,
+            Difficulty::Perfect => 2000,
+        }
+    }
+
+    /// Chance of ignoring the search and playing a random move
+    fn blunder_chance(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.3,
+            Difficulty::Medium => 0.1,
+            Difficulty::Hard | Difficulty::Perfect => 0.0,
+        }
+    }
+
+    /// Softmax temperature relative to the spread of move scores,
+    /// None always picks the best move
+    fn temperature(&self) -> Option<f64> {
+        match self {
+            Difficulty::Easy => Some(0.5),
+            Difficulty::Medium => Some(0.2),
+            Difficulty::Hard => Some(0.05),
+            Difficulty::Perfect => None,
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "hard" => Ok(Difficulty::Hard),
+            "perfect" => Ok(Difficulty::Perfect),
+            _ => Err(anyhow!(
+                "Unknown difficulty '{}', expected easy, medium, hard or perfect",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Computer {
+    pub player: Player,
+    pub difficulty: Difficulty,
+    pub seed: u64,
+    rng: Rng,
+}
+
+impl Computer {
+    pub fn new(player: Player, difficulty: Difficulty, seed: u64) -> Self {
+        Computer {
+            player,
+            difficulty,
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Board index of the computers move, None if there is nothing to play.
+    /// Weaker difficulties sometimes pick worse moves, drawn from the seeded
+    /// generator so the same seed and human moves replay the same game.
+    pub fn choose_move(&mut self, state: &State) -> Option<usize> {
+        let depth = self.difficulty.depth(&state.rules);
+        let scores = score_moves(state, self.player, depth);
+
+        if scores.is_empty() {
+            return None;
+        }
+
+        if self.rng.next_f64() < self.difficulty.blunder_chance() {
+            let pick = self.rng.next_u64() as usize % scores.len();
+            return Some(scores[pick].0);
+        }
+
+        let Some(temperature) = self.difficulty.temperature() else {
+            return best_move(&scores);
+        };
+
+        // weight each move by how close it is to the best one, scaled by the
+        // spread of scores so huge win/loss scores and small heuristic scores
+        // both give sensible probabilities
+        let max = scores.iter().map(|(_, score)| *score).max()?;
+        let min = scores.iter().map(|(_, score)| *score).min()?;
+        let spread = cmp::max(max - min, 1) as f64;
+        let weights: Vec<f64> = scores
+            .iter()
+            .map(|(_, score)| (-((max - score) as f64) / (spread * temperature)).exp())
+            .collect();
+
+        let mut pick = self.rng.next_f64() * weights.iter().sum::<f64>();
+        for ((index, _), weight) in scores.iter().zip(weights) {
+            if pick < weight {
+                return Some(*index);
+            }
+            pick -= weight;
+        }
+
+        best_move(&scores)
+    }
+}
+
+fn best_move(scores: &[(usize, i64)]) -> Option<usize> {
+    scores
+        .iter()
+        .max_by_key(|(index, score)| (*score, cmp::Reverse(*index)))
+        .map(|(index, _)| *index)
+}
+
+/// How many plies to look ahead, the classic 3x3 board is searched to the end
+/// while bigger boards fall back to a heuristic evaluation at the horizon
+pub fn search_depth(rules: &Rules) -> u32 {
+    match rules.cells() {
+        0..=9 => 9,
+        10..=16 => 4,
+        _ => 2,
+    }
+}
+
+/// Minimax score of every candidate move for `player`, higher is better.
+/// Each root move gets a full alpha-beta window so the scores are exact
+/// and can be compared against each other, not just used to pick the best.
+pub fn score_moves(state: &State, player: Player, depth: u32) -> Vec<(usize, i64)> {
+    if !state.active || depth == 0 {
+        return Vec::new();
+    }
+
+    let mut search = Search::new(state);
+    let symbol = char::from(&player);
+
+    search
+        .candidates()
+        .into_iter()
+        .map(|index| {
+            search.board[index] = symbol;
+            let score = if search.wins_through(index) {
+                WIN_SCORE
+            } else {
+                -search.negamax(
+                    opponent(symbol),
+                    depth - 1,
+                    1,
+                    -WIN_SCORE - 1,
+                    WIN_SCORE + 1,
+                )
+            };
+            search.board[index] = ' ';
+            (index, score)
+        })
+        .collect()
+}
+
+fn opponent(symbol: char) -> char {
+    match symbol {
+        'X' => 'O',
+        _ => 'X',
+    }
+}
+
+struct Search {
+    board: Vec<char>,
+    size: usize,
+    win_length: usize,
+}
+
+impl Search {
+    fn new(state: &State) -> Self {
+        Search {
+            board: state.board.clone(),
+            size: state.rules.size as usize,
+            win_length: state.rules.win_length as usize,
+        }
+    }
+
+    // negamax formulation of minimax: the score is always from the point of view
+    // of `to_move`, and the previous move is known not to have ended the game
+    fn negamax(&mut self, to_move: char, depth: u32, ply: i64, mut alpha: i64, beta: i64) -> i64 {
+        let moves = self.candidates();
+        if moves.is_empty() {
+            return 0;
+        }
+
+        if depth == 0 {
+            return self.evaluate(to_move);
+        }
+
+        let mut best = -WIN_SCORE - 1;
+        for index in moves {
+            self.board[index] = to_move;
+            // prefer quick wins and slow losses
+            let score = if self.wins_through(index) {
+                WIN_SCORE - ply
+            } else {
+                -self.negamax(opponent(to_move), depth - 1, ply + 1, -beta, -alpha)
+            };
+            self.board[index] = ' ';
+
+            best = cmp::max(best, score);
+            alpha = cmp::max(alpha, score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best
+    }
+
+    /// Empty cells worth considering, ordered from the center outwards.
+    /// On bigger boards only cells next to an existing mark are considered,
+    /// and an empty one only gets the center.
+    fn candidates(&self) -> Vec<usize> {
+        let size = self.size as isize;
+        let near_only = self.board.len() > 9;
+        if near_only && self.board.iter().all(|c| *c == ' ') {
+            return vec![(self.size / 2) * self.size + self.size / 2];
+        }
+
+        let mut moves: Vec<usize> = (0..self.board.len())
+            .filter(|&index| self.board[index] == ' ')
+            .filter(|&index| {
+                if !near_only {
+                    return true;
+                }
+
+                let (row, col) = ((index / self.size) as isize, (index % self.size) as isize);
+                (-1..=1).any(|d_row| {
+                    (-1..=1).any(|d_col| {
+                        let (r, c) = (row + d_row, col + d_col);
+                        (0..size).contains(&r)
+                            && (0..size).contains(&c)
+                            && self.board[(r * size + c) as usize] != ' '
+                    })
+                })
+            })
+            .collect();
+
+        let center = (size - 1) as f32 / 2.0;
+        let distance = |index: &usize| {
+            let row = (index / self.size) as f32;
+            let col = (index % self.size) as f32;
+            ((row - center).abs() + (col - center).abs()) as u32
+        };
+        moves.sort_by_key(distance);
+        moves
+    }
+
+    /// Does the mark at `index` complete a line of `win_length`
+    fn wins_through(&self, index: usize) -> bool {
+        let symbol = self.board[index];
+        let size = self.size as isize;
+        let (row, col) = ((index / self.size) as isize, (index % self.size) as isize);
+
+        let run = |d_row: isize, d_col: isize| {
+            let mut count = 0;
+            let (mut r, mut c) = (row + d_row, col + d_col);
+            while (0..size).contains(&r)
+                && (0..size).contains(&c)
+                && self.board[(r * size + c) as usize] == symbol
+            {
+                count += 1;
+                r += d_row;
+                c += d_col;
+            }
+            count
+        };
+
+        [(0, 1), (1, 0), (1, 1), (1, -1)]
+            .iter()
+            .any(|&(d_row, d_col)| 1 + run(d_row, d_col) + run(-d_row, -d_col) >= self.win_length)
+    }
+
+    /// Heuristic score for `to_move` when the search stops before the game ends.
+    /// Every window of `win_length` cells that only one player has marks in
+    /// counts for that player, weighted by how many marks are already there.
+    fn evaluate(&self, to_move: char) -> i64 {
+        let size = self.size as isize;
+        let win_length = self.win_length as isize;
+        let mut score = 0;
+
+        for (d_row, d_col) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+            for row in 0..size {
+                for col in 0..size {
+                    let end_row = row + d_row * (win_length - 1);
+                    let end_col = col + d_col * (win_length - 1);
+                    if !(0..size).contains(&end_row) || !(0..size).contains(&end_col) {
+                        continue;
+                    }
+
+                    let (mut mine, mut theirs) = (0, 0);
+                    for step in 0..win_length {
+                        match self.board
+                            [((row + d_row * step) * size + col + d_col * step) as usize]
+                        {
+                            ' ' => (),
+                            c if c == to_move => mine += 1,
+                            _ => theirs += 1,
+                        }
+                    }
+
+                    match (mine, theirs) {
+                        (0, 0) => (),
+                        (n, 0) => score += 1 << (2 * n),
+                        (0, n) => score -= 1 << (2 * n),
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perfect(player: Player) -> Computer {
+        Computer::new(player, Difficulty::Perfect, 0)
+    }
+
+    fn state_from(board: &str, current_player: Player) -> State {
+        let size = (board.len() as f64).sqrt() as u8;
+        let mut state = State::new(Rules::new(size, size.min(5)).unwrap());
+        state.board = board
+            .chars()
+            .map(|c| if c == '.' { ' ' } else { c })
+            .collect();
+        state.round = state.board.iter().filter(|c| **c != ' ').count() as u16;
+        state.current_player = current_player;
+        state
+    }
+
+    #[test]
+    fn test_takes_winning_move() {
+        let state = state_from("XX.OO....", Player::O);
+        assert_eq!(perfect(Player::O).choose_move(&state), Some(5));
+    }
+
+    #[test]
+    fn test_blocks_opponent() {
+        let state = state_from("OO..X....", Player::X);
+        assert_eq!(perfect(Player::X).choose_move(&state), Some(2));
+    }
+
+    #[test]
+    fn test_no_move_when_game_is_over() {
+        let mut state = state_from("XXXOO....", Player::O);
+        state.check_status();
+        assert_eq!(perfect(Player::O).choose_move(&state), None);
+    }
+
+    // plays every possible sequence of moves for O against the computer as X
+    fn never_loses(state: &mut State, computer: &mut Computer) {
+        state.check_status();
+        if !state.active {
+            assert_ne!(state.winner, Some(Player::O), "lost on {:?}", state.board);
+            return;
+        }
+
+        if state.current_player == computer.player {
+            let index = computer.choose_move(state).unwrap();
+            state.board[index] = 'X';
+            state.current_player = Player::O;
+            never_loses(state, computer);
+            state.board[index] = ' ';
+            state.current_player = Player::X;
+            state.active = true;
+            state.winner = None;
+            return;
+        }
+
+        for index in 0..9 {
+            if state.board[index] == ' ' {
+                state.board[index] = 'O';
+                state.current_player = Player::X;
+                never_loses(state, computer);
+                state.board[index] = ' ';
+                state.current_player = Player::O;
+                state.active = true;
+                state.winner = None;
+            }
+        }
+    }
+
+    #[test]
+    fn test_perfect_play_never_loses() {
+        let mut state = State::new(Rules::default());
+        never_loses(&mut state, &mut perfect(Player::X));
+    }
+
+    #[test]
+    fn test_blocks_on_large_board() {
+        // O has four in a row on a 15x15 board with one end already blocked, X must block the other
+        let mut state = State::new(Rules::new(15, 5).unwrap());
+        for col in 5..9 {
+            state.board[7 * 15 + col] = 'O';
+        }
+        state.board[7 * 15 + 4] = 'X';
+        state.board[6 * 15 + 5] = 'X';
+        state.board[8 * 15 + 5] = 'X';
+        state.current_player = Player::X;
+
+        assert_eq!(perfect(Player::X).choose_move(&state), Some(7 * 15 + 9));
+    }
+
+    #[test]
+    fn test_opens_in_the_center_of_large_boards() {
+        let state = State::new(Rules::new(15, 5).unwrap());
+        assert_eq!(Search::new(&state).candidates(), vec![7 * 15 + 7]);
+        let state = State::new(Rules::new(4, 4).unwrap());
+        assert_eq!(Search::new(&state).candidates(), vec![2 * 4 + 2]);
+
+        // after that only cells around the marks
+        let mut state = State::new(Rules::new(15, 5).unwrap());
+        state.board[0] = 'X';
+        let mut candidates = Search::new(&state).candidates();
+        candidates.sort();
+        assert_eq!(candidates, vec![1, 15, 16]);
+    }
+
+    #[test]
+    fn test_difficulty_from_str() {
+        assert_eq!("easy".parse::<Difficulty>().unwrap(), Difficulty::Easy);
+        assert_eq!("medium".parse::<Difficulty>().unwrap(), Difficulty::Medium);
+        assert_eq!("hard".parse::<Difficulty>().unwrap(), Difficulty::Hard);
+        assert_eq!(
+            "perfect".parse::<Difficulty>().unwrap(),
+            Difficulty::Perfect
+        );
+        assert!("impossible".parse::<Difficulty>().is_err());
+    }
+
+    #[test]
+    fn test_same_seed_same_moves() {
+        let play = |seed| {
+            let mut computer = Computer::new(Player::X, Difficulty::Easy, seed);
+            let mut state = State::new(Rules::new(5, 4).unwrap());
+            state.current_player = Player::X;
+            (0..10)
+                .map(|_| {
+                    let index = computer.choose_move(&state).unwrap();
+                    state.board[index] = 'X';
+                    index
+                })
+                .collect::<Vec<usize>>()
+        };
+
+        assert_eq!(play(42), play(42));
+        assert_ne!(play(42), play(43));
+    }
+
+    #[test]
+    fn test_easy_makes_mistakes() {
+        // easy should sometimes miss an immediate win, perfect never does
+        let state = state_from("XX.OO....", Player::O);
+        let mut easy = Computer::new(Player::O, Difficulty::Easy, 7);
+        let missed = (0..200)
+            .filter(|_| easy.choose_move(&state) != Some(5))
+            .count();
+        assert!(missed > 0);
+
+        let mut perfect = perfect(Player::O);
+        assert!((0..20).all(|_| perfect.choose_move(&state) == Some(5)));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{input::Key, network::MAX_CHAT_LEN, terminal};
+
+/// Chat lines kept around, older ones scroll away for good
+const MAX_HISTORY: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatLine {
+    pub from: String,
+    pub text: String,
+}
+
+/// Chat between the two players of a network game
+#[derive(Debug, Default)]
+pub struct Chat {
+    pub lines: Vec<ChatLine>,
+    /// Bytes typed so far, None unless the chat input is open.
+    /// Kept as bytes because multi-byte characters arrive one byte at a time.
+    pub input: Option<Vec<u8>>,
+    /// Line the player finished typing, the main loop sends it
+    pub outgoing: Option<String>,
+}
+
+impl Chat {
+    pub fn is_typing(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn open(&mut self) {
+        self.input.get_or_insert_with(Vec::new);
+    }
+
+    pub fn cancel(&mut self) {
+        self.input = None;
+    }
+
+    /// Adds a typed byte, anything past `MAX_CHAT_LEN` is dropped
+    pub fn type_byte(&mut self, byte: u8) {
+        if let Some(input) = &mut self.input {
+            if input.len() < MAX_CHAT_LEN {
+                input.push(byte);
+            }
+        }
+    }
+
+    /// Removes the last character, which may be several bytes
+    pub fn backspace(&mut self) {
+        if let Some(input) = &mut self.input {
+            // continuation bytes all look like 0b10xxxxxx
+            while let Some(byte) = input.pop() {
+                if byte & 0b1100_0000 != 0b1000_0000 {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Closes the input and queues what was typed for sending
+    pub fn submit(&mut self, name: &str) {
+        let Some(input) = self.input.take() else {
+            return;
+        };
+
+        let mut text = clean(&String::from_utf8_lossy(&input));
+        // a character cut off at the limit turns into a longer replacement character
+        while text.len() > MAX_CHAT_LEN {
+            text.pop();
+        }
+        if text.is_empty() {
+            return;
+        }
+
+        self.push(name, &text);
+        self.outgoing = Some(text);
+    }
+
+    /// Adds a line the other player sent
+    pub fn receive(&mut self, from: &str, text: &str) {
+        let text = clean(text);
+        if !text.is_empty() {
+            self.push(from, &text);
+        }
+    }
+
+    fn push(&mut self, from: &str, text: &str) {
+        self.lines.push(ChatLine {
+            from: from.to_string(),
+            text: text.to_string(),
+        });
+        if self.lines.len() > MAX_HISTORY {
+            self.lines.remove(0);
+        }
+    }
+
+    /// Most recent `height` rows of chat wrapped to `width` columns
+    fn visible_rows(&self, width: usize, height: usize) -> Vec<String> {
+        let mut rows = Vec::new();
+        for line in &self.lines {
+            let chars: Vec<char> = format!("{}: {}", line.from, line.text).chars().collect();
+            rows.extend(chars.chunks(width).map(|chunk| chunk.iter().collect()));
+        }
+
+        rows.split_off(rows.len().saturating_sub(height))
+    }
+
+    /// Draws the pane with its top left corner at `(x, y)`, the last row is the input.
+    /// `talk` is the key that starts typing, if any. Returns where the terminal cursor
+    /// goes while typing.
+    pub fn draw(
+        &self,
+        (x, y): (u16, u16),
+        width: u16,
+        height: u16,
+        talk: Option<Key>,
+    ) -> Option<(u16, u16)> {
+        if width < 10 || height < 3 {
+            return None;
+        }
+
+        let header = match (self.is_typing(), talk) {
+            (true, _) => String::from("Chat (enter to send, esc to cancel)"),
+            (false, Some(key)) => format!("Chat ({} to talk)", key),
+            (false, None) => String::from("Chat"),
+        };
+        let header: String = header.chars().take(width as usize).collect();
+        print!("{}{}", terminal::Ansi::MoveCursor(x, y), header);
+
+        let rows = self.visible_rows(width as usize, height as usize - 2);
+        for (i, row) in rows.iter().enumerate() {
+            print!("{}{}", terminal::Ansi::MoveCursor(x, y + 1 + i as u16), row);
+        }
+
+        let input = self.input.as_ref()?;
+        let input = String::from_utf8_lossy(input);
+        // only the end of a long line fits
+        let shown: Vec<char> = input.chars().collect();
+        let shown: String = shown[shown.len().saturating_sub(width as usize - 3)..]
+            .iter()
+            .collect();
+        let input_y = y + height - 1;
+        print!("{}> {}", terminal::Ansi::MoveCursor(x, input_y), shown);
+
+        Some((x + 2 + shown.chars().count() as u16, input_y))
+    }
+}
+
+// keep escape sequences and the like out of the terminal
+fn clean(text: &str) -> String {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    text.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_text(chat: &mut Chat, text: &str) {
+        for byte in text.bytes() {
+            chat.type_byte(byte);
+        }
+    }
+
+    #[test]
+    fn test_typing_and_sending() {
+        let mut chat = Chat::default();
+
+        // nothing is typed while the input is closed
+        chat.type_byte(b'a');
+        assert!(!chat.is_typing());
+
+        chat.open();
+        type_text(&mut chat, "good luck!");
+        chat.submit("alice");
+        assert!(!chat.is_typing());
+        assert_eq!(chat.outgoing.as_deref(), Some("good luck!"));
+        assert_eq!(
+            chat.lines,
+            vec![ChatLine {
+                from: String::from("alice"),
+                text: String::from("good luck!")
+            }]
+        );
+
+        // empty lines are not sent
+        chat.outgoing = None;
+        chat.open();
+        type_text(&mut chat, "   ");
+        chat.submit("alice");
+        assert_eq!(chat.outgoing, None);
+        assert_eq!(chat.lines.len(), 1);
+
+        chat.open();
+        type_text(&mut chat, "never mind");
+        chat.cancel();
+        assert!(!chat.is_typing());
+        assert_eq!(chat.lines.len(), 1);
+    }
+
+    #[test]
+    fn test_backspace_removes_whole_characters() {
+        let mut chat = Chat::default();
+        chat.open();
+        type_text(&mut chat, "hå");
+        chat.backspace();
+        assert_eq!(chat.input.as_deref(), Some(b"h".as_slice()));
+        chat.backspace();
+        chat.backspace();
+        assert_eq!(chat.input.as_deref(), Some(b"".as_slice()));
+    }
+
+    #[test]
+    fn test_input_length_is_limited() {
+        let mut chat = Chat::default();
+        chat.open();
+        type_text(&mut chat, &"a".repeat(MAX_CHAT_LEN + 10));
+        chat.submit("alice");
+        assert_eq!(chat.outgoing.unwrap().len(), MAX_CHAT_LEN);
+    }
+
+    #[test]
+    fn test_received_lines_are_cleaned() {
+        let mut chat = Chat::default();
+        chat.receive("bob", "\x1B[2Jhi\x07");
+        chat.receive("bob", "\n");
+        assert_eq!(chat.lines.len(), 1);
+        assert_eq!(chat.lines[0].text, "[2Jhi");
+    }
+
+    #[test]
+    fn test_only_the_latest_rows_are_visible() {
+        let mut chat = Chat::default();
+        for i in 0..MAX_HISTORY + 5 {
+            chat.receive("bob", &i.to_string());
+        }
+        assert_eq!(chat.lines.len(), MAX_HISTORY);
+
+        chat.receive("bob", "a long line that wraps");
+        let rows = chat.visible_rows(12, 3);
+        assert_eq!(rows, vec!["bob: a long ", "line that wr", "aps"]);
+
+        let rows = chat.visible_rows(12, 4);
+        assert_eq!(rows[0], "bob: 104");
+    }
+}
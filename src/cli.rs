@@ -0,0 +1,818 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    num::IntErrorKind,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    ai::Difficulty,
+    clock::TimeControl,
+    game::{Mode, Rules, Variant},
+    lobby,
+    network::Transport,
+    record::Record,
+    replay,
+    theme::Theme,
+};
+
+/// A subcommand as `--help` lists it
+struct Command {
+    name: &'static str,
+    /// Placeholder of the positional argument, None if the command takes none
+    argument: Option<&'static str>,
+    /// Whether the argument can be left out, network games behind a server don't need an address
+    optional: bool,
+    about: &'static str,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "local",
+        argument: None,
+        optional: false,
+        about: "Two players at the same keyboard, the default",
+    },
+    Command {
+        name: "computer",
+        argument: None,
+        optional: false,
+        about: "Play against the computer",
+    },
+    Command {
+        name: "host",
+        argument: Some("ADDRESS"),
+        optional: true,
+        about: "Host a network game on ADDRESS, like 127.0.0.1:1337",
+    },
+    Command {
+        name: "join",
+        argument: Some("ADDRESS"),
+        optional: true,
+        about: "Join the network game hosted on ADDRESS",
+    },
+    Command {
+        name: "spectate",
+        argument: Some("ADDRESS"),
+        optional: false,
+        about: "Watch the network game hosted on ADDRESS",
+    },
+    Command {
+        name: "replay",
+        argument: Some("FILE"),
+        optional: false,
+        about: "Step through a game saved with --save",
+    },
+    Command {
+        name: "stats",
+        argument: None,
+        optional: false,
+        about: "Print your results and rating",
+    },
+    Command {
+        name: "games",
+        argument: Some("SERVER"),
+        optional: false,
+        about: "List the open games on a lobby server",
+    },
+    Command {
+        name: "server",
+        argument: Some("ADDRESS"),
+        optional: false,
+        about: "Run a lobby server on ADDRESS",
+    },
+    Command {
+        name: "rendezvous",
+        argument: Some("ADDRESS"),
+        optional: false,
+        about: "Run a rendezvous server on ADDRESS",
+    },
+    Command {
+        name: "help",
+        argument: Some("COMMAND"),
+        optional: true,
+        about: "Print help, for a single command if one is given",
+    },
+];
+
+/// An option as `--help` lists it, along with the commands it works with
+struct Flag {
+    name: &'static str,
+    /// Placeholder of the value, None for switches
+    value: Option<&'static str>,
+    about: &'static str,
+    commands: &'static [&'static str],
+}
+
+const PLAYING: &[&str] = &["local", "computer", "host", "join"];
+const NETWORK: &[&str] = &["host", "join"];
+const CONNECTING: &[&str] = &["host", "join", "spectate"];
+
+const FLAGS: &[Flag] = &[
+    Flag {
+        name: "--size",
+        value: Some("N"),
+        about: "Play on an NxN board, 3 to 19",
+        commands: PLAYING,
+    },
+    Flag {
+        name: "--win",
+        value: Some("K"),
+        about: "Marks in a row needed to win",
+        commands: PLAYING,
+    },
+    Flag {
+        name: "--ultimate",
+        value: None,
+        about: "Play ultimate tic-tac-toe",
+        commands: &["local", "host", "join"],
+    },
+    Flag {
+        name: "--difficulty",
+        value: Some("LEVEL"),
+        about: "easy, medium, hard or perfect (default)",
+        commands: &["computer"],
+    },
+    Flag {
+        name: "--seed",
+        value: Some("N"),
+        about: "Seed of the computer's mistakes, to play the same game again",
+        commands: &["computer"],
+    },
+    Flag {
+        name: "--name",
+        value: Some("NAME"),
+        about: "Your name, defaults to your user name",
+        commands: &["local", "computer", "host", "join", "spectate"],
+    },
+    Flag {
+        name: "--transport",
+        value: Some("tcp|udp"),
+        about: "How to reach the other player, tcp by default",
+        commands: CONNECTING,
+    },
+    Flag {
+        name: "--simulate-loss",
+        value: Some("SHARE"),
+        about: "Drop this share of the udp packets sent, from 0 up to 1",
+        commands: CONNECTING,
+    },
+    Flag {
+        name: "--secret",
+        value: Some("PASSPHRASE"),
+        about: "Encrypt the game, everyone needs the same passphrase",
+        commands: CONNECTING,
+    },
+    Flag {
+        name: "--rendezvous",
+        value: Some("SERVER"),
+        about: "Meet the other player through a rendezvous server",
+        commands: NETWORK,
+    },
+    Flag {
+        name: "--code",
+        value: Some("CODE"),
+        about: "Game code both players give the rendezvous server",
+        commands: NETWORK,
+    },
+    Flag {
+        name: "--server",
+        value: Some("SERVER"),
+        about: "Play on a lobby server",
+        commands: NETWORK,
+    },
+    Flag {
+        name: "--game",
+        value: Some("NAME"),
+        about: "Name of the game on the lobby server",
+        commands: NETWORK,
+    },
+    Flag {
+        name: "--best-of",
+        value: Some("N"),
+        about: "Play a match of N games",
+        commands: &["host"],
+    },
+    Flag {
+        name: "--clock",
+        value: Some("TIME"),
+        about: "Chess clock like 5m or 5m+2s",
+        commands: &["local", "computer", "host"],
+    },
+    Flag {
+        name: "--save",
+        value: Some("FILE"),
+        about: "Write the game to FILE whenever it changes",
+        commands: PLAYING,
+    },
+    Flag {
+        name: "--load",
+        value: Some("FILE"),
+        about: "Continue a game saved with --save",
+        commands: &["local", "computer", "host"],
+    },
+    Flag {
+        name: "--theme",
+        value: Some("NAME"),
+        about: "classic (default), high-contrast or monochrome",
+        commands: &["local", "computer", "host", "join", "spectate", "replay"],
+    },
+    Flag {
+        name: "--delay",
+        value: Some("SECONDS"),
+        about: "Time between moves when the replay plays on its own",
+        commands: &["replay"],
+    },
+];
+
+/// What the command line asks for
+pub enum Action {
+    Run(Box<Args>),
+    /// Print this and exit
+    Help(String),
+    Version,
+}
+
+pub struct Args {
+    pub mode: Mode,
+    pub addr: String,
+    pub is_host: bool,
+    pub rules: Rules,
+    /// Rules were picked on the command line rather than left at the defaults
+    pub rules_given: bool,
+    pub difficulty: Difficulty,
+    pub seed: u64,
+    pub name: String,
+    pub transport: Transport,
+    /// Share of udp packets to drop on purpose
+    pub loss: f64,
+    /// Rendezvous server and game code to find the other player through
+    pub rendezvous: Option<(String, String)>,
+    /// Run a rendezvous server on `addr` instead of playing
+    pub serve_rendezvous: bool,
+    /// Passphrase both players need to know, all messages are sealed with it
+    pub secret: Option<String>,
+    /// Games in the host's match, None plays on without a limit
+    pub best_of: Option<u8>,
+    /// Time each player gets, None plays without a clock
+    pub clock: Option<TimeControl>,
+    /// File the game record is written to whenever it changes
+    pub save: Option<String>,
+    /// Saved game to pick up where it was left, or to step through in a replay
+    pub load: Option<Record>,
+    /// Time between two moves while a replay plays on its own
+    pub delay: Duration,
+    /// Colours picked on the command line, they win over the config and `NO_COLOR`
+    pub theme: Option<Theme>,
+    /// Lobby server and name of the game to create or join there
+    pub lobby: Option<(String, String)>,
+    /// Run a lobby server on `addr` instead of playing
+    pub serve_lobby: bool,
+    /// Print the open games of the lobby server at `addr`
+    pub list_games: bool,
+    /// Print the results kept across sessions
+    pub show_stats: bool,
+}
+
+/// Parses the arguments after the program name. Flags may come before or after the
+/// command, every flag is checked against the command it is used with.
+pub fn parse(args: Vec<String>) -> anyhow::Result<Action> {
+    let mut positional = Vec::new();
+    // in the order given, so the first problem is the one reported
+    let mut given: Vec<&'static Flag> = Vec::new();
+    let mut values: HashMap<&'static str, String> = HashMap::new();
+    let mut help = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => help = true,
+            "-V" | "--version" => return Ok(Action::Version),
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                // `--size=4` works as well as `--size 4`
+                let (name, inline) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (arg.as_str(), None),
+                };
+                let flag = FLAGS
+                    .iter()
+                    .find(|flag| flag.name == name)
+                    .ok_or_else(|| anyhow!("Unknown option {}", name))?;
+
+                let value = match (flag.value, inline) {
+                    (Some(_), Some(value)) => value,
+                    (Some(placeholder), None) => args
+                        .next()
+                        .ok_or_else(|| anyhow!("{} needs a value, {}", flag.name, placeholder))?,
+                    (None, Some(_)) => return Err(anyhow!("{} doesn't take a value", flag.name)),
+                    (None, None) => String::new(),
+                };
+                if values.insert(flag.name, value).is_some() {
+                    return Err(anyhow!("{} is given twice", flag.name));
+                }
+                given.push(flag);
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let name = positional.first().map_or("local", String::as_str);
+    let command = find_command(name)?;
+    let argument = positional.get(1).cloned();
+    if let Some(extra) = positional.get(2 - command.argument.is_none() as usize) {
+        return Err(anyhow!("Unexpected argument '{}'", extra));
+    }
+
+    if command.name == "help" {
+        return match argument {
+            Some(name) => Ok(Action::Help(command_help(find_command(&name)?))),
+            None => Ok(Action::Help(general_help())),
+        };
+    }
+    if help {
+        return Ok(Action::Help(match positional.is_empty() {
+            true => general_help(),
+            false => command_help(command),
+        }));
+    }
+
+    if let Some(flag) = given
+        .iter()
+        .find(|flag| !flag.commands.contains(&command.name))
+    {
+        return Err(anyhow!("{} can't be used with {}", flag.name, command.name));
+    }
+    if argument.is_none() && command.argument.is_some() && !command.optional {
+        return Err(anyhow!(
+            "{} needs {}",
+            command.name,
+            command.argument.unwrap_or_default()
+        ));
+    }
+
+    Ok(Action::Run(Box::new(args_for(
+        command.name,
+        argument,
+        values,
+    )?)))
+}
+
+fn find_command(name: &str) -> anyhow::Result<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name == name)
+        .ok_or_else(|| anyhow!("Unknown command '{}'", name))
+}
+
+// numbers too big for a byte are out of range like any other, `Rules::new` says what fits
+fn board_number(flag: &str, value: &str) -> anyhow::Result<u8> {
+    match value.parse::<u8>() {
+        Ok(number) => Ok(number),
+        Err(reason) if *reason.kind() == IntErrorKind::PosOverflow => Ok(u8::MAX),
+        Err(_) => Err(anyhow!("{} must be a number", flag)),
+    }
+}
+
+// turns the flags into typed values, checking the ones that only work together
+fn args_for(
+    command: &str,
+    argument: Option<String>,
+    mut values: HashMap<&'static str, String>,
+) -> anyhow::Result<Args> {
+    let size_flag = values.remove("--size");
+    let win_flag = values.remove("--win");
+    let ultimate = values.remove("--ultimate").is_some();
+    let rules_given = size_flag.is_some() || win_flag.is_some() || ultimate;
+
+    let size = size_flag
+        .map(|s| board_number("--size", &s))
+        .transpose()?
+        .unwrap_or(3);
+    // default to the whole row on small boards and five in a row (gomoku) on big ones
+    let win_length = win_flag
+        .map(|s| board_number("--win", &s))
+        .transpose()?
+        .unwrap_or(size.min(5));
+    let mut rules = Rules::new(size, win_length)?;
+    if ultimate {
+        if size != 3 || win_length != 3 {
+            return Err(anyhow!("--ultimate is always played on 3x3 boards"));
+        }
+        rules = Rules::ultimate();
+    }
+
+    let save = values.remove("--save");
+    let load = values
+        .remove("--load")
+        .map(|path| read_record(&path))
+        .transpose()?;
+    if let Some(record) = &load {
+        if rules_given {
+            return Err(anyhow!("A loaded game keeps the rules it was played with"));
+        }
+        rules = record.rules;
+    }
+
+    let difficulty = values
+        .remove("--difficulty")
+        .map(|s| s.parse::<Difficulty>())
+        .transpose()?
+        .unwrap_or(Difficulty::Perfect);
+    // pass the same seed again to replay a game against the computer
+    let seed = match values.remove("--seed") {
+        Some(s) => s
+            .parse::<u64>()
+            .map_err(|_| anyhow!("--seed must be a number"))?,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+    };
+
+    let name = values
+        .remove("--name")
+        .or_else(|| env::var("USER").ok())
+        .unwrap_or_else(|| String::from("player"));
+
+    // both players have to pick the same transport
+    let transport = values
+        .remove("--transport")
+        .map(|s| s.parse::<Transport>())
+        .transpose()?;
+    let rendezvous = match (values.remove("--rendezvous"), values.remove("--code")) {
+        (Some(server), Some(code)) => Some((server, code)),
+        (Some(_), None) => return Err(anyhow!("--rendezvous needs a game --code")),
+        (None, Some(_)) => return Err(anyhow!("--code needs a --rendezvous server")),
+        (None, None) => None,
+    };
+    // meeting through a rendezvous server is always over udp
+    let transport = match (transport, &rendezvous) {
+        (Some(Transport::Tcp), Some(_)) => {
+            return Err(anyhow!("--rendezvous only works with --transport udp"))
+        }
+        (_, Some(_)) => Transport::Udp,
+        (transport, None) => transport.unwrap_or(Transport::Tcp),
+    };
+
+    let loss = match values.remove("--simulate-loss") {
+        Some(_) if transport != Transport::Udp => {
+            return Err(anyhow!("--simulate-loss only works with --transport udp"))
+        }
+        Some(s) => s
+            .parse::<f64>()
+            .ok()
+            .filter(|loss| (0.0..1.0).contains(loss))
+            .ok_or_else(|| anyhow!("--simulate-loss must be a number from 0 up to 1"))?,
+        None => 0.0,
+    };
+
+    let secret = values.remove("--secret");
+    if secret.as_deref() == Some("") {
+        return Err(anyhow!("--secret can't be empty"));
+    }
+
+    let lobby = match (values.remove("--server"), values.remove("--game")) {
+        (Some(server), Some(game)) => Some((server, game)),
+        (Some(_), None) => return Err(anyhow!("--server needs the name of a --game")),
+        (None, Some(_)) => return Err(anyhow!("--game needs a lobby --server")),
+        (None, None) => None,
+    };
+    if let Some((_, game)) = &lobby {
+        if game.is_empty() || game.len() > lobby::MAX_GAME_NAME_LEN {
+            return Err(anyhow!(
+                "--game must be between 1 and {} bytes",
+                lobby::MAX_GAME_NAME_LEN
+            ));
+        }
+        if rendezvous.is_some() {
+            return Err(anyhow!("Pick either a --rendezvous or a lobby --server"));
+        }
+        if transport != Transport::Tcp {
+            return Err(anyhow!("Lobby servers are only reached over tcp"));
+        }
+        // the server has to read the moves to check them
+        if secret.is_some() {
+            return Err(anyhow!("--secret can't be used with a lobby --server"));
+        }
+    }
+
+    let best_of = values
+        .remove("--best-of")
+        .map(|s| {
+            s.parse::<u8>()
+                .ok()
+                .filter(|best_of| (1..=99).contains(best_of))
+                .ok_or_else(|| anyhow!("--best-of must be a number from 1 to 99"))
+        })
+        .transpose()?;
+    let clock = values
+        .remove("--clock")
+        .map(|s| s.parse::<TimeControl>())
+        .transpose()?;
+    let theme = values
+        .remove("--theme")
+        .map(|s| s.parse::<Theme>())
+        .transpose()?;
+    let delay = values
+        .remove("--delay")
+        .map(|s| {
+            s.parse::<f64>()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .filter(|delay| (replay::MIN_DELAY..=replay::MAX_DELAY).contains(delay))
+                .ok_or_else(|| anyhow!("--delay must be a number of seconds from 0.1 to 10"))
+        })
+        .transpose()?;
+
+    // nobody would be there to run the clock, and the server starts every game from an empty board
+    if lobby.is_some() && clock.is_some() {
+        return Err(anyhow!("--clock can't be used with a lobby --server"));
+    }
+    if lobby.is_some() && load.is_some() {
+        return Err(anyhow!("--load can't be used with a lobby --server"));
+    }
+
+    let mut parsed = Args {
+        mode: Mode::Local,
+        addr: String::default(),
+        is_host: false,
+        rules,
+        rules_given,
+        difficulty,
+        seed,
+        name,
+        transport,
+        loss,
+        rendezvous,
+        serve_rendezvous: false,
+        secret,
+        best_of,
+        clock,
+        save,
+        load,
+        delay: delay.unwrap_or(Duration::from_secs(1)),
+        theme,
+        lobby,
+        serve_lobby: false,
+        list_games: false,
+        show_stats: false,
+    };
+
+    match command {
+        "local" => (),
+        "computer" => {
+            // a loaded game can still be an ultimate one
+            if parsed.rules.variant == Variant::Ultimate {
+                return Err(anyhow!("The computer opponent only plays classic games"));
+            }
+            parsed.mode = Mode::VsComputer;
+        }
+        "stats" => parsed.show_stats = true,
+        "replay" => {
+            let record = read_record(&argument.unwrap_or_default())?;
+            parsed.rules = record.rules;
+            parsed.load = Some(record);
+            parsed.mode = Mode::Replay;
+        }
+        "spectate" => {
+            parsed.mode = Mode::Spectator;
+            parsed.addr = argument.unwrap_or_default();
+        }
+        "rendezvous" => {
+            parsed.serve_rendezvous = true;
+            parsed.addr = argument.unwrap_or_default();
+        }
+        "server" => {
+            parsed.serve_lobby = true;
+            parsed.addr = argument.unwrap_or_default();
+        }
+        "games" => {
+            parsed.list_games = true;
+            parsed.addr = argument.unwrap_or_default();
+        }
+        _ => {
+            parsed.mode = Mode::Network;
+            parsed.is_host = command == "host";
+            parsed.addr = match (&parsed.rendezvous, argument) {
+                (_, Some(_)) if parsed.lobby.is_some() => {
+                    return Err(anyhow!("Lobby games only need the --server address"))
+                }
+                (_, Some(addr)) => addr,
+                (None, None) if parsed.lobby.is_some() => String::new(),
+                // behind a rendezvous the address is only where to send from
+                (Some(_), None) => String::from("0.0.0.0:0"),
+                (None, None) => return Err(anyhow!("{} needs an ADDRESS", command)),
+            };
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Reads a game saved with `--save`
+fn read_record(path: &str) -> anyhow::Result<Record> {
+    fs::read_to_string(path)
+        .map_err(|reason| anyhow!("Can't read {}: {}", path, reason))?
+        .parse::<Record>()
+        .map_err(|reason| anyhow!("Can't load {}: {}", path, reason))
+}
+
+// "host [ADDRESS]" or "spectate ADDRESS"
+fn usage(command: &Command) -> String {
+    match (command.argument, command.optional) {
+        (Some(argument), true) => format!("{} [{}]", command.name, argument),
+        (Some(argument), false) => format!("{} {}", command.name, argument),
+        (None, _) => command.name.to_string(),
+    }
+}
+
+// "--size N"
+fn flag_usage(flag: &Flag) -> String {
+    match flag.value {
+        Some(value) => format!("{} {}", flag.name, value),
+        None => flag.name.to_string(),
+    }
+}
+
+// two columns, the left one as wide as its longest entry
+fn columns(rows: &[(String, &str)]) -> String {
+    let width = rows.iter().map(|(left, _)| left.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(left, right)| format!("  {:<width$}  {}\n", left, right, width = width))
+        .collect()
+}
+
+fn general_help() -> String {
+    let commands: Vec<(String, &str)> = COMMANDS
+        .iter()
+        .map(|command| (usage(command), command.about))
+        .collect();
+    let mut flags: Vec<(String, &str)> = FLAGS
+        .iter()
+        .map(|flag| (flag_usage(flag), flag.about))
+        .collect();
+    flags.push((String::from("-h, --help"), "Print help"));
+    flags.push((String::from("-V, --version"), "Print the version"));
+
+    format!(
+        "Tic Tac Toe in the terminal\n\nUsage: tic-tac-term [COMMAND] [OPTIONS]\n\n\
+         Commands:\n{}\nOptions:\n{}\nRun tic-tac-term help COMMAND to see which options a command takes.\n",
+        columns(&commands),
+        columns(&flags)
+    )
+}
+
+fn command_help(command: &Command) -> String {
+    let flags: Vec<(String, &str)> = FLAGS
+        .iter()
+        .filter(|flag| flag.commands.contains(&command.name))
+        .map(|flag| (flag_usage(flag), flag.about))
+        .collect();
+
+    let mut help = format!(
+        "{}\n\nUsage: tic-tac-term {}",
+        command.about,
+        usage(command)
+    );
+    match flags.is_empty() {
+        true => help.push('\n'),
+        false => help.push_str(&format!(" [OPTIONS]\n\nOptions:\n{}", columns(&flags))),
+    }
+    help
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> anyhow::Result<Action> {
+        parse(line.split_whitespace().map(String::from).collect())
+    }
+
+    fn run(line: &str) -> Args {
+        match parse_line(line) {
+            Ok(Action::Run(args)) => *args,
+            Ok(_) => panic!("{:?} did not start anything", line),
+            Err(reason) => panic!("{:?} failed: {}", line, reason),
+        }
+    }
+
+    fn error(line: &str) -> String {
+        match parse_line(line) {
+            Err(reason) => reason.to_string(),
+            Ok(_) => panic!("{:?} should fail", line),
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let args = run("");
+        assert_eq!(args.mode, Mode::Local);
+        assert_eq!(args.rules, Rules::default());
+        assert!(!args.rules_given);
+        assert_eq!(run("local --size 4").rules, Rules::new(4, 4).unwrap());
+
+        let args = run("host 127.0.0.1:1337 --best-of 3");
+        assert_eq!(args.mode, Mode::Network);
+        assert!(args.is_host);
+        assert_eq!(args.addr, "127.0.0.1:1337");
+        assert_eq!(args.best_of, Some(3));
+
+        // flags go anywhere, with or without an equals sign
+        let args = run("--transport=udp join 127.0.0.1:1337 --name bob");
+        assert!(!args.is_host);
+        assert_eq!(args.transport, Transport::Udp);
+        assert_eq!(args.name, "bob");
+
+        let args = run("join --server example.com:1337 --game pizza");
+        assert_eq!(args.addr, "");
+        assert!(args.lobby.is_some());
+
+        let args = run("computer --difficulty easy --seed 7");
+        assert_eq!(args.mode, Mode::VsComputer);
+        assert_eq!(args.difficulty, Difficulty::Easy);
+        assert_eq!(args.seed, 7);
+
+        assert_eq!(run("--ultimate").rules.variant, Variant::Ultimate);
+        assert!(run("stats").show_stats);
+        assert!(run("games example.com:1337").list_games);
+        assert_eq!(run("spectate 127.0.0.1:1337").mode, Mode::Spectator);
+        assert_eq!(run("--theme monochrome").theme, Some(Theme::MONOCHROME));
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = [
+            ("hots 127.0.0.1:1337", "Unknown command 'hots'"),
+            ("--colour red", "Unknown option --colour"),
+            ("host", "host needs an ADDRESS"),
+            ("spectate", "spectate needs ADDRESS"),
+            ("replay", "replay needs FILE"),
+            ("host 127.0.0.1:1337 extra", "Unexpected argument 'extra'"),
+            ("stats now", "Unexpected argument 'now'"),
+            ("--size", "--size needs a value, N"),
+            ("--size 4 --size 5", "--size is given twice"),
+            ("--ultimate=yes", "--ultimate doesn't take a value"),
+            ("--size four", "--size must be a number"),
+            ("--size 300", "Board size must be between 3 and 19"),
+            (
+                "--size 5 --win 1000",
+                "Win length must be between 3 and the board size",
+            ),
+            (
+                "join 127.0.0.1:1337 --best-of 3",
+                "--best-of can't be used with join",
+            ),
+            ("--difficulty easy", "--difficulty can't be used with local"),
+            (
+                "computer --ultimate",
+                "--ultimate can't be used with computer",
+            ),
+            (
+                "replay game.txt --size 4",
+                "--size can't be used with replay",
+            ),
+            ("--delay 2", "--delay can't be used with local"),
+            ("stats --theme classic", "--theme can't be used with stats"),
+            (
+                "host --server example.com:1337 --game pizza --clock 5m",
+                "--clock can't be used with a lobby --server",
+            ),
+            ("help hots", "Unknown command 'hots'"),
+        ];
+        for (line, expected) in errors {
+            assert_eq!(error(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_help_and_version() {
+        assert!(matches!(parse_line("--version"), Ok(Action::Version)));
+        assert!(matches!(parse_line("host -V"), Ok(Action::Version)));
+
+        let Ok(Action::Help(general)) = parse_line("--help") else {
+            panic!("no help");
+        };
+        assert!(general.starts_with("Tic Tac Toe in the terminal\n\nUsage: tic-tac-term [COMMAND]"));
+        for command in COMMANDS {
+            assert!(general.contains(&usage(command)), "{}", command.name);
+        }
+        assert!(general.contains("  --best-of N "));
+
+        // help for a single command only lists what it takes
+        let Ok(Action::Help(host)) = parse_line("host --help") else {
+            panic!("no help");
+        };
+        assert!(host.contains("Usage: tic-tac-term host [ADDRESS] [OPTIONS]"));
+        assert!(host.contains("--best-of N"));
+        assert!(!host.contains("--difficulty"));
+        assert!(matches!(parse_line("help host"), Ok(Action::Help(help)) if help == host));
+
+        let Ok(Action::Help(stats)) = parse_line("help stats") else {
+            panic!("no help");
+        };
+        assert_eq!(
+            stats,
+            "Print your results and rating\n\nUsage: tic-tac-term stats\n"
+        );
+    }
+}
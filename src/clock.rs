@@ -0,0 +1,349 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+
+use crate::game::Player;
+
+/// Longest clock that fits the wire format comfortably
+const MAX_CLOCK: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time each player gets for the whole game and how much is added after every move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+/// Parses `30s`, `5m`, `1m30s` or any of those with an increment like `5m+2s`
+impl FromStr for TimeControl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "Invalid clock '{}', expected something like 30s, 5m or 5m+2s",
+                s
+            )
+        };
+
+        let (base, increment) = match s.split_once('+') {
+            Some((base, increment)) => (base, Some(increment)),
+            None => (s, None),
+        };
+        let base = parse_duration(base).ok_or_else(invalid)?;
+        let increment = match increment {
+            Some(increment) => parse_duration(increment).ok_or_else(invalid)?,
+            None => Duration::ZERO,
+        };
+
+        if base.is_zero() {
+            return Err(anyhow!("The clock needs some time on it"));
+        }
+        if base > MAX_CLOCK || increment > MAX_CLOCK {
+            return Err(anyhow!("Clocks can be at most 24h"));
+        }
+
+        Ok(TimeControl { base, increment })
+    }
+}
+
+// "1h", "5m", "1m30s", every number needs a unit
+fn parse_duration(s: &str) -> Option<Duration> {
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let value: u64 = number.parse().ok()?;
+        total = total.checked_add(value.checked_mul(unit)?)?;
+        number.clear();
+    }
+
+    if s.is_empty() || !number.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(total))
+}
+
+/// Chess style clock for both players, only the player to move has theirs running.
+/// Nobody's clock runs before the first move.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clock {
+    pub control: TimeControl,
+    o_left: Duration,
+    x_left: Duration,
+    /// Whose clock is running and since when
+    running: Option<(Player, Instant)>,
+    /// Player who ran out of time, which ends the game
+    pub flagged: Option<Player>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Clock {
+            control,
+            o_left: control.base,
+            x_left: control.base,
+            running: None,
+            flagged: None,
+        }
+    }
+
+    /// Back to the full time for the next game
+    pub fn reset(&mut self) {
+        *self = Clock::new(self.control);
+    }
+
+    fn left_mut(&mut self, player: Player) -> &mut Duration {
+        match player {
+            Player::O => &mut self.o_left,
+            Player::X => &mut self.x_left,
+        }
+    }
+
+    pub fn remaining(&self, player: Player) -> Duration {
+        let left = match player {
+            Player::O => self.o_left,
+            Player::X => self.x_left,
+        };
+        match self.running {
+            Some((running, since)) if running == player => left.saturating_sub(since.elapsed()),
+            _ => left,
+        }
+    }
+
+    /// Stops the clock of `player`, who just moved, adds the increment and starts the opponent's
+    pub fn moved(&mut self, player: Player) {
+        if self.flagged.is_some() {
+            return;
+        }
+
+        if matches!(self.running, Some((running, _)) if running == player) {
+            let left = self.remaining(player) + self.control.increment;
+            *self.left_mut(player) = left;
+        }
+        self.running = Some((player.toggle(), Instant::now()));
+    }
+
+    /// Runs the clock of `player` without touching the times, after a move was taken back
+    pub fn start(&mut self, player: Player) {
+        if self.flagged.is_none() {
+            self.stop();
+            self.running = Some((player, Instant::now()));
+        }
+    }
+
+    /// Stops both clocks, the game is over
+    pub fn stop(&mut self) {
+        if let Some((player, _)) = self.running {
+            let left = self.remaining(player);
+            *self.left_mut(player) = left;
+        }
+        self.running = None;
+    }
+
+    /// Lets `elapsed` pass on the running clock without waiting for it
+    #[cfg(test)]
+    pub fn advance(&mut self, elapsed: Duration) {
+        if let Some((_, since)) = &mut self.running {
+            *since -= elapsed;
+        }
+    }
+
+    /// Returns the player whose time just ran out, only once
+    pub fn check_flag(&mut self) -> Option<Player> {
+        let (player, _) = self.running?;
+        if !self.remaining(player).is_zero() {
+            return None;
+        }
+
+        self.stop();
+        self.flagged = Some(player);
+        Some(player)
+    }
+
+    /// Time left as `4:32`, tenths of a second are shown in the last ten seconds
+    pub fn display(&self, player: Player) -> String {
+        let left = self.remaining(player);
+        let seconds = left.as_secs();
+        match seconds < 10 {
+            true => format!("0:{:02}.{}", seconds, left.subsec_millis() / 100),
+            false => format!("{}:{:02}", seconds / 60, seconds % 60),
+        }
+    }
+}
+
+/// Binary format (18 bytes), times in milliseconds:
+/// - Bytes 0-3: Starting time (u32, big endian)
+/// - Bytes 4-7: Increment (u32, big endian)
+/// - Bytes 8-11: Time left for O (u32, big endian)
+/// - Bytes 12-15: Time left for X (u32, big endian)
+/// - Byte 16: Running clock (0 = none, 1 = O, 2 = X), it keeps running from when the message arrives
+/// - Byte 17: Player who ran out of time (0 = none, 1 = O, 2 = X)
+impl TryFrom<&[u8]> for Clock {
+    fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() != 18 {
+            return Err(anyhow!("Clock can only be deserialized from 18 bytes"));
+        }
+
+        let millis = |i: usize| {
+            let ms = u32::from_be_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+            Duration::from_millis(ms as u64)
+        };
+        let player = |byte: u8| match byte {
+            0 => Ok(None),
+            1 => Ok(Some(Player::O)),
+            2 => Ok(Some(Player::X)),
+            _ => Err(anyhow!("Invalid player in clock")),
+        };
+
+        Ok(Clock {
+            control: TimeControl {
+                base: millis(0),
+                increment: millis(4),
+            },
+            o_left: millis(8),
+            x_left: millis(12),
+            running: player(bytes[16])?.map(|player| (player, Instant::now())),
+            flagged: player(bytes[17])?,
+        })
+    }
+
+    type Error = anyhow::Error;
+}
+
+impl From<&Clock> for Vec<u8> {
+    fn from(clock: &Clock) -> Self {
+        let millis = |duration: Duration| {
+            let ms = duration.as_millis().min(u32::MAX as u128);
+            (ms as u32).to_be_bytes()
+        };
+        let player = |player: Option<Player>| match player {
+            None => 0,
+            Some(Player::O) => 1,
+            Some(Player::X) => 2,
+        };
+
+        let mut bytes = Vec::with_capacity(18);
+        bytes.extend(millis(clock.control.base));
+        bytes.extend(millis(clock.control.increment));
+        bytes.extend(millis(clock.remaining(Player::O)));
+        bytes.extend(millis(clock.remaining(Player::X)));
+        bytes.push(player(clock.running.map(|(player, _)| player)));
+        bytes.push(player(clock.flagged));
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time_control() {
+        let control: TimeControl = "30s".parse().unwrap();
+        assert_eq!(control.base, Duration::from_secs(30));
+        assert_eq!(control.increment, Duration::ZERO);
+
+        let control: TimeControl = "5m+2s".parse().unwrap();
+        assert_eq!(control.base, Duration::from_secs(300));
+        assert_eq!(control.increment, Duration::from_secs(2));
+
+        let control: TimeControl = "1m30s".parse().unwrap();
+        assert_eq!(control.base, Duration::from_secs(90));
+
+        for invalid in ["", "30", "5x", "m", "5m+", "0s", "25h", "+2s"] {
+            assert!(invalid.parse::<TimeControl>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_clock_runs_for_the_player_to_move() {
+        let control = TimeControl {
+            base: Duration::from_secs(60),
+            increment: Duration::from_secs(2),
+        };
+        let mut clock = Clock::new(control);
+
+        // nothing runs before the first move
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(clock.remaining(Player::O), control.base);
+
+        clock.moved(Player::O);
+        assert_eq!(clock.remaining(Player::O), control.base);
+        clock.advance(Duration::from_millis(20));
+        assert!(clock.remaining(Player::X) < control.base);
+
+        // the increment makes up for the time spent
+        clock.moved(Player::X);
+        assert!(clock.remaining(Player::X) > control.base);
+        assert!(clock.remaining(Player::X) < control.base + control.increment);
+
+        clock.stop();
+        let left = clock.remaining(Player::O);
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(clock.remaining(Player::O), left);
+    }
+
+    #[test]
+    fn test_flag_fall() {
+        let mut clock = Clock::new(TimeControl {
+            base: Duration::from_millis(30),
+            increment: Duration::ZERO,
+        });
+        clock.moved(Player::O);
+        assert_eq!(clock.check_flag(), None);
+
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(clock.check_flag(), Some(Player::X));
+        assert_eq!(clock.flagged, Some(Player::X));
+        assert_eq!(clock.remaining(Player::X), Duration::ZERO);
+        // reported once
+        assert_eq!(clock.check_flag(), None);
+
+        clock.reset();
+        assert_eq!(clock.flagged, None);
+        assert_eq!(clock.remaining(Player::X), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_display() {
+        let mut clock = Clock::new("5m".parse().unwrap());
+        assert_eq!(clock.display(Player::O), "5:00");
+        clock.o_left = Duration::from_millis(9_420);
+        assert_eq!(clock.display(Player::O), "0:09.4");
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut clock = Clock::new("5m+2s".parse().unwrap());
+        clock.x_left = Duration::from_millis(1234);
+        clock.flagged = Some(Player::X);
+
+        let bytes: Vec<u8> = (&clock).into();
+        assert_eq!(bytes.len(), 18);
+        assert_eq!(&bytes[12..16], &1234u32.to_be_bytes());
+        assert_eq!(Clock::try_from(bytes.as_slice()).unwrap(), clock);
+
+        // a running clock keeps running on the other side
+        clock.reset();
+        clock.moved(Player::O);
+        let bytes: Vec<u8> = (&clock).into();
+        let received = Clock::try_from(bytes.as_slice()).unwrap();
+        assert!(matches!(received.running, Some((Player::X, _))));
+
+        assert!(Clock::try_from(&bytes[..17]).is_err());
+    }
+}
@@ -0,0 +1,448 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    ffi::OsString,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+
+use crate::{input::Key, theme::Theme};
+
+/// Something a key can be bound to while playing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    RowStart,
+    RowEnd,
+    /// Places the current player's mark, in replays starts or pauses playing
+    Place,
+    PlaceX,
+    PlaceO,
+    Undo,
+    Redo,
+    Restart,
+    Quit,
+    FreeCursor,
+    Chat,
+    HideCursor,
+    ScrollUp,
+    ScrollDown,
+    /// Replays play faster or slower
+    Faster,
+    Slower,
+}
+
+// names in the config file, and the keys each action has unless the config says otherwise
+const ACTIONS: [(&str, Action, &[&str]); 20] = [
+    ("move-up", Action::MoveUp, &["up", "k", "w"]),
+    ("move-down", Action::MoveDown, &["down", "j", "s"]),
+    ("move-left", Action::MoveLeft, &["left", "h", "a"]),
+    ("move-right", Action::MoveRight, &["right", "l", "d"]),
+    ("row-start", Action::RowStart, &["home"]),
+    ("row-end", Action::RowEnd, &["end"]),
+    ("place", Action::Place, &["space", "enter"]),
+    ("place-x", Action::PlaceX, &["x"]),
+    ("place-o", Action::PlaceO, &["o"]),
+    ("undo", Action::Undo, &["u"]),
+    ("redo", Action::Redo, &["ctrl-r"]),
+    ("restart", Action::Restart, &["r"]),
+    ("quit", Action::Quit, &["q"]),
+    ("free-cursor", Action::FreeCursor, &["f"]),
+    ("chat", Action::Chat, &["c"]),
+    ("hide-cursor", Action::HideCursor, &["v"]),
+    ("scroll-up", Action::ScrollUp, &["pageup"]),
+    ("scroll-down", Action::ScrollDown, &["pagedown"]),
+    ("faster", Action::Faster, &["+"]),
+    ("slower", Action::Slower, &["-"]),
+];
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, ..) = ACTIONS
+            .iter()
+            .find(|(_, action, _)| action == self)
+            .expect("every action has a name");
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        ACTIONS
+            .iter()
+            .find(|(name, ..)| *name == s)
+            .map(|(_, action, _)| *action)
+            .ok_or_else(|| anyhow!("Unknown action {}", s))
+    }
+}
+
+/// Which action each key does
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    actions: HashMap<Key, Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap {
+            actions: HashMap::new(),
+        };
+        for (_, action, keys) in ACTIONS {
+            let keys: Vec<Key> = keys
+                .iter()
+                .map(|name| name.parse().expect("default keys have valid names"))
+                .collect();
+            keymap.bind(action, &keys);
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.actions.get(&key).copied()
+    }
+
+    /// A key that does `action`, for telling the player what to press. The same one
+    /// every time when there are several, None once the config unbound it.
+    pub fn key(&self, action: Action) -> Option<Key> {
+        self.actions
+            .iter()
+            .filter(|(_, bound)| **bound == action)
+            .map(|(key, _)| *key)
+            .min_by_key(|key| key.to_string())
+    }
+
+    /// Binds `keys` to `action` in place of the keys it had, taking them away
+    /// from whatever they did before
+    pub fn bind(&mut self, action: Action, keys: &[Key]) {
+        self.actions.retain(|_, bound| *bound != action);
+        for key in keys {
+            self.actions.insert(*key, action);
+        }
+    }
+}
+
+/// Settings read from the config file, anything it leaves out keeps its default
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub keys: Keymap,
+    /// Theme picked in the config, None leaves it to `NO_COLOR`
+    pub theme: Option<Theme>,
+}
+
+impl Config {
+    /// Reads the config at `path`, a missing file is the default config
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => text
+                .parse()
+                .map_err(|reason| anyhow!("Invalid config {}: {}", path.display(), reason)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(error) => Err(anyhow!("Can't read {}: {}", path.display(), error)),
+        }
+    }
+}
+
+/// The part of TOML needed for the settings, sections holding `name = value` lines
+/// where the value is a string or a list of strings. `#` starts a comment.
+///
+/// ```toml
+/// [keys]
+/// place = "enter"
+/// quit = ["esc", "q"]
+///
+/// [display]
+/// theme = "high-contrast"
+/// ```
+///
+/// Every action under `[keys]` gets exactly the keys listed for it.
+impl FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut config = Config::default();
+        let mut section = None;
+        // keys this file binds, so one key can't do two things
+        let mut bound: HashMap<Key, Action> = HashMap::new();
+        let mut set = HashSet::new();
+
+        for (index, line) in s.lines().enumerate() {
+            let at_line = |reason: String| anyhow!("Line {}: {}", index + 1, reason);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = without_comment(name)
+                    .strip_suffix(']')
+                    .ok_or_else(|| at_line(String::from("expected ] after the section name")))?
+                    .trim();
+                match name {
+                    "keys" | "display" => section = Some(name),
+                    _ => return Err(at_line(format!("unknown section [{}]", name))),
+                }
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| at_line(String::from("expected name = value")))?;
+            let name = name.trim();
+            let values = parse_value(value.trim()).map_err(|reason| at_line(reason.to_string()))?;
+
+            match section {
+                Some("keys") => {
+                    let action = name
+                        .parse::<Action>()
+                        .map_err(|reason| at_line(reason.to_string()))?;
+                    if !set.insert(action) {
+                        return Err(at_line(format!("{} is set twice", action)));
+                    }
+                    let mut keys = Vec::new();
+                    for value in values {
+                        let key = value
+                            .parse::<Key>()
+                            .map_err(|reason| at_line(reason.to_string()))?;
+                        if let Some(other) = bound.insert(key, action) {
+                            return Err(at_line(format!(
+                                "{} is bound to both {} and {}",
+                                key, other, action
+                            )));
+                        }
+                        keys.push(key);
+                    }
+                    config.keys.bind(action, &keys);
+                }
+                Some("display") => match (name, values.as_slice()) {
+                    ("theme", [theme]) => {
+                        config.theme = Some(
+                            theme
+                                .parse()
+                                .map_err(|reason: anyhow::Error| at_line(reason.to_string()))?,
+                        )
+                    }
+                    ("theme", _) => return Err(at_line(String::from("theme takes a single name"))),
+                    _ => return Err(at_line(format!("unknown setting {}", name))),
+                },
+                _ => return Err(at_line(format!("{} is outside of a section", name))),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+// a quoted string or a list of them, followed by nothing but a comment
+fn parse_value(text: &str) -> anyhow::Result<Vec<String>> {
+    let (values, rest) = match text.strip_prefix('[') {
+        Some(mut rest) => {
+            let mut values = Vec::new();
+            loop {
+                rest = rest.trim_start();
+                if let Some(after) = rest.strip_prefix(']') {
+                    break (values, after);
+                }
+                let (value, after) = parse_string(rest)?;
+                values.push(value);
+                rest = after.trim_start();
+                match (rest.strip_prefix(','), rest.starts_with(']')) {
+                    (Some(after), _) => rest = after,
+                    (None, true) => (),
+                    (None, false) => return Err(anyhow!("expected , or ] in the list")),
+                }
+            }
+        }
+        None => {
+            let (value, rest) = parse_string(text)?;
+            (vec![value], rest)
+        }
+    };
+
+    match without_comment(rest).is_empty() {
+        true => Ok(values),
+        false => Err(anyhow!("unexpected {} after the value", rest.trim())),
+    }
+}
+
+// reads a "basic string" off the front of `text`, returns it and what follows
+fn parse_string(text: &str) -> anyhow::Result<(String, &str)> {
+    let rest = text
+        .strip_prefix('"')
+        .ok_or_else(|| anyhow!("values need to be in double quotes"))?;
+
+    let mut value = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &rest[index + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                _ => return Err(anyhow!("only \\\" and \\\\ can be escaped")),
+            },
+            c => value.push(c),
+        }
+    }
+    Err(anyhow!("missing the closing \""))
+}
+
+fn without_comment(text: &str) -> &str {
+    text.split_once('#')
+        .map_or(text, |(before, _)| before)
+        .trim()
+}
+
+/// Where the config is read from, None when there is neither `$XDG_CONFIG_HOME` nor `$HOME`
+pub fn path() -> Option<PathBuf> {
+    config_path(env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME"))
+}
+
+fn config_path(config_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    let config_home = xdg_dir(config_home, home, ".config")?;
+    Some(config_home.join("tic-tac-term").join("config.toml"))
+}
+
+/// An XDG base directory, `dir` as its variable has it or else `fallback` inside `home`.
+/// The XDG base directory spec says to ignore relative paths, None when neither is left.
+pub fn xdg_dir(dir: Option<OsString>, home: Option<OsString>, fallback: &str) -> Option<PathBuf> {
+    dir.map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            home.map(PathBuf::from)
+                .filter(|home| home.is_absolute())
+                .map(|home| home.join(fallback))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_keys() {
+        let keys = Keymap::default();
+        assert_eq!(keys.action(Key::Char(' ')), Some(Action::Place));
+        assert_eq!(keys.action(Key::Enter), Some(Action::Place));
+        assert_eq!(keys.action(Key::Char('q')), Some(Action::Quit));
+        assert_eq!(keys.action(Key::Char('w')), Some(Action::MoveUp));
+        assert_eq!(keys.action(Key::Ctrl('r')), Some(Action::Redo));
+        assert_eq!(keys.action(Key::Escape), None);
+
+        for (name, action, _) in ACTIONS {
+            assert_eq!(name.parse::<Action>().unwrap(), action);
+            assert_eq!(action.to_string(), name);
+        }
+    }
+
+    #[test]
+    fn test_config_keys() {
+        let text = r##"
+            # enter places, escape quits
+            [keys]
+            place = "enter"   # space does nothing now
+            quit = ["esc", "q"]
+            move-up = ["up", "#"]
+            restart = "s"
+        "##;
+        let keys = text.parse::<Config>().unwrap().keys;
+
+        assert_eq!(keys.action(Key::Enter), Some(Action::Place));
+        assert_eq!(keys.action(Key::Char(' ')), None);
+        assert_eq!(keys.action(Key::Escape), Some(Action::Quit));
+        assert_eq!(keys.action(Key::Char('q')), Some(Action::Quit));
+        assert_eq!(keys.action(Key::Char('#')), Some(Action::MoveUp));
+        assert_eq!(keys.action(Key::Char('k')), None);
+        // taken away from moving down, which keeps its other keys
+        assert_eq!(keys.action(Key::Char('s')), Some(Action::Restart));
+        assert_eq!(keys.action(Key::Char('j')), Some(Action::MoveDown));
+        assert_eq!(keys.action(Key::Char('r')), None);
+        assert_eq!(keys.key(Action::Restart), Some(Key::Char('s')));
+        assert_eq!(keys.key(Action::Quit), Some(Key::Escape));
+
+        assert_eq!(text.parse::<Config>().unwrap().theme, None);
+        let config = "[display]\ntheme = \"monochrome\""
+            .parse::<Config>()
+            .unwrap();
+        assert_eq!(config.theme, Some(Theme::MONOCHROME));
+
+        // an empty list unbinds
+        let keys = "[keys]\nundo = []".parse::<Config>().unwrap().keys;
+        assert_eq!(keys.action(Key::Char('u')), None);
+        assert_eq!(keys.key(Action::Undo), None);
+        assert!("".parse::<Config>().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let errors = [
+            ("place = \"enter\"", "Line 1: place is outside of a section"),
+            ("[colors]", "Line 1: unknown section [colors]"),
+            ("[keys\n", "Line 1: expected ] after the section name"),
+            ("[keys]\njump = \"j\"", "Line 2: Unknown action jump"),
+            (
+                "[keys]\nquit = \"escape key\"",
+                "Line 2: Unknown key escape key",
+            ),
+            (
+                "[keys]\nquit = esc",
+                "Line 2: values need to be in double quotes",
+            ),
+            ("[keys]\nquit = \"esc", "Line 2: missing the closing \""),
+            (
+                "[keys]\nquit = [\"esc\" \"q\"]",
+                "Line 2: expected , or ] in the list",
+            ),
+            (
+                "[keys]\nquit = \"esc\" \"q\"",
+                "Line 2: unexpected \"q\" after the value",
+            ),
+            ("[keys]\nquit", "Line 2: expected name = value"),
+            (
+                "[keys]\nquit = \"q\"\nquit = \"esc\"",
+                "Line 3: quit is set twice",
+            ),
+            ("[keys]\nundo = []\nundo = []", "Line 3: undo is set twice"),
+            (
+                "[keys]\nquit = \"esc\"\nplace = [\"enter\", \"esc\"]",
+                "Line 3: esc is bound to both quit and place",
+            ),
+            (
+                "[keys]\nredo = \"ctrl-c\"",
+                "Line 2: ctrl-c is taken by the terminal",
+            ),
+        ];
+        for (text, expected) in errors {
+            let reason = text.parse::<Config>().unwrap_err();
+            assert_eq!(reason.to_string(), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_path() {
+        let path = |config: Option<&str>, home: Option<&str>| {
+            config_path(config.map(OsString::from), home.map(OsString::from))
+        };
+
+        assert_eq!(
+            path(Some("/config"), Some("/home/alice")),
+            Some(PathBuf::from("/config/tic-tac-term/config.toml"))
+        );
+        assert_eq!(
+            path(Some("relative"), Some("/home/alice")),
+            Some(PathBuf::from(
+                "/home/alice/.config/tic-tac-term/config.toml"
+            ))
+        );
+        assert_eq!(path(None, None), None);
+    }
+}
@@ -1,123 +1,1057 @@
 use std::{
-    fmt,
+    cmp::Ordering,
     io::{self, Write},
 };
 
 use anyhow::anyhow;
 
-use crate::{network::NetState, terminal};
+use crate::{
+    ai::{Computer, Difficulty},
+    chat::Chat,
+    clock::Clock,
+    config::{Action, Keymap},
+    history::{self, History},
+    input::Decoder,
+    network::{self, Move, NetState, Role},
+    record::{Outcome, Record},
+    replay::Replay,
+    terminal,
+    theme::Theme,
+    ultimate::{UltimateState, BOARD_NAMES},
+};
 
 #[derive(Debug)]
 pub struct Game {
     pub state: State,
     pub player: Player,
+    pub computer: Option<Computer>,
+    pub ultimate: Option<UltimateState>,
     pub net_state: NetState,
     pub mode: Mode,
     pub board_pos: (u16, u16),
     pub cursor_pos: (u16, u16),
     pub free_cursor: bool,
-    pub symbol_slots: [(u16, u16); 9],
+    /// Terminal cursor switched off with `v`
+    pub cursor_hidden: bool,
+    /// What the keys do, from the config file
+    pub keys: Keymap,
+    /// Keys still coming in from the terminal, kept between reads
+    pub input: Decoder,
+    pub theme: Theme,
+    pub symbol_slots: Vec<(u16, u16)>,
+    /// Board index of the most recently placed mark
+    pub last_move: Option<usize>,
+    pub name: String,
+    /// Display name the other player sent when connecting
+    pub opponent_name: Option<String>,
+    /// Elo ratings of this player and of the opponent, the computer's comes with its level
+    pub rating: Option<u16>,
+    pub opponent_rating: Option<u16>,
+    pub is_host: bool,
+    /// People watching the host's game
+    pub spectators: usize,
+    /// Only in network games where both players support chatting
+    pub chat: Option<Chat>,
+    /// Score over several games, only in network games where both players support rematches
+    pub series: Option<Series>,
+    pub rematch: Rematch,
+    pub history: History,
+    /// Only in network games where both players support taking back moves
+    pub takebacks: bool,
+    pub takeback: Takeback,
+    /// Time left for both players when playing with `--clock`, the host's is authoritative
+    pub clock: Option<Clock>,
+    /// Player who ran out of time, which ended the game
+    pub out_of_time: Option<Player>,
+    /// Recorded game being stepped through, only in replays
+    pub replay: Option<Replay>,
+    /// Result of a game that just ended, the main loop takes it to update the stats
+    pub finished: Option<Outcome>,
+    /// Whether the current game's result went to `finished` already, taking back
+    /// moves and finishing again doesn't count twice
+    pub result_counted: bool,
+    /// Player asked to quit, the main loop cleans up and exits
+    pub quitting: bool,
 }
 
 impl Game {
-    pub fn new(mode: Mode, is_host: bool) -> Self {
+    /// A game in `mode`, games against the computer come from `vs_computer`
+    pub fn new(mode: Mode, is_host: bool, rules: Rules) -> Self {
         let mut player = Player::O;
         let mut net_state = NetState::Active;
         if mode == Mode::Network && !is_host {
             player = Player::X;
             net_state = NetState::Waiting;
         }
+        if mode == Mode::Spectator {
+            // nothing to show until the host sends the state
+            net_state = NetState::Resuming;
+        }
+
+        let ultimate = match rules.variant {
+            Variant::Ultimate => Some(UltimateState::new()),
+            Variant::Classic => None,
+        };
+
+        let board_pos = (1, 1);
+        let board_size = match ultimate {
+            Some(_) => UltimateState::SIZE,
+            None => rules.size,
+        };
+        let symbol_slots = slot_positions(board_pos, board_size);
 
         Game {
             player,
+            computer: None,
+            ultimate,
             mode,
             net_state,
-            state: State {
-                board: [' '; 9],
-                round: 0,
-                active: true,
-                current_player: Player::O,
-                winner: None,
-            },
-            symbol_slots: [
-                (3, 2),
-                (7, 2),
-                (11, 2),
-                (3, 4),
-                (7, 4),
-                (11, 4),
-                (3, 6),
-                (7, 6),
-                (11, 6),
-            ],
-            board_pos: (1, 1),
-            cursor_pos: (3, 2),
+            state: State::new(rules),
+            cursor_pos: symbol_slots[0],
+            symbol_slots,
+            board_pos,
             free_cursor: false,
+            cursor_hidden: false,
+            keys: Keymap::default(),
+            input: Decoder::default(),
+            theme: Theme::default(),
+            last_move: None,
+            name: String::new(),
+            opponent_name: None,
+            rating: None,
+            opponent_rating: None,
+            is_host,
+            spectators: 0,
+            chat: None,
+            series: None,
+            rematch: Rematch::None,
+            history: History::default(),
+            takebacks: false,
+            takeback: Takeback::None,
+            clock: None,
+            out_of_time: None,
+            replay: None,
+            finished: None,
+            result_counted: false,
+            quitting: false,
+        }
+    }
+
+    /// A game against the computer playing at `difficulty`, its choices drawn from `seed`
+    pub fn vs_computer(rules: Rules, difficulty: Difficulty, seed: u64) -> Self {
+        let mut game = Game::new(Mode::VsComputer, false, rules);
+        game.computer = Some(Computer::new(game.player.toggle(), difficulty, seed));
+        game.opponent_rating = Some(difficulty.rating());
+        game
+    }
+
+    /// Board index of the cell under the cursor, if the cursor is on one
+    pub fn cursor_cell(&self) -> Option<usize> {
+        self.symbol_slots
+            .iter()
+            .position(|pos| pos == &self.cursor_pos)
+    }
+
+    /// Board index of the cell drawn at screen position `pos`, anywhere between
+    /// the borders counts and not just the mark in the middle
+    pub fn cell_at(&self, pos: (u16, u16)) -> Option<usize> {
+        let (x, y) = pos;
+        self.symbol_slots
+            .iter()
+            .position(|&(slot_x, slot_y)| slot_y == y && slot_x.abs_diff(x) <= 1)
+    }
+
+    /// Cells per row and column of the board on screen
+    pub fn board_size(&self) -> u8 {
+        match self.ultimate {
+            Some(_) => UltimateState::SIZE,
+            None => self.state.rules.size,
+        }
+    }
+
+    /// Overall game state, the big board in ultimate games
+    pub fn status(&self) -> &State {
+        match &self.ultimate {
+            Some(ultimate) => &ultimate.meta,
+            None => &self.state,
+        }
+    }
+
+    /// Winner of the current game, including a win on time
+    pub fn winner(&self) -> Option<Player> {
+        match self.out_of_time {
+            Some(loser) => Some(loser.toggle()),
+            None => self.status().winner,
+        }
+    }
+
+    /// Result of the current game so far
+    pub fn outcome(&self) -> Outcome {
+        let status = self.status();
+        match (self.out_of_time, status.active, status.winner) {
+            (Some(loser), ..) => Outcome::WonOnTime(loser.toggle()),
+            (None, true, _) => Outcome::Unfinished,
+            (None, false, Some(winner)) => Outcome::Won(winner),
+            (None, false, None) => Outcome::Draw,
         }
     }
 
+    /// Names of the players of O and X, the same name twice when both sit at this keyboard
+    pub fn player_names(&self) -> (String, String) {
+        let opponent_name = match self.mode {
+            Mode::Local => self.name.clone(),
+            Mode::VsComputer => String::from("computer"),
+            Mode::Network | Mode::Spectator | Mode::Replay => self
+                .opponent_name
+                .clone()
+                .unwrap_or_else(|| String::from("opponent")),
+        };
+
+        match self.player {
+            Player::O => (self.name.clone(), opponent_name),
+            Player::X => (opponent_name, self.name.clone()),
+        }
+    }
+
+    /// Checks the board for a result, a game that just ended is reported through `finished`.
+    /// Spectators and replays only watch, their games are someone else's.
     pub fn check_state(&mut self) {
-        self.state.check_status();
+        match &mut self.ultimate {
+            Some(ultimate) => ultimate.check_status(),
+            None => self.state.check_status(),
+        }
+
+        let watching = matches!(self.mode, Mode::Spectator | Mode::Replay);
+        if !self.status().active && !self.result_counted && !watching {
+            self.result_counted = true;
+            self.finished = Some(self.outcome());
+        }
     }
 
     pub fn draw_board(&self) {
-        print!(
-            "{}",
-            terminal::Ansi::MoveCursor(self.board_pos.0, self.board_pos.1)
-        );
-        println!("┌───┬───┬───┐");
-        println!(
-            "│ {} │ {} │ {} │",
-            self.state.board[0], self.state.board[1], self.state.board[2]
-        );
-        println!("├───┼───┼───┤");
-        println!(
-            "│ {} │ {} │ {} │",
-            self.state.board[3], self.state.board[4], self.state.board[5]
-        );
-        println!("├───┼───┼───┤");
-        println!(
-            "│ {} │ {} │ {} │",
-            self.state.board[6], self.state.board[7], self.state.board[8]
+        if let Some(ultimate) = &self.ultimate {
+            self.draw_ultimate_board(ultimate);
+            return;
+        }
+
+        let size = self.state.rules.size as usize;
+        let (x, y) = self.board_pos;
+
+        let border = |left: &str, mid: &str, right: &str| {
+            format!(
+                "{}{}───{}",
+                left,
+                format!("───{}", mid).repeat(size - 1),
+                right
+            )
+        };
+
+        let winning = self.winning_cells();
+        let mut lines = vec![border("┌", "┬", "┐")];
+        for (i, row) in self.state.board.chunks(size).enumerate() {
+            if i > 0 {
+                lines.push(border("├", "┼", "┤"));
+            }
+            let cells: String = row
+                .iter()
+                .enumerate()
+                .map(|(j, c)| format!("│ {} ", self.styled(i * size + j, *c, &winning)))
+                .collect();
+            lines.push(format!("{}│", cells));
+        }
+        lines.push(border("└", "┴", "┘"));
+
+        for (i, line) in lines.iter().enumerate() {
+            print!("{}{}", terminal::Ansi::MoveCursor(x, y + i as u16), line);
+        }
+    }
+
+    // marks in their player's style, the cursor and the winning line drawn on top.
+    // Replays highlight the last move, stepping back and forth would be hard to follow otherwise.
+    fn styled(&self, index: usize, symbol: char, winning: &[usize]) -> String {
+        let mut style = self.theme.mark(symbol);
+        if winning.contains(&index) {
+            style = style.and(self.theme.winning);
+        }
+        let highlighted = match self.mode {
+            Mode::Replay => self.last_move,
+            _ if self.free_cursor => None,
+            _ => self.cursor_cell(),
+        };
+        if highlighted == Some(index) {
+            style = style.and(self.theme.cursor);
+        }
+        style.paint(symbol)
+    }
+
+    // board indices of the marks that won, on ultimate boards the lines of the sub-boards
+    // that make up the winning line of the big board
+    fn winning_cells(&self) -> Vec<usize> {
+        match &self.ultimate {
+            Some(ultimate) => ultimate
+                .meta
+                .winning_line()
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|board| {
+                    ultimate.boards[board]
+                        .winning_line()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(move |cell| UltimateState::join(board, cell))
+                })
+                .collect(),
+            None => self.state.winning_line().unwrap_or_default(),
+        }
+    }
+
+    /// Nine 3x3 boards separated by double lines, empty cells the current
+    /// player is allowed to play in are dotted
+    fn draw_ultimate_board(&self, ultimate: &UltimateState) {
+        let (x, y) = self.board_pos;
+        let winning = self.winning_cells();
+
+        let mut lines = Vec::new();
+        for grid_row in 0..=9 {
+            let horizontal = if grid_row % 3 == 0 {
+                "═══"
+            } else {
+                "───"
+            };
+            let mut border = String::new();
+            for grid_col in 0..=9 {
+                border.push(grid_junction(grid_row, grid_col));
+                if grid_col < 9 {
+                    border.push_str(horizontal);
+                }
+            }
+            lines.push(border);
+
+            if grid_row == 9 {
+                break;
+            }
+
+            let mut cells = String::new();
+            for col in 0..9 {
+                cells.push(if col % 3 == 0 { '║' } else { '│' });
+                let index = grid_row * 9 + col;
+                let (board, _) = UltimateState::split(index);
+                let symbol = match ultimate.cell(index) {
+                    ' ' if ultimate.is_playable(board) => '·',
+                    symbol => symbol,
+                };
+                cells.push_str(&format!(" {} ", self.styled(index, symbol, &winning)));
+            }
+            cells.push('║');
+            lines.push(cells);
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            print!("{}{}", terminal::Ansi::MoveCursor(x, y + i as u16), line);
+        }
+    }
+
+    /// Text lines below the board
+    pub fn draw_info(&self) {
+        let below_board = self.board_pos.1 + self.board_size() as u16 * 2 + 1;
+
+        if let Some(ultimate) = &self.ultimate {
+            let next_board = match ultimate.forced_board {
+                Some(board) => BOARD_NAMES[board],
+                None => "any",
+            };
+            print!(
+                "{}Next board: {}",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board),
+                next_board
+            );
+        }
+
+        if self.mode == Mode::Network {
+            let (o_name, x_name) = self.player_names();
+            let (o_name, x_name) = (o_name.as_str(), x_name.as_str());
+            let (o_rating, x_rating) = match self.player {
+                Player::O => (self.rating, self.opponent_rating),
+                Player::X => (self.opponent_rating, self.rating),
+            };
+            print!(
+                "{}O: {}  X: {}",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board + 1),
+                with_rating(o_name, o_rating),
+                with_rating(x_name, x_rating)
+            );
+
+            let net_status = match self.net_state {
+                NetState::Active | NetState::Waiting if !self.status().active => {
+                    self.game_over_status(o_name, x_name)
+                }
+                NetState::Active if self.takeback == Takeback::Offered => format!(
+                    "Opponent wants to take back their move{}",
+                    self.press(Action::Undo, "to allow it")
+                ),
+                NetState::Waiting if self.takeback == Takeback::Requested => {
+                    String::from("Waiting for opponent to allow taking back your move")
+                }
+                NetState::Active => String::from("Your turn"),
+                NetState::Waiting => String::from("Waiting for opponent"),
+                NetState::Desynced => {
+                    String::from("Out of sync with opponent, waiting for the board")
+                }
+                NetState::Disconnected if self.is_host => {
+                    String::from("Opponent disconnected, waiting for them to reconnect")
+                }
+                NetState::Disconnected => String::from("Connection lost, reconnecting..."),
+                NetState::Resuming => String::from("Reconnected, resuming game"),
+                NetState::Closed => String::from("Opponent left the game"),
+            };
+            print!(
+                "{}{}",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board + 2),
+                net_status
+            );
+
+            let mut next_line = below_board + 3;
+            if let Some(clock) = &self.clock {
+                print!(
+                    "{}{}",
+                    terminal::Ansi::MoveCursor(self.board_pos.0, next_line),
+                    describe_clock(clock)
+                );
+                next_line += 1;
+            }
+
+            if let Some(series) = &self.series {
+                print!(
+                    "{}{}",
+                    terminal::Ansi::MoveCursor(self.board_pos.0, next_line),
+                    describe_score(series, o_name, x_name)
+                );
+                next_line += 1;
+            }
+
+            if self.spectators > 0 {
+                print!(
+                    "{}{} watching",
+                    terminal::Ansi::MoveCursor(self.board_pos.0, next_line),
+                    self.spectators
+                );
+            }
+        }
+
+        if self.mode == Mode::Spectator {
+            let host_name = self.opponent_name.as_deref().unwrap_or("host");
+            print!(
+                "{}Spectating {}'s game (O)",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board + 1),
+                host_name
+            );
+
+            let status = self.status();
+            let watch_status = match (&self.net_state, self.winner()) {
+                (NetState::Disconnected, _) => {
+                    String::from("Connection to host lost, reconnecting...")
+                }
+                (NetState::Resuming, _) => String::from("Waiting for the board"),
+                (NetState::Closed, _) => String::from("Host closed the game"),
+                (_, Some(winner)) => format!("{:?} won", winner),
+                _ if !status.active => String::from("Draw"),
+                _ => format!("{:?} to play", status.current_player),
+            };
+            print!(
+                "{}{}",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board + 2),
+                watch_status
+            );
+
+            let mut next_line = below_board + 3;
+            if let Some(clock) = &self.clock {
+                print!(
+                    "{}{}",
+                    terminal::Ansi::MoveCursor(self.board_pos.0, next_line),
+                    describe_clock(clock)
+                );
+                next_line += 1;
+            }
+
+            if let Some(series) = &self.series {
+                print!(
+                    "{}{}",
+                    terminal::Ansi::MoveCursor(self.board_pos.0, next_line),
+                    describe_score(series, "O", "X")
+                );
+            }
+        }
+
+        if let Some(replay) = &self.replay {
+            print!(
+                "{}O: {}  X: {}",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board + 1),
+                replay.record.o_name,
+                replay.record.x_name
+            );
+            print!(
+                "{}{}",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board + 2),
+                replay.describe()
+            );
+
+            let controls = match replay.is_playing() {
+                true => format!(
+                    "Playing a move every {:?}{}",
+                    replay.delay,
+                    self.press(Action::Place, "to pause")
+                ),
+                false => format!(
+                    "Left and right step{}",
+                    self.press(
+                        Action::Place,
+                        &format!("to play a move every {:?}", replay.delay)
+                    )
+                ),
+            };
+            print!(
+                "{}{}",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board + 3),
+                controls
+            );
+        }
+
+        if let (Mode::Local | Mode::VsComputer, Some(clock)) = (&self.mode, &self.clock) {
+            print!(
+                "{}{}",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board + 1),
+                describe_clock(clock)
+            );
+        }
+
+        if let Some(computer) = &self.computer {
+            let your_rating = self
+                .rating
+                .map(|rating| format!(", yours {}", rating))
+                .unwrap_or_default();
+            print!(
+                "{}Computer: {:?} (seed {}), rated {}{}",
+                terminal::Ansi::MoveCursor(self.board_pos.0, below_board),
+                computer.difficulty,
+                computer.seed,
+                computer.difficulty.rating(),
+                your_rating
+            );
+        }
+    }
+
+    // what a finished network game says, including how to get to the next one
+    fn game_over_status(&self, o_name: &str, x_name: &str) -> String {
+        let name = |player: Player| match player {
+            Player::O => o_name,
+            Player::X => x_name,
+        };
+
+        let result = match (self.out_of_time, self.status().winner) {
+            (Some(flagged), _) => format!("{} ran out of time", name(flagged)),
+            (None, Some(winner)) => format!("{} won", name(winner)),
+            (None, None) => String::from("Draw"),
+        };
+
+        let Some(series) = &self.series else {
+            return result;
+        };
+
+        match (series.result(), self.rematch) {
+            (Some(Some(winner)), _) => format!("{}, {} wins the match!", result, name(winner)),
+            (Some(None), _) => format!("{}, the match is drawn", result),
+            (None, Rematch::None) => {
+                format!("{}{}", result, self.press(Action::Restart, "for a rematch"))
+            }
+            (None, Rematch::Requested) => {
+                format!("{}, waiting for opponent to accept the rematch", result)
+            }
+            (None, Rematch::Offered) => {
+                format!(
+                    "{}, opponent wants a rematch{}",
+                    result,
+                    self.press(Action::Restart, "to accept")
+                )
+            }
+        }
+    }
+
+    // ", press u to allow it" with whatever key the config has for `action`, nothing without one
+    fn press(&self, action: Action, what: &str) -> String {
+        match self.keys.key(action) {
+            Some(key) => format!(", press {} {}", key, what),
+            None => String::new(),
+        }
+    }
+
+    // column right of the board where the side panes start
+    fn side_pane_x(&self) -> u16 {
+        let board_width = self.board_size() as u16 * 4 + 1;
+        self.board_pos.0 + board_width + 3
+    }
+
+    /// Moves played so far, right next to the board. Spectators only ever get
+    /// the host's board, so they have no list to show.
+    fn draw_history(&self) {
+        if self.mode == Mode::Spectator {
+            return;
+        }
+
+        let height = self.board_size() as u16 * 2 + 1;
+        self.history.draw(
+            (self.side_pane_x(), self.board_pos.1),
+            height,
+            self.board_size(),
         );
-        println!("└───┴───┴───┘");
+    }
+
+    /// Chat pane to the right of the move list, returns where the cursor goes while typing
+    fn draw_chat(&self) -> Option<(u16, u16)> {
+        let chat = self.chat.as_ref()?;
+
+        let x = self.side_pane_x() + history::PANE_WIDTH + 2;
+        let width = terminal::get_size().0.saturating_sub(x);
+        let height = self.board_size() as u16 * 2 + 1;
+
+        chat.draw(
+            (x, self.board_pos.1),
+            width,
+            height,
+            self.keys.key(Action::Chat),
+        )
     }
 
     pub fn render(&self) -> anyhow::Result<()> {
         print!("{}", terminal::Ansi::ClearScreen);
         self.draw_board();
-        super::terminal::print_debug(self);
-        print!(
-            "{}",
-            terminal::Ansi::MoveCursor(self.cursor_pos.0, self.cursor_pos.1)
-        );
+        self.draw_info();
+        self.draw_history();
+        let chat_cursor = self.draw_chat();
+        let (x, y) = chat_cursor.unwrap_or(self.cursor_pos);
+        print!("{}", terminal::Ansi::MoveCursor(x, y));
         io::stdout().flush()?;
         Ok(())
     }
 
     pub fn attempt_placing(&mut self, symbol: char) {
-        if self.mode == Mode::Network && self.player != symbol.into() {
-            // network mode and not players turn
+        if matches!(self.mode, Mode::Spectator | Mode::Replay) {
+            // read-only, the host's state or the record is all that is shown
             return;
         }
 
-        if let Some(placement_index) = self
-            .symbol_slots
-            .iter()
-            .position(|pos| pos == &self.cursor_pos)
-        {
-            if self.state.board[placement_index] == ' '
-                && self.state.current_player == symbol.into()
-                && self.state.active
-            {
-                self.state.board[placement_index] = symbol;
-                self.state.round += 1;
-                self.state.current_player = self.state.current_player.toggle();
+        if self.mode != Mode::Local && self.player != symbol.into() {
+            // opponent is on the network or the computer, not players symbol
+            return;
+        }
+
+        if self.mode == Mode::Network && !self.is_connected() {
+            // a move now could not be delivered
+            return;
+        }
+
+        if self.mode == Mode::Network && !self.is_host {
+            // the host decides whether a move at 0:00 still made it
+            if let Some(clock) = &self.clock {
+                if clock.remaining(self.player).is_zero() {
+                    return;
+                }
+            }
+        }
+
+        if let Some(placement_index) = self.cursor_cell() {
+            self.place(placement_index, symbol);
+        };
+    }
+
+    /// Lets the computer opponent make its move if it is its turn
+    pub fn play_computer_turn(&mut self) {
+        let Some(computer) = &mut self.computer else {
+            return;
+        };
+
+        if !self.state.active || self.state.current_player != computer.player {
+            return;
+        }
+
+        let symbol = char::from(&computer.player);
+        if let Some(placement_index) = computer.choose_move(&self.state) {
+            self.place(placement_index, symbol);
+        }
+    }
+
+    // shared by human, computer and network placements so all follow the same turn rules
+    fn place(&mut self, placement_index: usize, symbol: char) -> bool {
+        let round = self.status().round;
+        if !self.place_mark(placement_index, symbol) {
+            return false;
+        }
+
+        self.last_move = Some(placement_index);
+        self.history.record(Move {
+            cell: placement_index as u16,
+            player: symbol.into(),
+            round,
+        });
+        // moving on turns down a takeback, or gives up on asking for one
+        self.takeback = Takeback::None;
+
+        let active = self.status().active;
+        if let Some(clock) = &mut self.clock {
+            match active {
+                true => clock.moved(symbol.into()),
+                false => clock.stop(),
+            }
+        }
+
+        let status = self.status();
+        if !status.active && self.mode == Mode::Network {
+            let winner = status.winner;
+            if let Some(series) = &mut self.series {
+                series.record(winner);
+            }
+        }
+
+        true
+    }
+
+    // the turn rules alone, without anything else that comes with a move
+    fn place_mark(&mut self, placement_index: usize, symbol: char) -> bool {
+        match &mut self.ultimate {
+            Some(ultimate) => ultimate.place(placement_index, symbol),
+            None => {
+                let allowed = self.state.board[placement_index] == ' '
+                    && self.state.current_player == symbol.into()
+                    && self.state.active;
+                if allowed {
+                    self.state.board[placement_index] = symbol;
+                    self.state.round += 1;
+                    self.state.current_player = self.state.current_player.toggle();
+                    self.state.check_status();
+                }
+                allowed
+            }
+        }
+    }
+
+    /// Takes back the latest move, in network games the opponent has to allow it first
+    pub fn undo(&mut self) {
+        match self.mode {
+            Mode::Local => {
+                self.take_back();
+            }
+            // the computer's reply goes too, it would just play it again
+            Mode::VsComputer => {
+                while self.take_back() && self.status().current_player != self.player {}
+            }
+            Mode::Network => self.ask_for_takeback(),
+            Mode::Spectator | Mode::Replay => (),
+        }
+    }
+
+    /// Plays the last move that was taken back again, only in local games
+    pub fn redo(&mut self) {
+        if !matches!(self.mode, Mode::Local | Mode::VsComputer) {
+            return;
+        }
+
+        while let Some(mv) = self.history.next_redo() {
+            if !self.place(mv.cell as usize, char::from(&mv.player)) {
+                break;
+            }
+            if self.mode == Mode::Local || self.status().current_player == self.player {
+                break;
             }
+        }
+    }
+
+    // the board is rebuilt from the remaining moves, in ultimate games where the next
+    // move may go depends on the previous one so clearing a single cell is not enough
+    fn take_back(&mut self) -> bool {
+        if self.out_of_time.is_some() {
+            return false;
+        }
+
+        let Some(taken_back) = self.history.undo() else {
+            return false;
         };
+
+        let first_player = self.history.moves.first().unwrap_or(&taken_back).player;
+        // the moves were all played on this board before
+        let _ = self.rebuild(first_player, &self.history.moves.clone());
+
+        // nobody's clock runs before the first move
+        let current_player = self.status().current_player;
+        if let Some(clock) = &mut self.clock {
+            match self.history.moves.is_empty() {
+                true => clock.stop(),
+                false => clock.start(current_player),
+            }
+        }
+
+        true
+    }
+
+    // only the player who just moved can ask, while the opponent is still thinking
+    fn ask_for_takeback(&mut self) {
+        if !self.takebacks || !self.status().active || !self.is_connected() {
+            return;
+        }
+
+        let last_move_ours = self
+            .history
+            .moves
+            .last()
+            .is_some_and(|mv| mv.player == self.player);
+        match self.takeback {
+            Takeback::None if last_move_ours => self.takeback = Takeback::Requested,
+            Takeback::Offered => {
+                self.take_back();
+                self.takeback = Takeback::None;
+                self.sync_net_state();
+            }
+            _ => (),
+        }
+    }
+
+    /// Other player wants to take back `mv`, which has to be the latest move
+    pub fn takeback_requested(&mut self, mv: Move) {
+        let latest = self.history.moves.last() == Some(&mv);
+        if self.takebacks && self.status().active && latest && mv.player != self.player {
+            self.takeback = Takeback::Offered;
+        }
+    }
+
+    /// Other player allowed taking back `mv`
+    pub fn takeback_accepted(&mut self, mv: Move) {
+        if self.takeback == Takeback::Requested && self.history.moves.last() == Some(&mv) {
+            self.take_back();
+            self.takeback = Takeback::None;
+            self.sync_net_state();
+        }
+    }
+
+    /// Applies a move made by the other player to our copy of the state
+    pub fn apply_move(&mut self, mv: Move) -> anyhow::Result<()> {
+        if mv.player != self.player.toggle() {
+            return Err(anyhow!("Wrong player made the move"));
+        }
+
+        if mv.round != self.status().round {
+            return Err(anyhow!(
+                "Move was made in round {} but the current round is {}",
+                mv.round,
+                self.status().round
+            ));
+        }
+
+        let cells = self.board_size() as usize * self.board_size() as usize;
+        if mv.cell as usize >= cells {
+            return Err(anyhow!("Cell {} is outside the board", mv.cell));
+        }
+
+        if !self.place(mv.cell as usize, char::from(&mv.player)) {
+            return Err(anyhow!("Cell {} can't be played right now", mv.cell));
+        }
+
+        Ok(())
     }
 
-    pub fn validate(&mut self, potential_state: State) -> anyhow::Result<()> {
+    /// Ends the game when the player to move ran out of time. Only the host keeps an eye
+    /// on the clock in network games, the others hear about it through `sync_clock`.
+    pub fn check_clock(&mut self) {
+        if matches!(self.mode, Mode::Network | Mode::Spectator) && !self.is_host {
+            return;
+        }
+
+        let Some(clock) = &mut self.clock else {
+            return;
+        };
+        if let Some(player) = clock.check_flag() {
+            self.end_on_time(player);
+        }
+    }
+
+    /// Takes over the host's clock, which may have ended the game
+    pub fn sync_clock(&mut self, clock: Clock) {
+        let flagged = clock.flagged;
+        self.clock = Some(clock);
+        if let Some(player) = flagged {
+            self.end_on_time(player);
+        }
+    }
+
+    // a flag fall ends the game like a win on the board would
+    fn end_on_time(&mut self, player: Player) {
+        if !self.status().active {
+            return;
+        }
+
+        self.out_of_time = Some(player);
+        match &mut self.ultimate {
+            Some(ultimate) => ultimate.meta.active = false,
+            None => self.state.active = false,
+        }
+
+        // the others learn about the flag from the host, along with its score
+        if self.mode == Mode::Network && self.is_host {
+            let winner = self.winner();
+            if let Some(series) = &mut self.series {
+                series.record(winner);
+            }
+        }
+    }
+
+    /// Plays the moves of a saved game from the start, fails if one of them breaks the
+    /// rules or the saved result is not what the board shows
+    pub fn load(&mut self, record: &Record) -> anyhow::Result<()> {
+        if record.rules != self.state.rules {
+            return Err(anyhow!("Saved game was played with different rules"));
+        }
+
+        self.out_of_time = None;
+        if let Some(clock) = &mut self.clock {
+            clock.reset();
+        }
+
+        let first_player = record.moves.first().map_or(Player::O, |mv| mv.player);
+        self.history.clear();
+        self.rebuild(first_player, &record.moves)?;
+        self.history.moves = record.moves.clone();
+
+        if let Outcome::WonOnTime(winner) = record.outcome {
+            self.end_on_time(winner.toggle());
+        }
+        if self.outcome() != record.outcome {
+            return Err(anyhow!("Saved result does not match the moves"));
+        }
+        // it was counted when it was played
+        self.result_counted = !self.status().active;
+
+        if self.mode == Mode::Network {
+            self.sync_net_state();
+        }
+        Ok(())
+    }
+
+    /// Shows the moves of the replay up to where it is now, call it after the replay moved
+    pub fn show_replay(&mut self) {
+        let Some(replay) = &self.replay else {
+            return;
+        };
+
+        let moves = replay.record.moves[..replay.shown].to_vec();
+        let first_player = replay
+            .record
+            .moves
+            .first()
+            .map_or(Player::O, |mv| mv.player);
+        // the record was checked when it was read
+        let _ = self.rebuild(first_player, &moves);
+        self.history.clear();
+        self.history.moves = moves;
+        if let Some(cell) = self.last_move {
+            self.cursor_pos = self.symbol_slots[cell];
+        }
+    }
+
+    /// Lets auto-play in replays move on when it is time
+    pub fn tick_replay(&mut self) {
+        if self.replay.as_mut().is_some_and(Replay::tick) {
+            self.show_replay();
+        }
+    }
+
+    // plays `moves` on an empty board, which doesn't touch the history or the clock
+    fn rebuild(&mut self, first_player: Player, moves: &[Move]) -> anyhow::Result<()> {
+        self.clear_board(first_player);
+        self.last_move = None;
+        for mv in moves {
+            if !self.place_mark(mv.cell as usize, char::from(&mv.player)) {
+                return Err(anyhow!(
+                    "Move {}, {:?} {}, breaks the rules",
+                    mv.round + 1,
+                    mv.player,
+                    history::coordinate(mv.cell, self.board_size())
+                ));
+            }
+            self.last_move = Some(mv.cell as usize);
+        }
+        Ok(())
+    }
+
+    /// Replaces the state with the host's authoritative copy after a reconnect
+    pub fn resume(&mut self, payload: &[u8]) -> anyhow::Result<()> {
+        // moves that happened in between are unknown, so is everything before them
+        if payload != self.state_payload() {
+            self.history.clear();
+            self.takeback = Takeback::None;
+        }
+
+        match &mut self.ultimate {
+            Some(ultimate) => *ultimate = UltimateState::try_from(payload)?,
+            None => {
+                let resumed = State::try_from(payload)?;
+                if resumed.rules.size != self.state.rules.size
+                    || resumed.rules.win_length != self.state.rules.win_length
+                {
+                    return Err(anyhow!("Board size or win length does not match"));
+                }
+                self.state.board = resumed.board;
+                self.state.round = resumed.round;
+                self.state.active = resumed.active;
+                self.state.current_player = resumed.current_player;
+                self.state.winner = resumed.winner;
+            }
+        }
+
+        // the host already moved on to the next game
+        if self.status().active {
+            self.rematch = Rematch::None;
+            self.out_of_time = None;
+            self.result_counted = false;
+        }
+
+        self.sync_net_state();
+        Ok(())
+    }
+
+    /// Sets whose turn it is on the network from the state itself
+    pub fn sync_net_state(&mut self) {
+        self.net_state = match self.status().current_player == self.player {
+            true => NetState::Active,
+            false => NetState::Waiting,
+        };
+    }
+
+    fn is_connected(&self) -> bool {
+        !matches!(
+            self.net_state,
+            NetState::Disconnected | NetState::Resuming | NetState::Closed
+        )
+    }
+
+    /// Hash of the full state, exchanged with the other player to detect desyncs
+    pub fn state_hash(&self) -> u64 {
+        network::state_hash(&self.state_payload())
+    }
+
+    pub fn validate(&mut self, potential_state: State, from: Role) -> anyhow::Result<()> {
+        if from == Role::Spectator {
+            return Err(anyhow!("Spectators can't make moves"));
+        }
+
+        if potential_state.rules != self.state.rules {
+            return Err(anyhow!("Board size or win length does not match"));
+        }
+
+        if !self.state.active {
+            return Err(anyhow!("Game is already over"));
+        }
+
+        if self.state.current_player != self.player.toggle() {
+            return Err(anyhow!("Not the other player's turn"));
+        }
+
         let mut diff_indexes = Vec::new();
         for (i, (old, new)) in self
             .state
@@ -135,6 +1069,10 @@ impl Game {
             return Err(anyhow!("Exactly one move must be made"));
         }
 
+        if self.state.board[diff_indexes[0]] != ' ' {
+            return Err(anyhow!("Cell {} is already taken", diff_indexes[0]));
+        }
+
         if potential_state.round != self.state.round + 1 {
             return Err(anyhow!("Round number must increment by 1"));
         }
@@ -156,30 +1094,486 @@ impl Game {
         Ok(())
     }
 
+    /// Ultimate version of `validate`, the single changed cell is replayed on a copy
+    /// of the current state so the send-to-board rule is checked as well
+    pub fn validate_ultimate(
+        &mut self,
+        potential_state: UltimateState,
+        from: Role,
+    ) -> anyhow::Result<()> {
+        if from == Role::Spectator {
+            return Err(anyhow!("Spectators can't make moves"));
+        }
+
+        let Some(ultimate) = &mut self.ultimate else {
+            return Err(anyhow!("Not playing ultimate tic-tac-toe"));
+        };
+
+        let diff_indexes: Vec<usize> = (0..81)
+            .filter(|index| ultimate.cell(*index) != potential_state.cell(*index))
+            .collect();
+
+        if diff_indexes.len() != 1 {
+            return Err(anyhow!("Exactly one move must be made"));
+        }
+
+        let symbol = potential_state.cell(diff_indexes[0]);
+        if symbol == ' ' || Player::from(symbol) != self.player.toggle() {
+            return Err(anyhow!("Wrong player made the move"));
+        }
+
+        let mut expected = ultimate.clone();
+        if !expected.place(diff_indexes[0], symbol) {
+            return Err(anyhow!("Move is not allowed on that sub-board"));
+        }
+
+        if expected != potential_state {
+            return Err(anyhow!("State does not match the move that was made"));
+        }
+
+        *ultimate = potential_state;
+        Ok(())
+    }
+
+    /// Checks a move made by `mv.player` and applies it, for the lobby server which referees
+    /// games without playing in them. The state the move leads to goes through `validate`
+    /// like a full state sent by the other player would.
+    pub fn validate_move(&mut self, mv: Move) -> anyhow::Result<()> {
+        // validate expects the move from the opponent of whoever's copy this is
+        self.player = mv.player.toggle();
+
+        if mv.round != self.status().round {
+            return Err(anyhow!(
+                "Move was made in round {} but the current round is {}",
+                mv.round,
+                self.status().round
+            ));
+        }
+
+        let cell = mv.cell as usize;
+        let symbol = char::from(&mv.player);
+        match &self.ultimate {
+            Some(ultimate) => {
+                let mut potential_state = ultimate.clone();
+                if cell >= 81 || !potential_state.place(cell, symbol) {
+                    return Err(anyhow!("Cell {} can't be played right now", mv.cell));
+                }
+                self.validate_ultimate(potential_state, Role::Player)
+            }
+            None => {
+                if cell >= self.state.board.len() {
+                    return Err(anyhow!("Cell {} is outside the board", mv.cell));
+                }
+                let mut potential_state = self.state.clone();
+                potential_state.board[cell] = symbol;
+                potential_state.round += 1;
+                potential_state.current_player = potential_state.current_player.toggle();
+                potential_state.check_status();
+                self.validate(potential_state, Role::Player)
+            }
+        }
+    }
+
+    /// Current state in the wire format of the variant being played
+    pub fn state_payload(&self) -> Vec<u8> {
+        match &self.ultimate {
+            Some(ultimate) => ultimate.into(),
+            None => (&self.state).into(),
+        }
+    }
+
+    /// Parses a state payload from the other player or a spectator and validates it,
+    /// returns Ok(false) if the other player's payload is not newer than the current state
+    pub fn validate_payload(&mut self, payload: &[u8], from: Role) -> anyhow::Result<bool> {
+        if self.ultimate.is_some() {
+            let potential_state = UltimateState::try_from(payload)?;
+            if potential_state.meta.round <= self.status().round && from == Role::Player {
+                return Ok(false);
+            }
+            self.validate_ultimate(potential_state, from)?;
+        } else {
+            let potential_state = State::try_from(payload)?;
+            if potential_state.round <= self.state.round && from == Role::Player {
+                return Ok(false);
+            }
+            self.validate(potential_state, from)?;
+        }
+
+        Ok(true)
+    }
+
     pub fn get_current_player(&self) -> &Player {
-        &self.state.current_player
+        &self.status().current_player
     }
 
     pub fn restart(&mut self) {
         match self.mode {
-            Mode::Local => self.state.restart(),
-            Mode::Network => (),
+            Mode::Local | Mode::VsComputer => {
+                self.clear_board(Player::O);
+                self.last_move = None;
+                self.out_of_time = None;
+                self.result_counted = false;
+                self.history.clear();
+                if let Some(clock) = &mut self.clock {
+                    clock.reset();
+                }
+            }
+            Mode::Network => self.ask_for_rematch(),
+            Mode::Spectator | Mode::Replay => (),
+        }
+    }
+
+    // a network game only starts over once both players asked for it
+    fn ask_for_rematch(&mut self) {
+        let Some(series) = &self.series else {
+            return;
+        };
+
+        if self.status().active || series.result().is_some() || !self.is_connected() {
+            return;
+        }
+
+        match self.rematch {
+            Rematch::None => self.rematch = Rematch::Requested,
+            Rematch::Requested => (),
+            Rematch::Offered => self.next_game(),
+        }
+    }
+
+    /// Other player asked for a rematch, starts it right away if we already asked too
+    pub fn rematch_requested(&mut self) {
+        if self.status().active || self.series.is_none() {
+            return;
+        }
+
+        match self.rematch {
+            Rematch::Requested => self.next_game(),
+            _ => self.rematch = Rematch::Offered,
+        }
+    }
+
+    /// Other player accepted our rematch
+    pub fn rematch_accepted(&mut self) {
+        if self.rematch == Rematch::Requested {
+            self.next_game();
+        }
+    }
+
+    /// Clears the board for the next game of the series, the first move alternates between games
+    pub fn next_game(&mut self) {
+        let first_player = self
+            .series
+            .as_ref()
+            .map(Series::first_player)
+            .unwrap_or(Player::O);
+
+        self.clear_board(first_player);
+        if let Some(clock) = &mut self.clock {
+            clock.reset();
+        }
+
+        self.last_move = None;
+        self.out_of_time = None;
+        self.result_counted = false;
+        self.history.clear();
+        self.rematch = Rematch::None;
+        self.takeback = Takeback::None;
+        self.sync_net_state();
+    }
+
+    fn clear_board(&mut self, first_player: Player) {
+        self.state.restart();
+        self.state.current_player = first_player;
+        if let Some(ultimate) = &mut self.ultimate {
+            ultimate.restart();
+            ultimate.meta.current_player = first_player;
+        }
+    }
+}
+
+// "alice (1216)", just the name for players without a rating
+fn with_rating(name: &str, rating: Option<u16>) -> String {
+    match rating {
+        Some(rating) => format!("{} ({})", name, rating),
+        None => name.to_string(),
+    }
+}
+
+// "Best of 5: alice 2 - 1 bob", draws are only mentioned once there are any
+fn describe_score(series: &Series, o_name: &str, x_name: &str) -> String {
+    let label = match series.best_of {
+        Some(best_of) => format!("Best of {}", best_of),
+        None => String::from("Score"),
+    };
+    let mut score = format!(
+        "{}: {} {} - {} {}",
+        label, o_name, series.o_wins, series.x_wins, x_name
+    );
+    if series.draws > 0 {
+        score.push_str(&format!(" ({} drawn)", series.draws));
+    }
+    score
+}
+
+// "O 4:32  X 5:00", and who lost on time once it happened
+fn describe_clock(clock: &Clock) -> String {
+    let times = format!(
+        "O {}  X {}",
+        clock.display(Player::O),
+        clock.display(Player::X)
+    );
+    match clock.flagged {
+        Some(flagged) => format!("{}  {:?} ran out of time", times, flagged),
+        None => times,
+    }
+}
+
+/// Box drawing character where grid line `row` meets grid line `col` on the
+/// ultimate board, every third line is double to separate the sub-boards
+fn grid_junction(row: usize, col: usize) -> char {
+    let (top, bottom) = (row == 0, row == 9);
+    let (left, right) = (col == 0, col == 9);
+
+    match (row.is_multiple_of(3), col.is_multiple_of(3)) {
+        (true, true) => match (top, bottom, left, right) {
+            (true, _, true, _) => '╔',
+            (true, _, _, true) => '╗',
+            (true, ..) => '╦',
+            (_, true, true, _) => '╚',
+            (_, true, _, true) => '╝',
+            (_, true, ..) => '╩',
+            (_, _, true, _) => '╠',
+            (_, _, _, true) => '╣',
+            _ => '╬',
+        },
+        (true, false) if top => '╤',
+        (true, false) if bottom => '╧',
+        (true, false) => '╪',
+        (false, true) if left => '╟',
+        (false, true) if right => '╢',
+        (false, true) => '╫',
+        (false, false) => '┼',
+    }
+}
+
+/// Screen positions of every cell on a board drawn at `board_pos`,
+/// each cell is 4 columns wide and 2 rows high including its border
+fn slot_positions(board_pos: (u16, u16), size: u8) -> Vec<(u16, u16)> {
+    let (x, y) = board_pos;
+    (0..size as u16)
+        .flat_map(|row| (0..size as u16).map(move |col| (x + 2 + col * 4, y + 1 + row * 2)))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    Classic,
+    /// Nine 3x3 boards inside a big one, see `UltimateState`
+    Ultimate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rules {
+    pub size: u8,
+    pub win_length: u8,
+    pub variant: Variant,
+}
+
+impl Rules {
+    pub const MIN_SIZE: u8 = 3;
+    pub const MAX_SIZE: u8 = 19;
+
+    pub fn new(size: u8, win_length: u8) -> anyhow::Result<Self> {
+        if !(Self::MIN_SIZE..=Self::MAX_SIZE).contains(&size) {
+            return Err(anyhow!(
+                "Board size must be between {} and {}",
+                Self::MIN_SIZE,
+                Self::MAX_SIZE
+            ));
+        }
+
+        if win_length < Self::MIN_SIZE || win_length > size {
+            return Err(anyhow!(
+                "Win length must be between {} and the board size",
+                Self::MIN_SIZE
+            ));
+        }
+
+        Ok(Rules {
+            size,
+            win_length,
+            variant: Variant::Classic,
+        })
+    }
+
+    pub fn ultimate() -> Self {
+        Rules {
+            variant: Variant::Ultimate,
+            ..Rules::default()
+        }
+    }
+
+    pub fn cells(&self) -> usize {
+        self.size as usize * self.size as usize
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            size: 3,
+            win_length: 3,
+            variant: Variant::Classic,
+        }
+    }
+}
+
+/// Running score of the games played over one connection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Series {
+    /// Games the match is played over, None keeps going for as long as the players want
+    pub best_of: Option<u8>,
+    pub o_wins: u8,
+    pub x_wins: u8,
+    pub draws: u8,
+}
+
+impl Series {
+    pub fn new(best_of: Option<u8>) -> Self {
+        Series {
+            best_of,
+            ..Default::default()
+        }
+    }
+
+    pub fn played(&self) -> u16 {
+        self.o_wins as u16 + self.x_wins as u16 + self.draws as u16
+    }
+
+    /// Counts a finished game, games after the match is decided don't count
+    pub fn record(&mut self, winner: Option<Player>) {
+        if self.result().is_some() {
+            return;
+        }
+
+        match winner {
+            Some(Player::O) => self.o_wins = self.o_wins.saturating_add(1),
+            Some(Player::X) => self.x_wins = self.x_wins.saturating_add(1),
+            None => self.draws = self.draws.saturating_add(1),
+        }
+    }
+
+    /// None while the match goes on, Some(None) when it ended without a winner
+    pub fn result(&self) -> Option<Option<Player>> {
+        let best_of = self.best_of?;
+
+        // a majority of the games can't be caught up with
+        let needed = best_of / 2 + 1;
+        if self.o_wins >= needed {
+            return Some(Some(Player::O));
+        }
+        if self.x_wins >= needed {
+            return Some(Some(Player::X));
+        }
+
+        // draws can leave every game played without a majority
+        if self.played() >= best_of as u16 {
+            return Some(match self.o_wins.cmp(&self.x_wins) {
+                Ordering::Greater => Some(Player::O),
+                Ordering::Less => Some(Player::X),
+                Ordering::Equal => None,
+            });
+        }
+
+        None
+    }
+
+    /// Who places the first mark of the next game, players take turns starting
+    pub fn first_player(&self) -> Player {
+        match self.played() % 2 {
+            0 => Player::O,
+            _ => Player::X,
         }
     }
 }
 
-#[derive(Debug)]
+/// Binary format (4 bytes):
+/// - Byte 0: Games in the match, 0 for no limit
+/// - Byte 1: Games won by O
+/// - Byte 2: Games won by X
+/// - Byte 3: Drawn games
+impl TryFrom<&[u8]> for Series {
+    fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() != 4 {
+            return Err(anyhow!("Series can only be deserialized from 4 bytes"));
+        }
+
+        Ok(Series {
+            best_of: (bytes[0] != 0).then_some(bytes[0]),
+            o_wins: bytes[1],
+            x_wins: bytes[2],
+            draws: bytes[3],
+        })
+    }
+
+    type Error = anyhow::Error;
+}
+
+impl From<&Series> for Vec<u8> {
+    fn from(series: &Series) -> Self {
+        vec![
+            series.best_of.unwrap_or(0),
+            series.o_wins,
+            series.x_wins,
+            series.draws,
+        ]
+    }
+}
+
+/// Where agreeing on another game stands after a network game ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rematch {
+    None,
+    /// We asked, waiting for the opponent to accept
+    Requested,
+    /// Opponent asked, pressing restart accepts
+    Offered,
+}
+
+/// Where taking back the latest move of a network game stands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Takeback {
+    None,
+    /// We asked to take back our move, waiting for the opponent to allow it
+    Requested,
+    /// Opponent asked to take back their move, pressing undo allows it
+    Offered,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct State {
-    pub board: [char; 9],
-    pub round: u8,
+    pub board: Vec<char>,
+    pub rules: Rules,
+    pub round: u16,
     pub active: bool,
     pub current_player: Player,
     pub winner: Option<Player>,
 }
 
 impl State {
+    pub fn new(rules: Rules) -> Self {
+        State {
+            board: vec![' '; rules.cells()],
+            rules,
+            round: 0,
+            active: true,
+            current_player: Player::O,
+            winner: None,
+        }
+    }
+
     pub fn restart(&mut self) {
-        self.board = [' '; 9];
+        self.board = vec![' '; self.rules.cells()];
         self.round = 0;
         self.active = true;
         self.current_player = Player::O;
@@ -199,72 +1593,107 @@ impl State {
     }
 
     fn check_rows(&mut self) -> Option<Player> {
-        self.board
-            .chunks(3)
-            .find(|row| row[0] == row[1] && row[1] == row[2] && row[0] != ' ')
-            .map(|c_arr| c_arr[0].into())
+        self.check_direction(0, 1)
     }
 
     fn check_cols(&mut self) -> Option<Player> {
-        for col in 0..3 {
-            if self.board[col] == self.board[col + 3]
-                && self.board[col + 3] == self.board[col + 6]
-                && self.board[col] != ' '
-            {
-                return Some(self.board[col].into());
-            }
-        }
-        None
+        self.check_direction(1, 0)
     }
 
     fn check_diagonal(&mut self) -> Option<Player> {
-        let board = self.board;
+        self.check_direction(1, 1)
+            .or_else(|| self.check_direction(1, -1))
+    }
 
-        if board[0] == board[4] && board[4] == board[8] && board[4] != ' ' {
-            return Some(board[4].into());
-        }
+    fn check_direction(&self, d_row: isize, d_col: isize) -> Option<Player> {
+        let line = self.line_in_direction(d_row, d_col)?;
+        Some(self.board[line[0]].into())
+    }
 
-        if board[2] == board[4] && board[4] == board[6] && board[4] != ' ' {
-            return Some(board[4].into());
+    /// Cells of the line that won the game, None while nobody has won
+    pub fn winning_line(&self) -> Option<Vec<usize>> {
+        self.winner?;
+        [(0, 1), (1, 0), (1, 1), (1, -1)]
+            .into_iter()
+            .find_map(|(d_row, d_col)| self.line_in_direction(d_row, d_col))
+    }
+
+    /// Looks for `win_length` equal marks in a line starting at any cell
+    /// and stepping `d_row`/`d_col` each time, returns the cells of the first one
+    fn line_in_direction(&self, d_row: isize, d_col: isize) -> Option<Vec<usize>> {
+        let size = self.rules.size as isize;
+        let win_length = self.rules.win_length as isize;
+
+        for row in 0..size {
+            for col in 0..size {
+                let symbol = self.board[(row * size + col) as usize];
+                // empty, or a drawn sub-board on the ultimate big board
+                if symbol != 'X' && symbol != 'O' {
+                    continue;
+                }
+
+                let end_row = row + d_row * (win_length - 1);
+                let end_col = col + d_col * (win_length - 1);
+                if !(0..size).contains(&end_row) || !(0..size).contains(&end_col) {
+                    continue;
+                }
+
+                let line: Vec<usize> = (0..win_length)
+                    .map(|step| ((row + d_row * step) * size + col + d_col * step) as usize)
+                    .collect();
+                if line.iter().all(|&index| self.board[index] == symbol) {
+                    return Some(line);
+                }
+            }
         }
 
         None
     }
 }
 
-/// Binary format (11 bytes):
-/// - Bytes 0-8: Board state ('X', 'O', or ' ' for each cell)
-/// - Byte 9: Round count
-/// - Byte 10: Flag byte
+/// Binary format (5 + N² bytes for an N×N board):
+/// - Byte 0: Board size (N)
+/// - Byte 1: Marks in a row needed to win
+/// - Next N² bytes: Board state ('X', 'O', or ' ' for each cell)
+/// - Next 2 bytes: Round count (u16, big endian)
+/// - Last byte: Flag byte
 ///   - Bit 0: Current player (0 = X, 1 = O)
 ///   - Bit 1: Game active (0 = inactive, 1 = active)
 ///   - Bit 2: Has winner (0 = no, 1 = yes)
 ///   - Bit 3: Winner type (0 = X, 1 = O) if bit 2 is set
 impl TryFrom<&[u8]> for State {
     fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
-        if bytes.len() != 11 {
-            return Err(anyhow!("Full state can only be deserialized from 11 bytes"));
+        if bytes.len() < 2 {
+            return Err(anyhow!("State is missing board size and win length"));
+        }
+
+        let rules = Rules::new(bytes[0], bytes[1])?;
+        let cells = rules.cells();
+
+        if bytes.len() != cells + 5 {
+            return Err(anyhow!(
+                "Full state for a {0}x{0} board can only be deserialized from {1} bytes",
+                rules.size,
+                cells + 5
+            ));
         }
-        // board is 9 bytes (no need for full char (4bytes), can only have 3 values)
-        let board_bytes = &bytes[0..9];
-        let board: [char; 9] = board_bytes
+        // one byte per cell (no need for full char (4bytes), can only have 3 values)
+        let board: Vec<char> = bytes[2..2 + cells]
             .iter()
             .map(|b| match b {
                 b'X' => 'X',
                 b'O' => 'O',
                 _ => ' ',
             })
-            .collect::<Vec<char>>()
-            .try_into()
-            .expect("Failed to convert board bytes");
+            .collect();
 
-        // round count is single u8
-        let round = bytes[9];
+        // round count is u16
+        let round = u16::from_be_bytes([bytes[2 + cells], bytes[3 + cells]]);
 
         // current_player = 1bit
         // winner = 2bit (some/none + player)
         // active = 1bit bool
-        let flags_byte = bytes[10];
+        let flags_byte = bytes[4 + cells];
 
         // extract flags
         // TODO add more comments about bit ops
@@ -285,9 +1714,9 @@ impl TryFrom<&[u8]> for State {
             None
         };
 
-        // full state from 11byte
         Ok(State {
             board,
+            rules,
             round,
             active,
             current_player,
@@ -300,10 +1729,14 @@ impl TryFrom<&[u8]> for State {
 
 impl From<&State> for Vec<u8> {
     fn from(state: &State) -> Self {
-        let mut bytes: Vec<u8> = Vec::with_capacity(11);
+        let mut bytes: Vec<u8> = Vec::with_capacity(state.board.len() + 5);
 
-        // board 9 bytes
-        for c in state.board {
+        // rules 2 bytes
+        bytes.push(state.rules.size);
+        bytes.push(state.rules.win_length);
+
+        // board 1 byte per cell
+        for c in &state.board {
             let byte = match c {
                 'X' => b'X',
                 'O' => b'O',
@@ -312,8 +1745,8 @@ impl From<&State> for Vec<u8> {
             bytes.push(byte)
         }
 
-        // round count 1 byte (u8)
-        bytes.push(state.round);
+        // round count 2 bytes (u16)
+        bytes.extend(state.round.to_be_bytes());
 
         // pack flags into a single byte
         let mut flags_byte: u8 = 0;
@@ -345,76 +1778,25 @@ impl From<&State> for Vec<u8> {
     }
 }
 
-impl From<String> for State {
-    fn from(value: String) -> Self {
-        let x: Vec<&str> = value.split("###").collect();
-        let board: [char; 9] = x
-            .first()
-            .expect("No board data")
-            .split(',')
-            .map(|s| s.chars().next().unwrap_or(' '))
-            .collect::<Vec<char>>()
-            .try_into()
-            .expect("failed to convert to char");
-        let current_player: Player = x
-            .get(1)
-            .expect("no current player")
-            .chars()
-            .next()
-            .expect("cant parse char")
-            .into();
-        let round = x.get(2).map(|r| r.parse::<u8>().unwrap()).unwrap();
-        let active = x.get(3).map(|s| s.parse::<bool>().unwrap()).unwrap();
-        let winner = x.get(4).and_then(|s| s.chars().next()).and_then(|c| {
-            if c == ' ' {
-                return None;
-            }
-
-            Some(Player::from(c))
-        });
-
-        State {
-            board,
-            round,
-            active,
-            current_player,
-            winner,
-        }
-    }
-}
-
-impl fmt::Display for State {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let board = self
-            .board
-            .iter()
-            .map(|c| c.to_string())
-            .collect::<Vec<String>>()
-            .join(",");
-        let curr_player: char = (&self.current_player).into();
-        let winner = self.winner.as_ref().map(char::from).unwrap_or(' ');
-        write!(
-            f,
-            "{}###{}###{}###{}###{}",
-            board, curr_player, self.round, self.active, winner
-        )
-    }
-}
-
 #[derive(Debug, PartialEq)]
 pub enum Mode {
     Local,
+    VsComputer,
     Network,
+    /// Watching someone else's network game
+    Spectator,
+    /// Stepping through a saved game
+    Replay,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Player {
     X,
     O,
 }
 
 impl Player {
-    fn toggle(&self) -> Player {
+    pub fn toggle(&self) -> Player {
         match self {
             Player::O => Player::X,
             Player::X => Player::O,
@@ -444,6 +1826,7 @@ impl From<&Player> for char {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::input::Key;
 
     #[test]
     fn test_player_toggle() {
@@ -466,7 +1849,7 @@ mod tests {
 
     #[test]
     fn test_new_game_state() {
-        let game = Game::new(Mode::Local, false);
+        let game = Game::new(Mode::Local, false, Rules::default());
 
         // check default state
         assert_eq!(game.state.board, [' '; 9]);
@@ -480,7 +1863,8 @@ mod tests {
     fn test_win_conditions() {
         // horizontal win
         let mut state = State {
-            board: ['X', 'X', 'X', ' ', 'O', ' ', 'O', ' ', ' '],
+            board: vec!['X', 'X', 'X', ' ', 'O', ' ', 'O', ' ', ' '],
+            rules: Rules::default(),
             round: 5,
             active: true,
             current_player: Player::O,
@@ -492,7 +1876,8 @@ mod tests {
 
         // vertical win
         let mut state = State {
-            board: ['O', ' ', 'X', 'O', 'X', ' ', 'O', ' ', ' '],
+            board: vec!['O', ' ', 'X', 'O', 'X', ' ', 'O', ' ', ' '],
+            rules: Rules::default(),
             round: 5,
             active: true,
             current_player: Player::X,
@@ -503,7 +1888,8 @@ mod tests {
 
         // diagonal win
         let mut state = State {
-            board: ['X', 'O', ' ', 'O', 'X', ' ', ' ', ' ', 'X'],
+            board: vec!['X', 'O', ' ', 'O', 'X', ' ', ' ', ' ', 'X'],
+            rules: Rules::default(),
             round: 5,
             active: true,
             current_player: Player::O,
@@ -513,10 +1899,113 @@ mod tests {
         assert_eq!(state.winner, Some(Player::X));
     }
 
+    #[test]
+    fn test_win_conditions_larger_boards() {
+        // 4x4 board, four in a row needed, three is not enough
+        let mut state = State::new(Rules::new(4, 4).unwrap());
+        state.board[0] = 'X';
+        state.board[1] = 'X';
+        state.board[2] = 'X';
+        state.check_status();
+        assert_eq!(state.winner, None);
+        assert!(state.active);
+
+        state.board[3] = 'X';
+        state.check_status();
+        assert_eq!(state.winner, Some(Player::X));
+
+        // 5x5 board, three in a row on the anti-diagonal away from the corners
+        let mut state = State::new(Rules::new(5, 3).unwrap());
+        state.board[4] = 'O'; // (0, 4)
+        state.board[8] = 'O'; // (1, 3)
+        state.board[12] = 'O'; // (2, 2)
+        state.check_status();
+        assert_eq!(state.winner, Some(Player::O));
+        assert_eq!(state.winning_line(), Some(vec![4, 8, 12]));
+
+        // 15x15 gomoku, five in a column near the bottom edge
+        let mut state = State::new(Rules::new(15, 5).unwrap());
+        for row in 10..15 {
+            state.board[row * 15 + 7] = 'X';
+        }
+        state.check_status();
+        assert_eq!(state.winner, Some(Player::X));
+
+        // a run broken by the edge of the board should not wrap around
+        let mut state = State::new(Rules::new(4, 3).unwrap());
+        state.board[2] = 'O';
+        state.board[3] = 'O';
+        state.board[4] = 'O';
+        state.check_status();
+        assert_eq!(state.winner, None);
+    }
+
+    #[test]
+    fn test_winning_line_drawn() {
+        let mut game = Game::new(Mode::Local, true, Rules::default());
+        game.theme = Theme::MONOCHROME;
+        for (index, symbol) in [(0, 'O'), (3, 'X'), (4, 'O'), (5, 'X'), (8, 'O')] {
+            assert!(game.place(index, symbol));
+        }
+        assert_eq!(game.state.winning_line(), Some(vec![0, 4, 8]));
+        assert_eq!(game.winning_cells(), vec![0, 4, 8]);
+
+        // the winning marks blink, the cursor cell is reversed
+        let winning = game.winning_cells();
+        assert_eq!(game.styled(4, 'O', &winning), "\x1B[4m\x1B[5mO\x1B[0m");
+        assert_eq!(game.styled(3, 'X', &winning), "\x1B[1mX\x1B[0m");
+        game.cursor_pos = game.symbol_slots[2];
+        assert_eq!(game.styled(2, ' ', &winning), "\x1B[7m \x1B[0m");
+        game.free_cursor = true;
+        assert_eq!(game.styled(2, ' ', &winning), " ");
+
+        // nobody won yet
+        game.restart();
+        assert!(game.winning_cells().is_empty());
+
+        // ultimate: the lines inside the sub-boards that won the big board
+        let mut game = Game::new(Mode::Local, true, Rules::ultimate());
+        let ultimate = game.ultimate.as_mut().unwrap();
+        for board in [0, 4, 8] {
+            for cell in [0, 4, 8] {
+                ultimate.boards[board].board[cell] = 'O';
+            }
+        }
+        ultimate.check_status();
+        let ultimate = game.ultimate.as_ref().unwrap();
+        assert_eq!(ultimate.meta.winner, Some(Player::O));
+        let expected: Vec<usize> = [
+            (0, 0),
+            (0, 4),
+            (0, 8),
+            (4, 0),
+            (4, 4),
+            (4, 8),
+            (8, 0),
+            (8, 4),
+            (8, 8),
+        ]
+        .iter()
+        .map(|&(board, cell)| UltimateState::join(board, cell))
+        .collect();
+        assert_eq!(game.winning_cells(), expected);
+    }
+
+    #[test]
+    fn test_rules_validation() {
+        assert!(Rules::new(3, 3).is_ok());
+        assert!(Rules::new(15, 5).is_ok());
+        assert!(Rules::new(2, 2).is_err()); // board too small
+        assert!(Rules::new(20, 5).is_err()); // board too big
+        assert!(Rules::new(4, 5).is_err()); // can't fit the line on the board
+        assert!(Rules::new(5, 2).is_err()); // too easy
+    }
+
     #[test]
     fn test_draw_condition() {
         let mut state = State {
-            board: ['X', 'O', 'X', 'X', 'O', 'O', 'O', 'X', 'X'],
+            board: vec!['X', 'O', 'X', 'X', 'O', 'O', 'O', 'X', 'X'],
+            rules: Rules::default(),
             round: 9,
             active: true,
             current_player: Player::O,
@@ -529,7 +2018,7 @@ mod tests {
 
     #[test]
     fn test_attempt_placing() {
-        let mut game = Game::new(Mode::Local, false);
+        let mut game = Game::new(Mode::Local, false, Rules::default());
 
         // place X at position 0
         game.cursor_pos = game.symbol_slots[0];
@@ -548,10 +2037,40 @@ mod tests {
         assert_eq!(game.state.board[0], 'O'); // should still be O (already occupied)
     }
 
+    #[test]
+    fn test_vs_computer() {
+        let mut game = Game::vs_computer(Rules::default(), Difficulty::Perfect, 0);
+        assert_eq!(game.player, Player::O);
+
+        // human can't place the computers symbol
+        game.cursor_pos = game.symbol_slots[0];
+        game.state.current_player = Player::X;
+        game.attempt_placing('X');
+        assert_eq!(game.state.board[0], ' ');
+        game.state.current_player = Player::O;
+
+        // computer waits for the human to move first
+        game.play_computer_turn();
+        assert_eq!(game.state.round, 0);
+
+        game.attempt_placing('O');
+        assert_eq!(game.state.board[0], 'O');
+
+        game.play_computer_turn();
+        assert_eq!(game.state.round, 2);
+        assert_eq!(game.state.board.iter().filter(|c| **c == 'X').count(), 1);
+        assert_eq!(game.state.current_player, Player::O);
+
+        // computer can't move twice in a row
+        game.play_computer_turn();
+        assert_eq!(game.state.round, 2);
+    }
+
     #[test]
     fn test_serialization_deserialization() {
         let original_state = State {
-            board: ['X', 'O', ' ', ' ', 'X', ' ', 'O', ' ', ' '],
+            board: vec!['X', 'O', ' ', ' ', 'X', ' ', 'O', ' ', ' '],
+            rules: Rules::default(),
             round: 5,
             active: true,
             current_player: Player::X,
@@ -559,7 +2078,7 @@ mod tests {
         };
 
         let bytes: Vec<u8> = (&original_state).into();
-        assert_eq!(bytes.len(), 11); // should be exactly 11 bytes
+        assert_eq!(bytes.len(), 14); // 9 cells + 5 bytes of rules, round and flags
 
         let deserialized_state = State::try_from(bytes.as_slice()).expect("Failed to deserialize");
 
@@ -573,67 +2092,743 @@ mod tests {
         assert_eq!(deserialized_state.winner, original_state.winner);
     }
 
+    #[test]
+    fn test_serialization_larger_board() {
+        let mut original_state = State::new(Rules::new(15, 5).unwrap());
+        original_state.board[0] = 'O';
+        original_state.board[224] = 'X';
+        original_state.round = 300;
+
+        let bytes: Vec<u8> = (&original_state).into();
+        assert_eq!(bytes.len(), 225 + 5);
+
+        let deserialized_state = State::try_from(bytes.as_slice()).expect("Failed to deserialize");
+        assert_eq!(deserialized_state.rules, original_state.rules);
+        assert_eq!(deserialized_state.board, original_state.board);
+        assert_eq!(deserialized_state.round, 300);
+
+        // truncated board
+        assert!(State::try_from(&bytes[..100]).is_err());
+    }
+
     #[test]
     fn test_move_validation() {
-        let mut game = Game::new(Mode::Network, true); // Host is Player::O
+        let mut game = Game::new(Mode::Network, true, Rules::default()); // Host is Player::O
 
         // set up current state
-        game.state.board = ['X', ' ', ' ', ' ', 'O', ' ', ' ', ' ', ' '];
+        game.state.board = vec!['X', ' ', ' ', ' ', 'O', ' ', ' ', ' ', ' '];
         game.state.round = 2;
         game.state.current_player = Player::X;
 
         // valid move by Player::X
         let valid_state = State {
-            board: ['X', ' ', ' ', ' ', 'O', ' ', ' ', 'X', ' '], // X placed at position 7
+            board: vec!['X', ' ', ' ', ' ', 'O', ' ', ' ', 'X', ' '], // X placed at position 7
+            rules: Rules::default(),
             round: 3,
             active: true,
             current_player: Player::O,
             winner: None,
         };
 
-        assert!(game.validate(valid_state).is_ok());
+        assert!(game.validate(valid_state, Role::Player).is_ok());
 
         // invalid move, wrong players turn
         let invalid_state1 = State {
-            board: ['X', ' ', ' ', ' ', 'O', ' ', 'O', ' ', ' '], // O placed, but it's X's turn
+            board: vec!['X', ' ', ' ', ' ', 'O', ' ', 'O', ' ', ' '], // O placed, but it's X's turn
+            rules: Rules::default(),
             round: 3,
             active: true,
             current_player: Player::X,
             winner: None,
         };
 
-        assert!(game.validate(invalid_state1).is_err());
+        assert!(game.validate(invalid_state1, Role::Player).is_err());
 
         // invalid move, multiple changes
         let invalid_state2 = State {
-            board: ['X', 'X', ' ', ' ', 'O', ' ', ' ', 'X', ' '], // Two new X's placed
+            board: vec!['X', 'X', ' ', ' ', 'O', ' ', ' ', 'X', ' '], // Two new X's placed
+            rules: Rules::default(),
             round: 3,
             active: true,
             current_player: Player::O,
             winner: None,
         };
 
-        assert!(game.validate(invalid_state2).is_err());
+        assert!(game.validate(invalid_state2, Role::Player).is_err());
     }
 
     #[test]
-    fn test_string_conversion() {
-        let state = State {
-            board: ['X', 'O', ' ', ' ', 'X', ' ', 'O', ' ', ' '],
-            round: 5,
-            active: true,
-            current_player: Player::X,
-            winner: None,
+    fn test_validate_move() {
+        // referee copy, it plays neither side
+        let mut game = Game::new(Mode::Network, true, Rules::default());
+        let mv = |cell, player, round| Move {
+            cell,
+            player,
+            round,
+        };
+
+        assert!(game.validate_move(mv(4, Player::X, 0)).is_err());
+        assert!(game.validate_move(mv(9, Player::O, 0)).is_err());
+        assert!(game.validate_move(mv(4, Player::O, 1)).is_err());
+        assert!(game.validate_move(mv(4, Player::O, 0)).is_ok());
+        assert_eq!(game.state.board[4], 'O');
+        assert_eq!(game.state.current_player, Player::X);
+
+        // taken cell, and the same player twice in a row
+        assert!(game.validate_move(mv(4, Player::X, 1)).is_err());
+        assert!(game.validate_move(mv(0, Player::O, 1)).is_err());
+        assert!(game.validate_move(mv(0, Player::X, 1)).is_ok());
+
+        let mut ultimate = Game::new(Mode::Network, true, Rules::ultimate());
+        assert!(ultimate.validate_move(mv(40, Player::O, 0)).is_ok());
+        // sent to the middle board, cell 0 is in the top left one
+        assert!(ultimate.validate_move(mv(0, Player::X, 1)).is_err());
+        assert!(ultimate.validate_move(mv(30, Player::X, 1)).is_ok());
+        assert_eq!(ultimate.status().round, 2);
+    }
+
+    #[test]
+    fn test_validation_rejects_spectators() {
+        let mut game = Game::new(Mode::Network, true, Rules::default());
+        game.state.current_player = Player::X;
+
+        // a move that would be fine from the opponent
+        let mut watched = game.state.clone();
+        watched.board[0] = 'X';
+        watched.round = 1;
+        watched.current_player = Player::O;
+
+        let payload: Vec<u8> = (&watched).into();
+        assert!(game.validate_payload(&payload, Role::Spectator).is_err());
+        assert!(game.validate(watched.clone(), Role::Spectator).is_err());
+        assert_eq!(game.state.round, 0);
+
+        // even replaying the current state is refused
+        let current: Vec<u8> = (&game.state).into();
+        assert!(game.validate_payload(&current, Role::Spectator).is_err());
+
+        assert!(game.validate(watched, Role::Player).is_ok());
+    }
+
+    #[test]
+    fn test_spectator_can_not_place() {
+        let mut game = Game::new(Mode::Spectator, false, Rules::default());
+        game.attempt_placing('O');
+        game.attempt_placing('X');
+        assert_eq!(game.state.round, 0);
+
+        let mut host = Game::new(Mode::Network, true, Rules::default());
+        host.attempt_placing('O');
+        game.resume(&host.state_payload()).unwrap();
+        assert_eq!(game.state.board[0], 'O');
+    }
+
+    #[test]
+    fn test_validation_rejects_different_rules() {
+        let mut game = Game::new(Mode::Network, true, Rules::default());
+        game.state.current_player = Player::X;
+
+        let mut other_rules = State::new(Rules::new(4, 3).unwrap());
+        other_rules.board[0] = 'X';
+        other_rules.round = 1;
+        other_rules.current_player = Player::O;
+
+        assert!(game.validate(other_rules, Role::Player).is_err());
+    }
+
+    #[test]
+    fn test_symbol_slots_follow_board_size() {
+        let game = Game::new(Mode::Local, false, Rules::new(4, 4).unwrap());
+        assert_eq!(game.symbol_slots.len(), 16);
+        assert_eq!(game.symbol_slots[0], (3, 2));
+        assert_eq!(game.symbol_slots[3], (15, 2));
+        assert_eq!(game.symbol_slots[15], (15, 8));
+        assert_eq!(game.cursor_pos, game.symbol_slots[0]);
+    }
+
+    #[test]
+    fn test_ultimate_game() {
+        let mut game = Game::new(Mode::Local, false, Rules::ultimate());
+        assert_eq!(game.board_size(), 9);
+        assert_eq!(game.symbol_slots.len(), 81);
+
+        // center cell of the top left board sends X to the center board
+        game.cursor_pos = game.symbol_slots[10];
+        game.attempt_placing('O');
+        assert_eq!(game.status().round, 1);
+        assert_eq!(game.get_current_player(), &Player::X);
+
+        let ultimate = game.ultimate.as_ref().unwrap();
+        assert_eq!(ultimate.boards[0].board[4], 'O');
+        assert_eq!(ultimate.forced_board, Some(4));
+
+        // X tries to play outside the center board
+        game.cursor_pos = game.symbol_slots[0];
+        game.attempt_placing('X');
+        assert_eq!(game.status().round, 1);
+
+        game.restart();
+        assert_eq!(game.status().round, 0);
+        assert_eq!(game.ultimate.as_ref().unwrap().forced_board, None);
+    }
+
+    #[test]
+    fn test_ultimate_validation() {
+        let mut game = Game::new(Mode::Network, true, Rules::ultimate()); // Host is Player::O
+        game.cursor_pos = game.symbol_slots[10];
+        game.attempt_placing('O');
+
+        // X plays in the wrong sub-board
+        let mut invalid_state = game.ultimate.clone().unwrap();
+        invalid_state.boards[0].board[0] = 'X';
+        invalid_state.meta.round += 1;
+        invalid_state.meta.current_player = Player::O;
+        let payload: Vec<u8> = (&invalid_state).into();
+        assert!(game.validate_payload(&payload, Role::Player).is_err());
+
+        // X plays in the center board as it should
+        let mut valid_state = game.ultimate.clone().unwrap();
+        assert!(valid_state.place(UltimateState::join(4, 0), 'X'));
+        let payload: Vec<u8> = (&valid_state).into();
+        assert!(game.validate_payload(&payload, Role::Player).unwrap());
+        assert_eq!(game.ultimate.as_ref().unwrap().forced_board, Some(0));
+
+        // the same state again is not newer
+        assert!(!game.validate_payload(&payload, Role::Player).unwrap());
+    }
+
+    #[test]
+    fn test_apply_move() {
+        let mut host = Game::new(Mode::Network, true, Rules::default()); // Player::O
+        let mut joiner = Game::new(Mode::Network, false, Rules::default()); // Player::X
+
+        // host places and sends the move
+        host.cursor_pos = host.symbol_slots[4];
+        host.attempt_placing('O');
+        assert_eq!(host.last_move, Some(4));
+        let mv = Move {
+            cell: 4,
+            player: Player::O,
+            round: 0,
+        };
+
+        // the host can't apply its own move
+        assert!(host.apply_move(mv).is_err());
+
+        assert!(joiner.apply_move(mv).is_ok());
+        assert_eq!(joiner.state.board[4], 'O');
+        assert_eq!(joiner.state_hash(), host.state_hash());
+
+        // same move again is for a round that already passed
+        assert!(joiner.apply_move(mv).is_err());
+
+        // occupied cell
+        let occupied = Move {
+            cell: 4,
+            player: Player::X,
+            round: 1,
+        };
+        assert!(host.apply_move(occupied).is_err());
+
+        // outside the board
+        let outside = Move {
+            cell: 9,
+            player: Player::X,
+            round: 1,
+        };
+        assert!(host.apply_move(outside).is_err());
+        assert_eq!(host.state.round, 1);
+    }
+
+    #[test]
+    fn test_apply_move_ultimate() {
+        let mut joiner = Game::new(Mode::Network, false, Rules::ultimate());
+        let mv = Move {
+            cell: UltimateState::join(4, 2) as u16,
+            player: Player::O,
+            round: 0,
+        };
+        assert!(joiner.apply_move(mv).is_ok());
+
+        // joiner plays X in the top right board, host has to follow the send-to-board rule
+        let mut host = Game::new(Mode::Network, true, Rules::ultimate());
+        host.apply_move(mv).unwrap_err();
+        host.cursor_pos = host.symbol_slots[UltimateState::join(4, 2)];
+        host.attempt_placing('O');
+
+        let wrong_board = Move {
+            cell: UltimateState::join(5, 0) as u16,
+            player: Player::X,
+            round: 1,
+        };
+        assert!(host.apply_move(wrong_board).is_err());
+
+        let right_board = Move {
+            cell: UltimateState::join(2, 0) as u16,
+            player: Player::X,
+            round: 1,
+        };
+        assert!(host.apply_move(right_board).is_ok());
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut host = Game::new(Mode::Network, true, Rules::default());
+        host.cursor_pos = host.symbol_slots[4];
+        host.attempt_placing('O');
+
+        // joiner missed the move while disconnected
+        let mut joiner = Game::new(Mode::Network, false, Rules::default());
+        joiner.net_state = NetState::Resuming;
+        joiner.resume(&host.state_payload()).unwrap();
+        assert_eq!(joiner.state.board[4], 'O');
+        assert_eq!(joiner.net_state, NetState::Active);
+        assert_eq!(joiner.state_hash(), host.state_hash());
+
+        // state for another board size
+        let other = Game::new(Mode::Network, true, Rules::new(4, 4).unwrap());
+        assert!(joiner.resume(&other.state_payload()).is_err());
+    }
+
+    #[test]
+    fn test_no_placing_while_disconnected() {
+        let mut game = Game::new(Mode::Network, true, Rules::default());
+        game.net_state = NetState::Disconnected;
+        game.attempt_placing('O');
+        assert_eq!(game.state.round, 0);
+
+        game.sync_net_state();
+        game.attempt_placing('O');
+        assert_eq!(game.state.round, 1);
+    }
+
+    #[test]
+    fn test_series_result() {
+        let mut series = Series::new(Some(3));
+        series.record(Some(Player::O));
+        assert_eq!(series.result(), None);
+        series.record(Some(Player::O));
+        assert_eq!(series.result(), Some(Some(Player::O)));
+
+        // games after the match is decided don't count
+        series.record(Some(Player::X));
+        assert_eq!(series.x_wins, 0);
+
+        // draws leave all games played without a majority
+        let mut series = Series::new(Some(3));
+        series.record(None);
+        series.record(Some(Player::X));
+        series.record(None);
+        assert_eq!(series.result(), Some(Some(Player::X)));
+
+        let mut series = Series::new(Some(2));
+        series.record(Some(Player::X));
+        series.record(Some(Player::O));
+        assert_eq!(series.result(), Some(None));
+
+        // without a limit the match never ends
+        let mut series = Series::new(None);
+        for _ in 0..10 {
+            series.record(Some(Player::O));
+        }
+        assert_eq!(series.result(), None);
+    }
+
+    #[test]
+    fn test_series_serialization() {
+        let series = Series {
+            best_of: Some(5),
+            o_wins: 2,
+            x_wins: 1,
+            draws: 1,
+        };
+        let bytes: Vec<u8> = (&series).into();
+        assert_eq!(bytes, vec![5, 2, 1, 1]);
+        assert_eq!(Series::try_from(bytes.as_slice()).unwrap(), series);
+
+        let unlimited = Series::new(None);
+        let bytes: Vec<u8> = (&unlimited).into();
+        assert_eq!(Series::try_from(bytes.as_slice()).unwrap(), unlimited);
+
+        assert!(Series::try_from([5, 2, 1].as_slice()).is_err());
+    }
+
+    // plays the host's moves on both games until O has three in the top row
+    fn play_until_o_wins(host: &mut Game, joiner: &mut Game) {
+        let mut round = host.state.round;
+        let mut play = |game: &mut Game, other: &mut Game, cell: usize, player: Player| {
+            game.cursor_pos = game.symbol_slots[cell];
+            game.attempt_placing(char::from(&player));
+            other
+                .apply_move(Move {
+                    cell: cell as u16,
+                    player,
+                    round,
+                })
+                .unwrap();
+            round += 1;
+        };
+
+        let cells = match host.state.current_player {
+            Player::O => [(0, 'O'), (3, 'X'), (1, 'O'), (4, 'X'), (2, 'O')].to_vec(),
+            Player::X => [(8, 'X'), (0, 'O'), (7, 'X'), (1, 'O'), (3, 'X'), (2, 'O')].to_vec(),
+        };
+        for (cell, symbol) in cells {
+            match Player::from(symbol) {
+                Player::O => play(host, joiner, cell, Player::O),
+                Player::X => play(joiner, host, cell, Player::X),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rematch() {
+        let mut host = Game::new(Mode::Network, true, Rules::default());
+        let mut joiner = Game::new(Mode::Network, false, Rules::default());
+        host.series = Some(Series::new(Some(3)));
+        joiner.series = Some(Series::new(None));
+
+        // no rematch while the game is still going
+        host.restart();
+        assert_eq!(host.rematch, Rematch::None);
+
+        play_until_o_wins(&mut host, &mut joiner);
+        assert!(!host.status().active);
+        assert_eq!(host.series.as_ref().unwrap().o_wins, 1);
+        assert_eq!(joiner.series.as_ref().unwrap().o_wins, 1);
+        assert_eq!(
+            joiner.game_over_status("alice", "bob"),
+            "alice won, press r for a rematch"
+        );
+
+        // host asks, the joiner accepts
+        host.restart();
+        assert_eq!(host.rematch, Rematch::Requested);
+        joiner.rematch_requested();
+        assert_eq!(joiner.rematch, Rematch::Offered);
+        // the hint follows the config, without a key there is none
+        joiner.keys.bind(Action::Restart, &[Key::Char('s')]);
+        assert_eq!(
+            joiner.game_over_status("alice", "bob"),
+            "alice won, opponent wants a rematch, press s to accept"
+        );
+        joiner.keys.bind(Action::Restart, &[]);
+        assert_eq!(
+            joiner.game_over_status("alice", "bob"),
+            "alice won, opponent wants a rematch"
+        );
+        joiner.keys.bind(Action::Restart, &[Key::Char('r')]);
+        joiner.restart();
+        host.rematch_accepted();
+        assert_eq!(host.rematch, Rematch::None);
+        assert_eq!(joiner.rematch, Rematch::None);
+
+        // X starts the second game
+        assert!(host.status().active);
+        assert_eq!(host.state.current_player, Player::X);
+        assert_eq!(host.net_state, NetState::Waiting);
+        assert_eq!(joiner.net_state, NetState::Active);
+        assert_eq!(host.state_hash(), joiner.state_hash());
+
+        // O wins again and takes the best of three
+        play_until_o_wins(&mut host, &mut joiner);
+        assert_eq!(
+            host.series.as_ref().unwrap().result(),
+            Some(Some(Player::O))
+        );
+        host.restart();
+        assert_eq!(host.rematch, Rematch::None);
+    }
+
+    #[test]
+    fn test_rematch_asked_by_both() {
+        let mut host = Game::new(Mode::Network, true, Rules::ultimate());
+        let mut joiner = Game::new(Mode::Network, false, Rules::ultimate());
+        host.series = Some(Series::new(None));
+        joiner.series = Some(Series::new(None));
+        for game in [&mut host, &mut joiner] {
+            game.ultimate.as_mut().unwrap().meta.active = false;
+            game.series.as_mut().unwrap().record(None);
+        }
+
+        // both pressed r before hearing from the other
+        host.restart();
+        joiner.restart();
+        host.rematch_requested();
+        joiner.rematch_requested();
+
+        for game in [&host, &joiner] {
+            assert_eq!(game.rematch, Rematch::None);
+            assert!(game.status().active);
+            assert_eq!(game.status().current_player, Player::X);
+        }
+    }
+
+    #[test]
+    fn test_finished_game_counts_once() {
+        let mut game = Game::new(Mode::Local, false, Rules::default());
+        // O takes the top row while X plays below it
+        for cell in [0, 3, 1, 4, 2] {
+            game.check_state();
+            assert_eq!(game.finished, None);
+            assert!(game.place(cell, char::from(game.get_current_player())));
+        }
+        game.check_state();
+        assert_eq!(game.finished.take(), Some(Outcome::Won(Player::O)));
+
+        // taking the winning move back and playing it again is still the same game
+        game.undo();
+        game.check_state();
+        game.redo();
+        game.check_state();
+        assert_eq!(game.finished, None);
+
+        game.restart();
+        for cell in [0, 3, 1, 4, 6, 5] {
+            assert!(game.place(cell, char::from(game.get_current_player())));
+        }
+        game.check_state();
+        assert_eq!(game.finished, Some(Outcome::Won(Player::X)));
+
+        // spectators only watch
+        let mut spectator = Game::new(Mode::Spectator, false, Rules::default());
+        spectator.state.active = false;
+        spectator.check_state();
+        assert_eq!(spectator.finished, None);
+    }
+
+    #[test]
+    fn test_flag_fall_loses_the_game() {
+        use crate::clock::TimeControl;
+        use std::time::Duration;
+
+        let control = TimeControl {
+            base: Duration::from_millis(30),
+            increment: Duration::ZERO,
         };
+        let mut host = Game::new(Mode::Network, true, Rules::default());
+        let mut joiner = Game::new(Mode::Network, false, Rules::default());
+        host.series = Some(Series::new(None));
+        joiner.series = Some(Series::new(None));
+        host.clock = Some(Clock::new(control));
+
+        host.cursor_pos = host.symbol_slots[0];
+        host.attempt_placing('O');
+        host.clock
+            .as_mut()
+            .unwrap()
+            .advance(Duration::from_millis(50));
+
+        // only the host calls it
+        joiner.clock = Some(Clock::new(control));
+        joiner.clock.as_mut().unwrap().moved(Player::O);
+        joiner
+            .clock
+            .as_mut()
+            .unwrap()
+            .advance(Duration::from_millis(50));
+        joiner.check_clock();
+        assert!(joiner.status().active);
+
+        host.check_clock();
+        assert!(!host.status().active);
+        assert_eq!(host.winner(), Some(Player::O));
+        assert_eq!(host.series.as_ref().unwrap().o_wins, 1);
+
+        // no moves after the flag fell
+        host.apply_move(Move {
+            cell: 4,
+            player: Player::X,
+            round: 1,
+        })
+        .unwrap_err();
+
+        joiner.sync_clock(host.clock.clone().unwrap());
+        assert!(!joiner.status().active);
+        assert_eq!(joiner.winner(), Some(Player::O));
+        assert_eq!(joiner.series.as_ref().unwrap().o_wins, 0);
+
+        // the clock starts over with the next game
+        host.restart();
+        joiner.rematch_requested();
+        joiner.restart();
+        host.rematch_accepted();
+        assert!(host.status().active);
+        assert_eq!(host.clock.as_ref().unwrap().flagged, None);
+        assert_eq!(host.winner(), None);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut game = Game::new(Mode::Local, false, Rules::default());
+        for (cell, symbol) in [(4, 'O'), (0, 'X'), (8, 'O')] {
+            game.cursor_pos = game.symbol_slots[cell];
+            game.attempt_placing(symbol);
+        }
+
+        game.undo();
+        game.undo();
+        assert_eq!(
+            game.state.board,
+            vec![' ', ' ', ' ', ' ', 'O', ' ', ' ', ' ', ' ']
+        );
+        assert_eq!(game.state.round, 1);
+        assert_eq!(game.state.current_player, Player::X);
+        assert_eq!(game.last_move, Some(4));
+
+        game.redo();
+        assert_eq!(game.state.board[0], 'X');
+        assert_eq!(game.state.current_player, Player::O);
+
+        // a different move forgets the rest
+        game.cursor_pos = game.symbol_slots[2];
+        game.attempt_placing('O');
+        game.redo();
+        assert_eq!(game.state.board[8], ' ');
+        assert_eq!(game.state.round, 3);
+
+        for _ in 0..5 {
+            game.undo();
+        }
+        assert_eq!(game.state, State::new(Rules::default()));
+    }
+
+    #[test]
+    fn test_undo_against_the_computer() {
+        let mut game = Game::vs_computer(Rules::default(), Difficulty::Perfect, 0);
+        game.cursor_pos = game.symbol_slots[0];
+        game.attempt_placing('O');
+        game.play_computer_turn();
+        assert_eq!(game.state.round, 2);
+
+        // the computer's reply is taken back with the move
+        game.undo();
+        assert_eq!(game.state.round, 0);
+        assert_eq!(game.state.current_player, Player::O);
 
-        let state_str = state.to_string();
+        game.redo();
+        assert_eq!(game.state.round, 2);
+        assert_eq!(game.state.current_player, Player::O);
+    }
+
+    #[test]
+    fn test_undo_ultimate() {
+        let mut game = Game::new(Mode::Local, false, Rules::ultimate());
+        game.cursor_pos = game.symbol_slots[10];
+        game.attempt_placing('O');
+        game.cursor_pos = game.symbol_slots[UltimateState::join(4, 0)];
+        game.attempt_placing('X');
+        assert_eq!(game.ultimate.as_ref().unwrap().forced_board, Some(0));
+
+        // the board the undone move sent O to is forgotten as well
+        game.undo();
+        let ultimate = game.ultimate.as_ref().unwrap();
+        assert_eq!(ultimate.forced_board, Some(4));
+        assert_eq!(ultimate.cell(UltimateState::join(4, 0)), ' ');
+        assert_eq!(game.get_current_player(), &Player::X);
+    }
 
-        let reconstructed = State::from(state_str);
+    #[test]
+    fn test_takeback() {
+        let mut host = Game::new(Mode::Network, true, Rules::default());
+        let mut joiner = Game::new(Mode::Network, false, Rules::default());
+        host.takebacks = true;
+        joiner.takebacks = true;
+
+        host.cursor_pos = host.symbol_slots[4];
+        host.attempt_placing('O');
+        let mv = Move {
+            cell: 4,
+            player: Player::O,
+            round: 0,
+        };
+        joiner.apply_move(mv).unwrap();
+
+        // only the player who made the move can ask
+        joiner.undo();
+        assert_eq!(joiner.takeback, Takeback::None);
+
+        host.undo();
+        assert_eq!(host.takeback, Takeback::Requested);
+        // nothing changes until the joiner allows it
+        assert_eq!(host.state.board[4], 'O');
+
+        joiner.takeback_requested(mv);
+        assert_eq!(joiner.takeback, Takeback::Offered);
+        joiner.undo();
+        host.takeback_accepted(mv);
+
+        for game in [&host, &joiner] {
+            assert_eq!(game.takeback, Takeback::None);
+            assert_eq!(game.state.round, 0);
+            assert_eq!(game.state.board[4], ' ');
+        }
+        assert_eq!(host.net_state, NetState::Active);
+        assert_eq!(joiner.net_state, NetState::Waiting);
+        assert_eq!(host.state_hash(), joiner.state_hash());
+
+        // playing on turns the request down
+        host.attempt_placing('O');
+        joiner.apply_move(mv).unwrap();
+        host.undo();
+        joiner.takeback_requested(mv);
+        joiner.cursor_pos = joiner.symbol_slots[0];
+        joiner.attempt_placing('X');
+        assert_eq!(joiner.takeback, Takeback::None);
+        host.apply_move(Move {
+            cell: 0,
+            player: Player::X,
+            round: 1,
+        })
+        .unwrap();
+        assert_eq!(host.takeback, Takeback::None);
+        // allowing a takeback for a move that is no longer the latest does nothing
+        host.takeback_accepted(mv);
+        assert_eq!(host.state.round, 2);
+
+        // without support on both sides there is no asking at all
+        let mut solo = Game::new(Mode::Network, true, Rules::default());
+        solo.attempt_placing('O');
+        solo.undo();
+        assert_eq!(solo.takeback, Takeback::None);
+    }
 
-        assert_eq!(reconstructed.board, state.board);
-        assert_eq!(reconstructed.round, state.round);
-        assert_eq!(reconstructed.active, state.active);
-        assert_eq!(reconstructed.current_player, state.current_player);
-        assert_eq!(reconstructed.winner, state.winner);
+    #[test]
+    fn test_replay() {
+        let mut game = Game::new(Mode::Local, false, Rules::ultimate());
+        for (board, cell) in [(4, 0), (0, 4), (4, 8)] {
+            let symbol = char::from(game.get_current_player());
+            assert!(game.place(UltimateState::join(board, cell), symbol));
+        }
+        let record = Record::from_game(&game);
+        assert_eq!(record.moves.len(), 3);
+
+        let mut replay = Game::new(Mode::Replay, false, Rules::ultimate());
+        replay.replay = Some(Replay::new(record, std::time::Duration::from_secs(1)));
+        replay.show_replay();
+        assert_eq!(replay.status().round, 0);
+
+        replay.replay.as_mut().unwrap().forward();
+        replay.replay.as_mut().unwrap().forward();
+        replay.show_replay();
+        let ultimate = replay.ultimate.as_ref().unwrap();
+        assert_eq!(ultimate.cell(UltimateState::join(0, 4)), 'X');
+        assert_eq!(ultimate.cell(UltimateState::join(4, 8)), ' ');
+        assert_eq!(ultimate.forced_board, Some(4));
+        assert_eq!(replay.history.moves.len(), 2);
+        assert_eq!(replay.last_move, Some(UltimateState::join(0, 4)));
+        replay.theme = Theme::MONOCHROME;
+        assert_eq!(
+            replay.styled(UltimateState::join(0, 4), 'X', &[]),
+            "\x1B[1m\x1B[7mX\x1B[0m"
+        );
+        assert_eq!(replay.styled(UltimateState::join(4, 0), 'O', &[]), "O");
+
+        // read-only, nothing changes the record
+        replay.attempt_placing('O');
+        replay.undo();
+        replay.restart();
+        assert_eq!(replay.history.moves.len(), 2);
+        assert_eq!(replay.status().round, 2);
     }
 }
@@ -0,0 +1,194 @@
+use crate::{network::Move, terminal};
+
+/// Width of the move list pane, enough for "999. O s19"
+pub const PANE_WIDTH: u16 = 12;
+
+/// Moves of the current game in the order they were played
+#[derive(Debug, Default)]
+pub struct History {
+    pub moves: Vec<Move>,
+    /// Moves taken back that can be played again, most recent last
+    pub undone: Vec<Move>,
+    /// Rows the list is scrolled up from the latest move
+    pub scroll: usize,
+}
+
+impl History {
+    /// Adds a move that was just played. Playing the move that would be redone next
+    /// keeps the rest of the moves to redo, any other move forgets them.
+    pub fn record(&mut self, mv: Move) {
+        match self.undone.last() == Some(&mv) {
+            true => {
+                self.undone.pop();
+            }
+            false => self.undone.clear(),
+        }
+        self.moves.push(mv);
+        self.scroll = 0;
+    }
+
+    /// Takes back the latest move, it can be redone until another move is played
+    pub fn undo(&mut self) -> Option<Move> {
+        let mv = self.moves.pop()?;
+        self.undone.push(mv);
+        self.scroll = 0;
+        Some(mv)
+    }
+
+    /// The move `undo` took back last
+    pub fn next_redo(&self) -> Option<Move> {
+        self.undone.last().copied()
+    }
+
+    pub fn clear(&mut self) {
+        *self = History::default();
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = (self.scroll + 1).min(self.moves.len().saturating_sub(1));
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    /// Moves as "3. O b2" that fit in `height` rows, scrolled back by `scroll`
+    fn visible_rows(&self, board_size: u8, height: usize) -> Vec<String> {
+        let scroll = self.scroll.min(self.moves.len().saturating_sub(height));
+        let end = self.moves.len() - scroll;
+        let start = end.saturating_sub(height);
+
+        self.moves[start..end]
+            .iter()
+            .enumerate()
+            .map(|(i, mv)| {
+                format!(
+                    "{}. {} {}",
+                    start + i + 1,
+                    char::from(&mv.player),
+                    coordinate(mv.cell, board_size)
+                )
+            })
+            .collect()
+    }
+
+    /// Draws the list with its top left corner at `(x, y)`
+    pub fn draw(&self, (x, y): (u16, u16), height: u16, board_size: u8) {
+        if height < 2 {
+            return;
+        }
+
+        print!("{}Moves", terminal::Ansi::MoveCursor(x, y));
+        let rows = self.visible_rows(board_size, height as usize - 1);
+        for (i, row) in rows.iter().enumerate() {
+            print!("{}{}", terminal::Ansi::MoveCursor(x, y + 1 + i as u16), row);
+        }
+    }
+}
+
+/// Column letter from the left and row number from the top, "b2" is the center of a 3x3 board
+pub fn coordinate(cell: u16, board_size: u8) -> String {
+    let size = board_size as u16;
+    let column = (b'a' + (cell % size) as u8) as char;
+    format!("{}{}", column, cell / size + 1)
+}
+
+/// Cell of a coordinate like "b2", None if it is not on the board
+pub fn parse_coordinate(coordinate: &str, board_size: u8) -> Option<u16> {
+    let mut chars = coordinate.chars();
+    let column = chars.next().filter(char::is_ascii_lowercase)? as u16 - 'a' as u16;
+    let row = chars.as_str();
+    if !row.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let row = row.parse::<u16>().ok()?.checked_sub(1)?;
+
+    let size = board_size as u16;
+    (column < size && row < size).then_some(row * size + column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Player;
+
+    fn mv(cell: u16, round: u16) -> Move {
+        let player = match round % 2 {
+            0 => Player::O,
+            _ => Player::X,
+        };
+        Move {
+            cell,
+            player,
+            round,
+        }
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let mut history = History::default();
+        history.record(mv(4, 0));
+        history.record(mv(0, 1));
+        history.record(mv(8, 2));
+
+        assert_eq!(history.undo(), Some(mv(8, 2)));
+        assert_eq!(history.undo(), Some(mv(0, 1)));
+        assert_eq!(history.next_redo(), Some(mv(0, 1)));
+
+        // playing the move that was taken back keeps the rest
+        history.record(mv(0, 1));
+        assert_eq!(history.next_redo(), Some(mv(8, 2)));
+
+        // anything else forgets them
+        history.record(mv(2, 2));
+        assert_eq!(history.next_redo(), None);
+        assert_eq!(history.moves.len(), 3);
+
+        history.clear();
+        assert_eq!(history.undo(), None);
+    }
+
+    #[test]
+    fn test_visible_rows() {
+        let mut history = History::default();
+        for round in 0..5 {
+            history.record(mv(round, round));
+        }
+
+        assert_eq!(
+            history.visible_rows(3, 2),
+            vec!["4. X a2".to_string(), "5. O b2".to_string()]
+        );
+
+        history.scroll_up();
+        assert_eq!(history.visible_rows(3, 2)[0], "3. O c1");
+
+        // can't scroll past the first move
+        for _ in 0..10 {
+            history.scroll_up();
+        }
+        assert_eq!(history.visible_rows(3, 2)[0], "1. O a1");
+        assert_eq!(history.visible_rows(3, 10).len(), 5);
+
+        history.scroll_down();
+        history.record(mv(5, 5));
+        assert_eq!(history.visible_rows(3, 1), vec!["6. X c2".to_string()]);
+    }
+
+    #[test]
+    fn test_coordinate() {
+        assert_eq!(coordinate(0, 3), "a1");
+        assert_eq!(coordinate(4, 3), "b2");
+        assert_eq!(coordinate(8, 3), "c3");
+        assert_eq!(coordinate(80, 9), "i9");
+        assert_eq!(coordinate(360, 19), "s19");
+
+        for cell in [0, 4, 8] {
+            assert_eq!(parse_coordinate(&coordinate(cell, 3), 3), Some(cell));
+        }
+        assert_eq!(parse_coordinate("s19", 19), Some(360));
+        for outside in ["d1", "a4", "a0", "B2", "b", "2b", "", "b+2"] {
+            assert_eq!(parse_coordinate(outside, 3), None, "{}", outside);
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+
+/// Longest escape sequence kept around, anything longer is garbage and dropped
+const MAX_SEQUENCE_LEN: usize = 32;
+
+/// How long the rest of a sequence may take to arrive, a lone escape is the escape key
+const SEQUENCE_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// A key press or mouse click, decoded from the bytes the terminal sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    Char(char),
+    /// Ctrl and a letter, always lowercase
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// F1 to F12
+    F(u8),
+    /// Left button pressed at a screen position, columns and rows count from 1
+    Click(u16, u16),
+}
+
+// names of the keys that aren't a single character
+const NAMED_KEYS: [(&str, Key); 14] = [
+    ("space", Key::Char(' ')),
+    ("enter", Key::Enter),
+    ("tab", Key::Tab),
+    ("backspace", Key::Backspace),
+    ("esc", Key::Escape),
+    ("up", Key::Up),
+    ("down", Key::Down),
+    ("left", Key::Left),
+    ("right", Key::Right),
+    ("home", Key::Home),
+    ("end", Key::End),
+    ("insert", Key::Insert),
+    ("delete", Key::Delete),
+    ("pageup", Key::PageUp),
+];
+
+/// Names as used in the config file: a single character like `q`, `ctrl-r`,
+/// `f1` to `f12`, `pagedown`, or one of the names in `NAMED_KEYS`
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((name, _)) = NAMED_KEYS.iter().find(|(_, key)| key == self) {
+            return write!(f, "{}", name);
+        }
+        match self {
+            Key::Char(c) => write!(f, "{}", c),
+            Key::Ctrl(c) => write!(f, "ctrl-{}", c),
+            Key::PageDown => write!(f, "pagedown"),
+            Key::F(number) => write!(f, "f{}", number),
+            Key::Click(x, y) => write!(f, "click at {},{}", x, y),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return match c.is_control() {
+                true => Err(anyhow!("Write control keys like ctrl-r")),
+                false => Ok(Key::Char(c)),
+            };
+        }
+
+        let name = s.to_ascii_lowercase();
+        if let Some((_, key)) = NAMED_KEYS.iter().find(|(named, _)| *named == name) {
+            return Ok(*key);
+        }
+        if let Some(letter) = name.strip_prefix("ctrl-") {
+            return match letter.as_bytes() {
+                // the terminal sends these the same as enter, tab and backspace
+                [b'h' | b'i' | b'j' | b'm'] => {
+                    Err(anyhow!("{} can't be told apart from other keys", name))
+                }
+                // interrupt, suspend and flow control never reach the game
+                [b'c' | b'q' | b's' | b'z'] => Err(anyhow!("{} is taken by the terminal", name)),
+                [letter @ b'a'..=b'z'] => Ok(Key::Ctrl(char::from(*letter))),
+                _ => Err(anyhow!("Unknown key {}", s)),
+            };
+        }
+        match name.as_str() {
+            "escape" => Ok(Key::Escape),
+            "return" => Ok(Key::Enter),
+            "pagedown" => Ok(Key::PageDown),
+            _ => match name.strip_prefix('f').map(str::parse::<u8>) {
+                Some(Ok(number @ 1..=12)) => Ok(Key::F(number)),
+                _ => Err(anyhow!("Unknown key {}", s)),
+            },
+        }
+    }
+}
+
+/// Puts keys back together from the bytes of a raw mode terminal. Bytes of
+/// sequences that mean nothing here are swallowed whole. Keep one around for
+/// the whole game, a sequence can start in one read and end in the next.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    pending: Vec<u8>,
+    /// Skipping the rest of a sequence that got too long, was garbled or cut off
+    discarding: bool,
+}
+
+impl Decoder {
+    /// Whether a sequence was started and is waiting for more bytes
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty() || self.discarding
+    }
+
+    /// Takes the next byte, returns a key once one is complete
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        if self.discarding {
+            // parameter bytes go on until the final byte ends the sequence
+            self.discarding = (0x20..=0x3F).contains(&byte);
+            return None;
+        }
+        self.pending.push(byte);
+
+        let decoded = match self.pending.as_slice() {
+            [byte] => single(*byte),
+            [b'\x1B', b'\x1B'] => {
+                // escape pressed twice, the second one may start a sequence
+                self.pending = vec![b'\x1B'];
+                return Some(Key::Escape);
+            }
+            [b'\x1B', b'[' | b'O'] => Decoded::Incomplete,
+            // alt and a key, nothing uses those
+            [b'\x1B', _] => Decoded::Unknown,
+            [b'\x1B', b'O', byte] => Decoded::Key(ss3(*byte)),
+            [b'\x1B', b'[', rest @ ..] => csi(rest),
+            bytes => utf8(bytes),
+        };
+
+        match decoded {
+            Decoded::Incomplete if self.pending.len() < MAX_SEQUENCE_LEN => None,
+            Decoded::Incomplete => {
+                self.pending.clear();
+                self.discarding = true;
+                None
+            }
+            Decoded::Key(key) => {
+                self.pending.clear();
+                key
+            }
+            Decoded::Unknown => {
+                self.pending.clear();
+                None
+            }
+            Decoded::Aborted => {
+                self.pending.clear();
+                self.discarding = true;
+                None
+            }
+        }
+    }
+
+    /// No more bytes came in time. A lone escape was the escape key, anything
+    /// else half read is dropped. The rest of a cut off sequence may still be on
+    /// its way, it is skipped up to its final byte or until the next flush.
+    pub fn flush(&mut self) -> Option<Key> {
+        let pending = std::mem::take(&mut self.pending);
+        self.discarding = matches!(pending.as_slice(), [b'\x1B', b'[', ..]);
+        match pending.as_slice() {
+            [b'\x1B'] => Some(Key::Escape),
+            _ => None,
+        }
+    }
+
+    /// Decodes the key starting with `first`, waiting briefly for the rest of it
+    pub fn read(&mut self, first: u8, term_rx: &mpsc::Receiver<u8>) -> Option<Key> {
+        let mut key = self.feed(first);
+        while key.is_none() && !self.pending.is_empty() {
+            key = match term_rx.recv_timeout(SEQUENCE_TIMEOUT) {
+                Ok(byte) => self.feed(byte),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => self.flush(),
+            };
+        }
+        key
+    }
+}
+
+enum Decoded {
+    /// Part of a longer sequence, more bytes needed
+    Incomplete,
+    /// Done, None when the bytes are valid but mean no key
+    Key(Option<Key>),
+    Unknown,
+    /// Garbled partway, whatever is left of it is skipped as well
+    Aborted,
+}
+
+fn single(byte: u8) -> Decoded {
+    let key = match byte {
+        b'\x1B' => return Decoded::Incomplete,
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        // terminals send either for backspace
+        b'\x7F' | b'\x08' => Key::Backspace,
+        b'\x01'..=b'\x1A' => Key::Ctrl(char::from(byte - 1 + b'a')),
+        byte if byte.is_ascii_control() => return Decoded::Key(None),
+        byte if byte.is_ascii() => Key::Char(char::from(byte)),
+        // the first byte of a multi-byte character
+        0b1100_0000..=0b1111_0111 => return Decoded::Incomplete,
+        _ => return Decoded::Unknown,
+    };
+    Decoded::Key(Some(key))
+}
+
+// `ESC O` and one byte, how some terminals send F1 to F4 and the arrows
+fn ss3(byte: u8) -> Option<Key> {
+    match byte {
+        b'P'..=b'S' => Some(Key::F(byte - b'P' + 1)),
+        byte => letter_key(byte),
+    }
+}
+
+fn letter_key(byte: u8) -> Option<Key> {
+    match byte {
+        b'A' => Some(Key::Up),
+        b'B' => Some(Key::Down),
+        b'C' => Some(Key::Right),
+        b'D' => Some(Key::Left),
+        b'H' => Some(Key::Home),
+        b'F' => Some(Key::End),
+        _ => None,
+    }
+}
+
+// what follows `ESC [`: parameter bytes, then a single final byte
+fn csi(bytes: &[u8]) -> Decoded {
+    let Some((&last, params)) = bytes.split_last() else {
+        return Decoded::Incomplete;
+    };
+
+    match last {
+        0x20..=0x3F => return Decoded::Incomplete,
+        0x40..=0x7E => (),
+        _ => return Decoded::Unknown,
+    }
+    if params.iter().any(|byte| !(0x20..=0x3F).contains(byte)) {
+        return Decoded::Unknown;
+    }
+
+    // SGR mouse reports look like `<0;12;3M`, lowercase m for releases
+    if let [b'<', params @ ..] = params {
+        let garbled = params
+            .iter()
+            .any(|byte| !byte.is_ascii_digit() && *byte != b';');
+        if garbled || !matches!(last, b'M' | b'm') {
+            return Decoded::Aborted;
+        }
+        return Decoded::Key(click(params, last));
+    }
+
+    // modifiers like the 5 in `1;5A` (ctrl-up) are ignored
+    let params = std::str::from_utf8(params).unwrap_or_default();
+    let first = params.split(';').next().unwrap_or_default();
+    let key = match (last, first.parse::<u8>().ok()) {
+        (b'~', Some(1 | 7)) => Some(Key::Home),
+        (b'~', Some(4 | 8)) => Some(Key::End),
+        (b'~', Some(2)) => Some(Key::Insert),
+        (b'~', Some(3)) => Some(Key::Delete),
+        (b'~', Some(5)) => Some(Key::PageUp),
+        (b'~', Some(6)) => Some(Key::PageDown),
+        (b'~', Some(number @ 11..=15)) => Some(Key::F(number - 10)),
+        (b'~', Some(number @ 17..=21)) => Some(Key::F(number - 11)),
+        (b'~', Some(number @ 23..=24)) => Some(Key::F(number - 12)),
+        (b'~', _) => None,
+        (byte, _) => letter_key(byte),
+    };
+    Decoded::Key(key)
+}
+
+// only presses of the left button count, releases and the other buttons are dropped
+fn click(params: &[u8], last: u8) -> Option<Key> {
+    let params = std::str::from_utf8(params).ok()?;
+    let mut fields = params.split(';').map(|field| field.parse::<u16>().ok());
+    match (last, fields.next()?, fields.next()?, fields.next()?) {
+        (b'M', Some(0), Some(x), Some(y)) => Some(Key::Click(x, y)),
+        _ => None,
+    }
+}
+
+// multi-byte characters, complete once the lead byte's length is reached
+fn utf8(bytes: &[u8]) -> Decoded {
+    let len = match bytes[0] {
+        0b1100_0000..=0b1101_1111 => 2,
+        0b1110_0000..=0b1110_1111 => 3,
+        0b1111_0000..=0b1111_0111 => 4,
+        _ => return Decoded::Unknown,
+    };
+    if bytes[1..]
+        .iter()
+        .any(|byte| byte & 0b1100_0000 != 0b1000_0000)
+    {
+        return Decoded::Unknown;
+    }
+    if bytes.len() < len {
+        return Decoded::Incomplete;
+    }
+
+    match std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|text| text.chars().next())
+    {
+        Some(c) => Decoded::Key(Some(Key::Char(c))),
+        None => Decoded::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Vec<Key> {
+        let mut decoder = Decoder::default();
+        let mut keys: Vec<Key> = bytes.iter().filter_map(|b| decoder.feed(*b)).collect();
+        keys.extend(decoder.flush());
+        keys
+    }
+
+    #[test]
+    fn test_plain_keys() {
+        assert_eq!(
+            decode(b"q 1\r\t\x7F\x08"),
+            vec![
+                Key::Char('q'),
+                Key::Char(' '),
+                Key::Char('1'),
+                Key::Enter,
+                Key::Tab,
+                Key::Backspace,
+                Key::Backspace,
+            ]
+        );
+        assert_eq!(decode(b"\x12\x01"), vec![Key::Ctrl('r'), Key::Ctrl('a')]);
+        assert_eq!(
+            decode("é€".as_bytes()),
+            vec![Key::Char('é'), Key::Char('€')]
+        );
+        assert_eq!(decode(b"\x1B"), vec![Key::Escape]);
+        assert_eq!(decode(b"\x1B\x1B[A"), vec![Key::Escape, Key::Up]);
+    }
+
+    #[test]
+    fn test_sequences() {
+        let sequences: [(&[u8], Key); 16] = [
+            (b"\x1B[A", Key::Up),
+            (b"\x1B[B", Key::Down),
+            (b"\x1B[C", Key::Right),
+            (b"\x1B[D", Key::Left),
+            (b"\x1BOA", Key::Up),
+            (b"\x1B[1;5D", Key::Left),
+            (b"\x1B[H", Key::Home),
+            (b"\x1B[4~", Key::End),
+            (b"\x1B[2~", Key::Insert),
+            (b"\x1B[3~", Key::Delete),
+            (b"\x1B[5~", Key::PageUp),
+            (b"\x1B[6~", Key::PageDown),
+            (b"\x1BOP", Key::F(1)),
+            (b"\x1B[15~", Key::F(5)),
+            (b"\x1B[24~", Key::F(12)),
+            (b"\x1B[<0;12;3M", Key::Click(12, 3)),
+        ];
+        for (bytes, key) in sequences {
+            assert_eq!(decode(bytes), vec![key], "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn test_unknown_sequences_are_dropped() {
+        // nothing of these leaks out as characters, the keys around them survive
+        let unknown: [&[u8]; 8] = [
+            b"\x1B[<0;12;3m",
+            b"\x1B[<0;q;2M",
+            b"\x1B[<2;12;3M",
+            b"\x1B[99~",
+            b"\x1B[Z",
+            b"\x1Bx",
+            b"\x1B[12\x01",
+            b"\xC3x",
+        ];
+        for bytes in unknown {
+            let mut input = bytes.to_vec();
+            input.push(b'q');
+            assert_eq!(decode(&input), vec![Key::Char('q')], "{:?}", bytes);
+        }
+
+        // a sequence cut off is dropped when nothing more comes
+        assert_eq!(decode(b"\x1B[1;5"), vec![]);
+
+        // endless garbage doesn't grow without bound
+        let mut decoder = Decoder::default();
+        decoder.feed(b'\x1B');
+        decoder.feed(b'[');
+        for _ in 0..100 {
+            assert_eq!(decoder.feed(b'1'), None);
+        }
+        assert!(decoder.pending.len() < MAX_SEQUENCE_LEN);
+        assert_eq!(decoder.feed(b'~'), None);
+        assert_eq!(decoder.feed(b'q'), Some(Key::Char('q')));
+    }
+
+    #[test]
+    fn test_key_names() {
+        let keys = [
+            ("q", Key::Char('q')),
+            ("Q", Key::Char('Q')),
+            ("space", Key::Char(' ')),
+            ("enter", Key::Enter),
+            ("esc", Key::Escape),
+            ("pageup", Key::PageUp),
+            ("pagedown", Key::PageDown),
+            ("ctrl-r", Key::Ctrl('r')),
+            ("f12", Key::F(12)),
+        ];
+        for (name, key) in keys {
+            assert_eq!(name.parse::<Key>().unwrap(), key);
+            assert_eq!(key.to_string(), name);
+        }
+        assert_eq!("Escape".parse::<Key>().unwrap(), Key::Escape);
+        assert_eq!("return".parse::<Key>().unwrap(), Key::Enter);
+
+        for name in [
+            "", "ctrl-", "ctrl-m", "ctrl-c", "ctrl-1", "f13", "jump", "\t",
+        ] {
+            assert!(name.parse::<Key>().is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_read_waits_for_the_rest() {
+        let (tx, rx) = mpsc::channel();
+        let mut decoder = Decoder::default();
+
+        for byte in b"[5~x" {
+            tx.send(*byte).unwrap();
+        }
+        assert_eq!(decoder.read(b'\x1B', &rx), Some(Key::PageUp));
+        assert_eq!(rx.recv().unwrap(), b'x');
+
+        // nothing followed the escape
+        assert_eq!(decoder.read(b'\x1B', &rx), Some(Key::Escape));
+
+        // the second of two escapes starts the next key
+        for byte in b"\x1B[A" {
+            tx.send(*byte).unwrap();
+        }
+        assert_eq!(decoder.read(b'\x1B', &rx), Some(Key::Escape));
+        assert_eq!(decoder.read(rx.recv().unwrap(), &rx), Some(Key::Up));
+
+        // the rest of a mouse report that came too late is skipped
+        for byte in b"[<0;" {
+            tx.send(*byte).unwrap();
+        }
+        assert_eq!(decoder.read(b'\x1B', &rx), None);
+        assert!(decoder.is_pending());
+        let tail: Vec<Key> = b"12;3Mq".iter().filter_map(|b| decoder.feed(*b)).collect();
+        assert_eq!(tail, vec![Key::Char('q')]);
+
+        // unless the terminal went quiet in between
+        tx.send(b'[').unwrap();
+        assert_eq!(decoder.read(b'\x1B', &rx), None);
+        assert_eq!(decoder.flush(), None);
+        assert!(!decoder.is_pending());
+        assert_eq!(decoder.read(b'q', &rx), Some(Key::Char('q')));
+    }
+}
@@ -0,0 +1,1117 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, BufWriter},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    game::{Game, Mode, Player, Rules, Series},
+    network::{
+        describe_rules, read_stream, rules_from_bytes, rules_to_bytes, write_stream, Hello,
+        Message, MessageType, Move, FEATURE_CHAT, FEATURE_MOVES, FEATURE_REMATCH, FEATURE_ULTIMATE,
+        HANDSHAKE_TIMEOUT, HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT, MAX_NAME_LEN, RECONNECT_GRACE,
+    },
+    secure::TOKEN_LEN,
+};
+
+/// Longest game name in bytes
+pub const MAX_GAME_NAME_LEN: usize = 32;
+/// Most open games sent in one listing, the count has to fit in a byte
+const MAX_LISTED: usize = 255;
+/// What the server can referee, clocks and takebacks need a host playing along
+const REFEREED_FEATURES: u32 = FEATURE_MOVES | FEATURE_ULTIMATE | FEATURE_CHAT | FEATURE_REMATCH;
+
+/// Messages between players and the lobby server before a game starts, each one
+/// travels as the payload of a `MessageType::Lobby` message
+#[derive(Debug, Clone, PartialEq)]
+pub enum LobbyMessage {
+    /// Asks for the games waiting for an opponent
+    List,
+    /// Opens a game and waits for someone to join it
+    Create {
+        game: String,
+        name: String,
+        rules: Rules,
+        best_of: Option<u8>,
+    },
+    /// Joins an open game, or gets back into a game in progress after losing the connection
+    Join {
+        game: String,
+        name: String,
+        /// None plays whatever the game was created with
+        rules: Option<Rules>,
+    },
+    /// Answer to `List`
+    Games(Vec<OpenGame>),
+    /// Both players are in, the `Hello` handshake with the server follows
+    Start,
+    /// Request was turned down, with the reason why
+    Refused(String),
+}
+
+/// A game in the lobby waiting for an opponent
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenGame {
+    pub game: String,
+    /// Name of the player who created it
+    pub host: String,
+    pub rules: Rules,
+    pub best_of: Option<u8>,
+}
+
+/// Binary format:
+/// - Byte 0: Message kind (0 = list, 1 = create, 2 = join, 3 = games, 4 = start, 5 = refused)
+///
+/// Create (4 bytes + game name + player name):
+/// - Bytes 1-3: Rules, see `rules_to_bytes`
+/// - Byte 4: Games in the match, 0 for no limit
+///
+/// Join (3 bytes + game name + player name):
+/// - Bytes 1-3: Rules, board size 0 to play whatever the game was created with
+///
+/// Games (1 byte + 4 bytes, game name and host name per game):
+/// - Byte 1: Number of games
+/// - Per game: rules and games in the match like in create
+///
+/// Refused: remaining bytes are the reason (UTF-8)
+///
+/// Names are a length byte followed by that many bytes of UTF-8.
+impl TryFrom<&[u8]> for LobbyMessage {
+    fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
+        let Some((kind, mut rest)) = bytes.split_first() else {
+            return Err(anyhow!("Lobby message is empty"));
+        };
+
+        let msg = match kind {
+            0 => LobbyMessage::List,
+            1 => {
+                let rules = rules_from_bytes(take(&mut rest, 3)?)?
+                    .ok_or_else(|| anyhow!("A new game needs rules"))?;
+                let best_of = take(&mut rest, 1)?[0];
+                LobbyMessage::Create {
+                    game: take_string(&mut rest)?,
+                    name: take_string(&mut rest)?,
+                    rules,
+                    best_of: (best_of != 0).then_some(best_of),
+                }
+            }
+            2 => {
+                let rules = rules_from_bytes(take(&mut rest, 3)?)?;
+                LobbyMessage::Join {
+                    game: take_string(&mut rest)?,
+                    name: take_string(&mut rest)?,
+                    rules,
+                }
+            }
+            3 => {
+                let count = take(&mut rest, 1)?[0];
+                let mut games = Vec::new();
+                for _ in 0..count {
+                    let rules = rules_from_bytes(take(&mut rest, 3)?)?
+                        .ok_or_else(|| anyhow!("Open game is missing its rules"))?;
+                    let best_of = take(&mut rest, 1)?[0];
+                    games.push(OpenGame {
+                        game: take_string(&mut rest)?,
+                        host: take_string(&mut rest)?,
+                        rules,
+                        best_of: (best_of != 0).then_some(best_of),
+                    });
+                }
+                LobbyMessage::Games(games)
+            }
+            4 => LobbyMessage::Start,
+            5 => return Ok(LobbyMessage::Refused(String::from_utf8(rest.to_vec())?)),
+            _ => return Err(anyhow!("Invalid lobby message")),
+        };
+
+        if !rest.is_empty() {
+            return Err(anyhow!("Lobby message is longer than expected"));
+        }
+
+        Ok(msg)
+    }
+
+    type Error = anyhow::Error;
+}
+
+impl From<&LobbyMessage> for Vec<u8> {
+    fn from(msg: &LobbyMessage) -> Self {
+        match msg {
+            LobbyMessage::List => vec![0],
+            LobbyMessage::Create {
+                game,
+                name,
+                rules,
+                best_of,
+            } => {
+                let mut bytes = vec![1];
+                bytes.extend(rules_to_bytes(Some(*rules)));
+                bytes.push(best_of.unwrap_or(0));
+                push_string(&mut bytes, game);
+                push_string(&mut bytes, name);
+                bytes
+            }
+            LobbyMessage::Join { game, name, rules } => {
+                let mut bytes = vec![2];
+                bytes.extend(rules_to_bytes(*rules));
+                push_string(&mut bytes, game);
+                push_string(&mut bytes, name);
+                bytes
+            }
+            LobbyMessage::Games(games) => {
+                let mut bytes = vec![3, games.len().min(MAX_LISTED) as u8];
+                for open in games.iter().take(MAX_LISTED) {
+                    bytes.extend(rules_to_bytes(Some(open.rules)));
+                    bytes.push(open.best_of.unwrap_or(0));
+                    push_string(&mut bytes, &open.game);
+                    push_string(&mut bytes, &open.host);
+                }
+                bytes
+            }
+            LobbyMessage::Start => vec![4],
+            LobbyMessage::Refused(reason) => {
+                let mut bytes = vec![5];
+                bytes.extend(reason.as_bytes());
+                bytes
+            }
+        }
+    }
+}
+
+// splits `len` bytes off the front of `bytes`
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> anyhow::Result<&'a [u8]> {
+    if bytes.len() < len {
+        return Err(anyhow!("Lobby message is cut short"));
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn take_string(bytes: &mut &[u8]) -> anyhow::Result<String> {
+    let len = take(bytes, 1)?[0];
+    Ok(String::from_utf8(take(bytes, len as usize)?.to_vec())?)
+}
+
+// names are checked before they get here, anything longer is cut to fit the length byte
+fn push_string(bytes: &mut Vec<u8>, string: &str) {
+    let string = &string.as_bytes()[..string.len().min(u8::MAX as usize)];
+    bytes.push(string.len() as u8);
+    bytes.extend(string);
+}
+
+/// Sends `request` to the lobby server on the other end of `stream` and waits until the
+/// game starts, the `Hello` handshake with the server comes next. A created game waits
+/// for an opponent, pass None as `timeout` to wait for as long as that takes.
+pub fn enter(
+    stream: &TcpStream,
+    request: &LobbyMessage,
+    timeout: Option<Duration>,
+) -> anyhow::Result<()> {
+    stream.set_read_timeout(timeout)?;
+    send(stream, request)?;
+
+    match receive(stream)? {
+        LobbyMessage::Start => Ok(()),
+        LobbyMessage::Refused(reason) => Err(anyhow!(reason)),
+        _ => Err(anyhow!("Lobby server answered with something unexpected")),
+    }
+}
+
+/// Games on the lobby server at `server` that are waiting for an opponent
+pub fn list_games(server: &str) -> anyhow::Result<Vec<OpenGame>> {
+    let stream = TcpStream::connect(server)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    send(&stream, &LobbyMessage::List)?;
+
+    match receive(&stream)? {
+        LobbyMessage::Games(games) => Ok(games),
+        LobbyMessage::Refused(reason) => Err(anyhow!(reason)),
+        _ => Err(anyhow!("Lobby server answered with something unexpected")),
+    }
+}
+
+fn send(stream: &TcpStream, msg: &LobbyMessage) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(stream);
+    write_stream(
+        &mut writer,
+        Message::new(MessageType::Lobby, msg.into()).into(),
+    )
+}
+
+// unbuffered, whatever follows belongs to the handshake or the game
+fn receive(stream: &TcpStream) -> anyhow::Result<LobbyMessage> {
+    let mut reader = BufReader::with_capacity(0, stream);
+    let msg = read_stream(&mut reader)?;
+    if msg.message_type != MessageType::Lobby {
+        return Err(anyhow!("Not a tic-tac-term lobby server"));
+    }
+
+    LobbyMessage::try_from(msg.payload.as_slice())
+}
+
+// someone who connected to the lobby and said who they are
+struct Visitor {
+    stream: TcpStream,
+    name: String,
+}
+
+// a game name in the lobby and how far along that game is
+enum Entry {
+    Open {
+        host: Visitor,
+        rules: Rules,
+        best_of: Option<u8>,
+    },
+    /// Opponent arrived, the handshakes are under way
+    Starting,
+    Running {
+        session: mpsc::Sender<SessionEvent>,
+        names: [String; 2],
+        /// Ratings the players greeted with, passed on to their opponents
+        ratings: [Option<u16>; 2],
+        /// Tokens the players first greeted with, only they can take their seats again
+        tokens: [[u8; TOKEN_LEN]; 2],
+        /// What the server greets the players with, apart from the opponent's name and rating
+        hello: Hello,
+    },
+}
+
+type Lobby = Arc<Mutex<HashMap<String, Entry>>>;
+
+// a game that panicked while holding the lock shouldn't take the lobby down for everyone
+fn games(lobby: &Lobby) -> MutexGuard<'_, HashMap<String, Entry>> {
+    lobby.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs the lobby server on `listener` forever. Players list, create and join games,
+/// each game is played as its own session on the server. The server keeps the
+/// authoritative state and checks every move before passing it on, so neither
+/// player has to trust the other.
+pub fn serve(listener: TcpListener) -> anyhow::Result<()> {
+    let lobby = Lobby::default();
+
+    for stream in listener.incoming() {
+        // one misbehaving player shouldn't take the server down for everyone
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let lobby = lobby.clone();
+        thread::spawn(move || visit(stream, &lobby));
+    }
+
+    Ok(())
+}
+
+// answers a single request, for joins this goes on to run the game
+fn visit(stream: TcpStream, lobby: &Lobby) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let (game, name) = match receive(&stream)? {
+        LobbyMessage::List => return send(&stream, &LobbyMessage::Games(open_games(lobby))),
+        LobbyMessage::Create { game, name, .. } | LobbyMessage::Join { game, name, .. }
+            if game.is_empty() || game.len() > MAX_GAME_NAME_LEN || name.len() > MAX_NAME_LEN =>
+        {
+            let reason = format!(
+                "Game names are 1 to {} bytes and player names at most {}",
+                MAX_GAME_NAME_LEN, MAX_NAME_LEN
+            );
+            return send(&stream, &LobbyMessage::Refused(reason));
+        }
+        LobbyMessage::Create {
+            game,
+            name,
+            rules,
+            best_of,
+        } => {
+            let mut games = games(lobby);
+            match games.get(&game) {
+                Some(Entry::Running { .. }) => (game, name),
+                // only a host that gave up waiting makes room for a new one, whatever the name
+                Some(Entry::Open { host, .. }) if is_alive(&host.stream) => {
+                    let reason = format!("A game named '{}' already exists", game);
+                    return send(&stream, &LobbyMessage::Refused(reason));
+                }
+                Some(Entry::Starting) => {
+                    let reason = format!("A game named '{}' already exists", game);
+                    return send(&stream, &LobbyMessage::Refused(reason));
+                }
+                _ => {
+                    println!("game {}: created by {}", game, name);
+                    let host = Visitor { stream, name };
+                    games.insert(
+                        game,
+                        Entry::Open {
+                            host,
+                            rules,
+                            best_of,
+                        },
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        LobbyMessage::Join { game, name, rules } => {
+            let mut games = games(lobby);
+            match games.remove(&game) {
+                Some(Entry::Open {
+                    host,
+                    rules: game_rules,
+                    best_of,
+                }) if is_alive(&host.stream) => {
+                    if let Some(wanted) = rules.filter(|rules| *rules != game_rules) {
+                        games.insert(
+                            game,
+                            Entry::Open {
+                                host,
+                                rules: game_rules,
+                                best_of,
+                            },
+                        );
+                        let reason = format!(
+                            "Rules don't match, you want {} but the game is played with {}",
+                            describe_rules(&wanted),
+                            describe_rules(&game_rules)
+                        );
+                        return send(&stream, &LobbyMessage::Refused(reason));
+                    }
+
+                    games.insert(game.clone(), Entry::Starting);
+                    drop(games);
+
+                    println!("game {}: {} joined {}", game, name, host.name);
+                    let players = [host, Visitor { stream, name }];
+                    return start(lobby, &game, players, game_rules, best_of);
+                }
+                Some(entry @ Entry::Running { .. }) => {
+                    games.insert(game.clone(), entry);
+                    (game, name)
+                }
+                Some(Entry::Starting) => {
+                    games.insert(game.clone(), Entry::Starting);
+                    let reason = format!("Game '{}' already has two players", game);
+                    return send(&stream, &LobbyMessage::Refused(reason));
+                }
+                // a host that went away takes its game along
+                Some(Entry::Open { .. }) | None => {
+                    let reason = format!("No open game named '{}'", game);
+                    return send(&stream, &LobbyMessage::Refused(reason));
+                }
+            }
+        }
+        _ => return Err(anyhow!("Not a lobby request")),
+    };
+
+    rejoin(lobby, &game, Visitor { stream, name })
+}
+
+// games waiting for an opponent, sorted by name
+fn open_games(lobby: &Lobby) -> Vec<OpenGame> {
+    let mut games = games(lobby);
+    // hosts that gave up waiting are only noticed here and when someone joins
+    games.retain(|_, entry| match entry {
+        Entry::Open { host, .. } => is_alive(&host.stream),
+        _ => true,
+    });
+
+    let mut open: Vec<OpenGame> = games
+        .iter()
+        .filter_map(|(game, entry)| match entry {
+            Entry::Open {
+                host,
+                rules,
+                best_of,
+            } => Some(OpenGame {
+                game: game.clone(),
+                host: host.name.clone(),
+                rules: *rules,
+                best_of: *best_of,
+            }),
+            _ => None,
+        })
+        .collect();
+    open.sort_by(|a, b| a.game.cmp(&b.game));
+    open.truncate(MAX_LISTED);
+    open
+}
+
+// a waiting host hasn't sent anything, so a readable stream means it was closed
+fn is_alive(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut buffer = [0; 1];
+    let alive = match stream.peek(&mut buffer) {
+        Ok(0) => false,
+        Ok(_) => true,
+        Err(err) => err.kind() == io::ErrorKind::WouldBlock,
+    };
+    let _ = stream.set_nonblocking(false);
+    alive
+}
+
+// the server reads the player's hello before sending its own, so
+// what it sends can depend on what both players support
+fn read_hello(stream: &TcpStream) -> anyhow::Result<Hello> {
+    let mut reader = BufReader::with_capacity(0, stream);
+    let msg = read_stream(&mut reader)?;
+    if msg.message_type != MessageType::Hello {
+        return Err(anyhow!("Player did not greet"));
+    }
+
+    Hello::try_from(msg.payload.as_slice())
+}
+
+// the server greets each player in the name of their opponent
+fn write_hello(
+    stream: &TcpStream,
+    hello: &Hello,
+    opponent: &str,
+    rating: Option<u16>,
+) -> anyhow::Result<()> {
+    let hello = Hello {
+        name: opponent.to_string(),
+        rating,
+        ..hello.clone()
+    };
+    let mut writer = BufWriter::new(stream);
+    write_stream(
+        &mut writer,
+        Message::new(MessageType::Hello, (&hello).into()).into(),
+    )
+}
+
+// greets both players and runs the game until it is over
+fn start(
+    lobby: &Lobby,
+    game: &str,
+    players: [Visitor; 2],
+    rules: Rules,
+    best_of: Option<u8>,
+) -> anyhow::Result<()> {
+    let (hello, theirs) = match greet_both(&players, rules) {
+        Ok(greeted) => greeted,
+        Err(reason) => {
+            games(lobby).remove(game);
+            for player in &players {
+                let _ = player.stream.shutdown(Shutdown::Both);
+            }
+            return Err(reason);
+        }
+    };
+
+    let mut referee = Game::new(Mode::Network, true, rules);
+    if hello.features & FEATURE_REMATCH != 0 {
+        referee.series = Some(Series::new(best_of));
+    }
+
+    let (events_tx, events) = mpsc::channel();
+    let names = [players[0].name.clone(), players[1].name.clone()];
+    let ratings = [theirs[0].rating, theirs[1].rating];
+    let tokens = [theirs[0].token, theirs[1].token];
+    let mut session = Session {
+        game: referee,
+        seats: [
+            Seat::new(&names[0], Player::O),
+            Seat::new(&names[1], Player::X),
+        ],
+        events_tx: events_tx.clone(),
+        sent_series: None,
+    };
+    let [host, joiner] = players;
+    session.attach(0, host.stream)?;
+    session.attach(1, joiner.stream)?;
+
+    games(lobby).insert(
+        game.to_string(),
+        Entry::Running {
+            session: events_tx,
+            names,
+            ratings,
+            tokens,
+            hello,
+        },
+    );
+
+    session.run(events);
+
+    games(lobby).remove(game);
+    println!("game {}: over", game);
+    Ok(())
+}
+
+// both players get the same greeting, with only the features both of them support,
+// returns it along with how the players greeted the server
+fn greet_both(players: &[Visitor; 2], rules: Rules) -> anyhow::Result<(Hello, [Hello; 2])> {
+    for player in players {
+        player.stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        send(&player.stream, &LobbyMessage::Start)?;
+    }
+
+    let theirs = [
+        read_hello(&players[0].stream)?,
+        read_hello(&players[1].stream)?,
+    ];
+    let mut hello = Hello::new(Some(rules), "");
+    hello.features = REFEREED_FEATURES & theirs[0].features & theirs[1].features;
+
+    // sent even if something doesn't fit, the players then see what it is
+    let ratings = [theirs[0].rating, theirs[1].rating];
+    write_hello(&players[0].stream, &hello, &players[1].name, ratings[1])?;
+    write_hello(&players[1].stream, &hello, &players[0].name, ratings[0])?;
+
+    if hello.features & FEATURE_MOVES == 0 {
+        return Err(anyhow!("A player does not support move based network play"));
+    }
+    for theirs in &theirs {
+        hello.agree(theirs)?;
+    }
+
+    Ok((hello, theirs))
+}
+
+// a player coming back to a game in progress, greeted like the first time.
+// The seat goes to whoever greets with the token it was first taken with.
+fn rejoin(lobby: &Lobby, game: &str, player: Visitor) -> anyhow::Result<()> {
+    let (session, names, ratings, tokens, hello) = match games(lobby).get(game) {
+        Some(Entry::Running {
+            session,
+            names,
+            ratings,
+            tokens,
+            hello,
+        }) if names.contains(&player.name) => (
+            session.clone(),
+            names.clone(),
+            *ratings,
+            *tokens,
+            hello.clone(),
+        ),
+        _ => {
+            let reason = format!("Game '{}' already has two players", game);
+            return send(&player.stream, &LobbyMessage::Refused(reason));
+        }
+    };
+
+    send(&player.stream, &LobbyMessage::Start)?;
+    let theirs = read_hello(&player.stream)?;
+    let seat = (0..2).find(|seat| names[*seat] == player.name && tokens[*seat] == theirs.token);
+    let opponent = match seat {
+        Some(seat) => 1 - seat,
+        None => match names[0] == player.name {
+            true => 1,
+            false => 0,
+        },
+    };
+    write_hello(&player.stream, &hello, &names[opponent], ratings[opponent])?;
+    hello.agree(&theirs)?;
+
+    // someone else under a player's name is told the game is over for them
+    let Some(seat) = seat else {
+        let mut writer = BufWriter::new(&player.stream);
+        let goodbye = Message::new(MessageType::Goodbye, Vec::new());
+        let _ = write_stream(&mut writer, goodbye.into());
+        let _ = player.stream.shutdown(Shutdown::Both);
+        return Ok(());
+    };
+
+    session.send(SessionEvent::Rejoined(seat, player.stream))?;
+    Ok(())
+}
+
+// what the reader threads and returning players tell a running game
+enum SessionEvent {
+    Received(usize, u64, Message),
+    ReadFailed(usize, u64),
+    /// Player is back in their seat and greeted
+    Rejoined(usize, TcpStream),
+}
+
+// one of the two players of a game, the creator has seat 0 and plays O
+struct Seat {
+    name: String,
+    player: Player,
+    writer: Option<BufWriter<TcpStream>>,
+    /// Connection generation, like in `Link` a stale reader can't report on a newer connection
+    generation: u64,
+    last_received: Instant,
+    last_sent: Instant,
+    lost_at: Option<Instant>,
+    /// The player coming back while their old connection still looks alive, takes over once it isn't
+    rejoining: Option<TcpStream>,
+    wants_rematch: bool,
+}
+
+impl Seat {
+    fn new(name: &str, player: Player) -> Self {
+        Seat {
+            name: name.to_string(),
+            player,
+            writer: None,
+            generation: 0,
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+            lost_at: None,
+            rejoining: None,
+            wants_rematch: false,
+        }
+    }
+
+    fn lose(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            // also wakes up the reader thread
+            let _ = writer.get_ref().shutdown(Shutdown::Both);
+        }
+        self.generation += 1;
+        self.lost_at.get_or_insert_with(Instant::now);
+    }
+}
+
+// a game between two players with the server as referee
+struct Session {
+    game: Game,
+    seats: [Seat; 2],
+    events_tx: mpsc::Sender<SessionEvent>,
+    /// Score the players were told about last
+    sent_series: Option<Series>,
+}
+
+impl Session {
+    // start reading from a player's fresh connection
+    fn attach(&mut self, seat: usize, stream: TcpStream) -> anyhow::Result<()> {
+        stream.set_read_timeout(None)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        self.seats[seat].lose();
+        let generation = self.seats[seat].generation;
+        let events_tx = self.events_tx.clone();
+        thread::spawn(move || loop {
+            match read_stream(&mut reader) {
+                Ok(incoming) => {
+                    if events_tx
+                        .send(SessionEvent::Received(seat, generation, incoming))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(_) => {
+                    let _ = events_tx.send(SessionEvent::ReadFailed(seat, generation));
+                    break;
+                }
+            }
+        });
+
+        let seat = &mut self.seats[seat];
+        seat.writer = Some(BufWriter::new(stream));
+        seat.last_received = Instant::now();
+        seat.last_sent = Instant::now();
+        seat.lost_at = None;
+        Ok(())
+    }
+
+    // a player who already came back takes over right away
+    fn lose(&mut self, seat: usize) {
+        self.seats[seat].lose();
+        if let Some(stream) = self.seats[seat].rejoining.take() {
+            if self.attach(seat, stream).is_ok() {
+                self.sync(seat);
+            }
+        }
+    }
+
+    // a live connection is never replaced, the newcomer waits until the old one drops
+    fn rejoin(&mut self, seat: usize, stream: TcpStream) {
+        if self.seats[seat].writer.is_some() {
+            if let Some(waiting) = self.seats[seat].rejoining.replace(stream) {
+                let _ = waiting.shutdown(Shutdown::Both);
+            }
+        } else if self.attach(seat, stream).is_ok() {
+            self.sync(seat);
+        }
+    }
+
+    // dropped while the player is away, they get the whole state when they are back
+    fn send(&mut self, seat_index: usize, msg: Message) {
+        let seat = &mut self.seats[seat_index];
+        let Some(writer) = &mut seat.writer else {
+            return;
+        };
+
+        seat.last_sent = Instant::now();
+        if write_stream(writer, msg.into()).is_err() {
+            self.lose(seat_index);
+        }
+    }
+
+    // the server's state is authoritative, players start and resume from it
+    fn sync(&mut self, seat: usize) {
+        self.send(
+            seat,
+            Message::new(MessageType::Payload, self.game.state_payload()),
+        );
+        if let Some(series) = &self.game.series {
+            self.send(seat, Message::new(MessageType::Score, series.into()));
+        }
+    }
+
+    fn run(&mut self, events: mpsc::Receiver<SessionEvent>) {
+        self.sync(0);
+        self.sync(1);
+        self.sent_series = self.game.series.clone();
+
+        loop {
+            for seat in 0..2 {
+                if self.seats[seat].writer.is_none() {
+                    continue;
+                }
+                if self.seats[seat].last_received.elapsed() >= HEARTBEAT_TIMEOUT {
+                    self.lose(seat);
+                } else if self.seats[seat].last_sent.elapsed() >= HEARTBEAT_INTERVAL {
+                    self.send(seat, Message::new(MessageType::Ping, Vec::new()));
+                }
+            }
+
+            let gone = self.seats.iter().any(|seat| {
+                seat.lost_at
+                    .is_some_and(|lost_at| lost_at.elapsed() >= RECONNECT_GRACE)
+            });
+            if gone {
+                self.end();
+                return;
+            }
+
+            let Ok(event) = events.recv_timeout(Duration::from_millis(100)) else {
+                continue;
+            };
+
+            match event {
+                SessionEvent::Received(seat, generation, incoming)
+                    if generation == self.seats[seat].generation =>
+                {
+                    self.seats[seat].last_received = Instant::now();
+                    if !self.handle(seat, incoming) {
+                        self.end();
+                        return;
+                    }
+                }
+                SessionEvent::ReadFailed(seat, generation)
+                    if generation == self.seats[seat].generation =>
+                {
+                    self.lose(seat);
+                }
+                SessionEvent::Rejoined(seat, stream) => self.rejoin(seat, stream),
+                _ => (),
+            }
+
+            if self.game.series != self.sent_series {
+                if let Some(series) = &self.game.series {
+                    let score = Message::new(MessageType::Score, series.into());
+                    self.send(0, score.clone());
+                    self.send(1, score);
+                }
+                self.sent_series = self.game.series.clone();
+            }
+        }
+    }
+
+    // returns false once the game is over for good
+    fn handle(&mut self, seat: usize, incoming: Message) -> bool {
+        let other = 1 - seat;
+
+        match incoming.message_type {
+            MessageType::Move => {
+                let result = Move::try_from(incoming.payload.as_slice())
+                    .and_then(|mv| self.referee(seat, mv));
+                let reply = Message::reply(result.is_ok(), self.game.state_hash());
+                self.send(seat, reply);
+                match result {
+                    Ok(_) => self.send(other, incoming),
+                    Err(_) => self.sync(seat),
+                }
+            }
+            // a player that got out of sync starts over from the server's state,
+            // one that doesn't know yet is told so first
+            MessageType::Accepted | MessageType::Rejected => {
+                let in_sync = incoming.message_type == MessageType::Accepted
+                    && incoming.state_hash().ok() == Some(self.game.state_hash());
+                if !in_sync {
+                    if incoming.message_type == MessageType::Accepted {
+                        self.send(seat, Message::reply(false, self.game.state_hash()));
+                    }
+                    self.sync(seat);
+                }
+            }
+            // overlong or garbled lines are dropped
+            MessageType::Chat if incoming.chat_text().is_ok() => self.send(other, incoming),
+            MessageType::RematchRequest | MessageType::RematchAccept => {
+                self.rematch(seat, incoming)
+            }
+            MessageType::Goodbye => {
+                self.send(other, incoming);
+                return false;
+            }
+            // pings only keep the connection alive
+            _ => (),
+        }
+
+        true
+    }
+
+    fn referee(&mut self, seat: usize, mv: Move) -> anyhow::Result<()> {
+        if mv.player != self.seats[seat].player {
+            return Err(anyhow!(
+                "{} plays {:?}",
+                self.seats[seat].name,
+                self.seats[seat].player
+            ));
+        }
+
+        self.game.validate_move(mv)?;
+
+        let status = self.game.status();
+        if !status.active {
+            let winner = status.winner;
+            if let Some(series) = &mut self.game.series {
+                series.record(winner);
+            }
+        }
+
+        Ok(())
+    }
+
+    // passed on like between two players, the server starts its next game
+    // once both asked for it, just like both players do
+    fn rematch(&mut self, seat: usize, incoming: Message) {
+        let Some(series) = &self.game.series else {
+            return;
+        };
+        if self.game.status().active || series.result().is_some() {
+            return;
+        }
+
+        self.seats[seat].wants_rematch = true;
+        self.send(1 - seat, incoming);
+
+        if self.seats.iter().all(|seat| seat.wants_rematch) {
+            self.game.next_game();
+            for seat in &mut self.seats {
+                seat.wants_rematch = false;
+            }
+        }
+    }
+
+    // whoever is still there hears that the game is over
+    fn end(&mut self) {
+        for seat in 0..2 {
+            if let Some(waiting) = self.seats[seat].rejoining.take() {
+                let _ = waiting.shutdown(Shutdown::Both);
+            }
+            self.send(seat, Message::new(MessageType::Goodbye, Vec::new()));
+            self.seats[seat].lose();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{Link, NetEvent};
+
+    #[test]
+    fn test_message_conversions() {
+        let create = LobbyMessage::Create {
+            game: String::from("pizza"),
+            name: String::from("alice"),
+            rules: Rules::default(),
+            best_of: Some(3),
+        };
+        let bytes: Vec<u8> = (&create).into();
+        assert_eq!(
+            bytes,
+            [&[1, 3, 3, 0, 3, 5][..], b"pizza", &[5], b"alice"].concat()
+        );
+        assert_eq!(LobbyMessage::try_from(bytes.as_slice()).unwrap(), create);
+
+        let join = LobbyMessage::Join {
+            game: String::from("pizza"),
+            name: String::from("bob"),
+            rules: None,
+        };
+        let bytes: Vec<u8> = (&join).into();
+        assert_eq!(LobbyMessage::try_from(bytes.as_slice()).unwrap(), join);
+
+        let games = LobbyMessage::Games(vec![
+            OpenGame {
+                game: String::from("pizza"),
+                host: String::from("alice"),
+                rules: Rules::ultimate(),
+                best_of: None,
+            },
+            OpenGame {
+                game: String::from("pasta"),
+                host: String::from("carol"),
+                rules: Rules::new(15, 5).unwrap(),
+                best_of: Some(5),
+            },
+        ]);
+        let bytes: Vec<u8> = (&games).into();
+        assert_eq!(LobbyMessage::try_from(bytes.as_slice()).unwrap(), games);
+
+        for msg in [
+            LobbyMessage::List,
+            LobbyMessage::Start,
+            LobbyMessage::Refused(String::from("go away")),
+        ] {
+            let bytes: Vec<u8> = (&msg).into();
+            assert_eq!(LobbyMessage::try_from(bytes.as_slice()).unwrap(), msg);
+        }
+
+        // name longer than its bytes, trailing bytes and a created game without rules
+        assert!(LobbyMessage::try_from([2, 0, 0, 0, 3, b'a'].as_slice()).is_err());
+        assert!(LobbyMessage::try_from([0, 0].as_slice()).is_err());
+        assert!(LobbyMessage::try_from([1, 0, 0, 0, 0, 1, b'a', 0].as_slice()).is_err());
+        assert!(LobbyMessage::try_from([9].as_slice()).is_err());
+    }
+
+    // waits for the next message the server sends, pings aside
+    fn receive_message(link: &mut Link) -> Message {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(NetEvent::Received(msg)) = link.poll(Duration::from_millis(50)) {
+                return msg;
+            }
+        }
+        panic!("nothing arrived from the lobby server");
+    }
+
+    // a rejected player gets the server's board and score to start over from
+    fn assert_resynced(link: &mut Link) {
+        assert_eq!(receive_message(link).message_type, MessageType::Rejected);
+        assert_eq!(receive_message(link).message_type, MessageType::Payload);
+        assert_eq!(receive_message(link).message_type, MessageType::Score);
+    }
+
+    #[test]
+    fn test_game_through_lobby() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(listener));
+
+        let host_server = server.clone();
+        let host_thread = thread::spawn(move || {
+            let mut hello = Hello::new(Some(Rules::default()), "alice");
+            hello.rating = Some(1300);
+            Link::lobby(&host_server, "pizza", true, Some(3), hello).unwrap()
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while list_games(&server).unwrap().is_empty() {
+            assert!(Instant::now() < deadline, "game never showed up");
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(
+            list_games(&server).unwrap(),
+            vec![OpenGame {
+                game: String::from("pizza"),
+                host: String::from("alice"),
+                rules: Rules::default(),
+                best_of: Some(3),
+            }]
+        );
+
+        // wrong name, rules that don't match and a game that is already taken
+        let bob = Hello::new(None, "bob");
+        assert!(Link::lobby(&server, "pasta", false, None, bob.clone()).is_err());
+        let picky = Hello::new(Some(Rules::new(4, 4).unwrap()), "bob");
+        assert!(Link::lobby(&server, "pizza", false, None, picky).is_err());
+        let hello = Hello::new(Some(Rules::default()), "mallory");
+        assert!(Link::lobby(&server, "pizza", true, None, hello).is_err());
+        // not even under the waiting host's name
+        let hello = Hello::new(Some(Rules::default()), "alice");
+        assert!(Link::lobby(&server, "pizza", true, None, hello).is_err());
+
+        let (mut joiner, host_hello) = Link::lobby(&server, "pizza", false, None, bob).unwrap();
+        let (mut host, joiner_hello) = host_thread.join().unwrap();
+        assert_eq!(host_hello.name, "alice");
+        assert_eq!(joiner_hello.name, "bob");
+        // ratings are passed on like the names
+        assert_eq!(host_hello.rating, Some(1300));
+        assert_eq!(joiner_hello.rating, None);
+        assert!(list_games(&server).unwrap().is_empty());
+
+        // both start from the server's board and score
+        for link in [&mut host, &mut joiner] {
+            assert_eq!(receive_message(link).message_type, MessageType::Payload);
+            let score = receive_message(link);
+            assert_eq!(score.message_type, MessageType::Score);
+            assert_eq!(
+                Series::try_from(score.payload.as_slice()).unwrap().best_of,
+                Some(3)
+            );
+        }
+
+        // a move out of turn never reaches the host
+        let cheat = Move {
+            cell: 4,
+            player: Player::X,
+            round: 0,
+        };
+        joiner.send(Message::new(MessageType::Move, cheat.into()));
+        assert_resynced(&mut joiner);
+
+        let mv = Move {
+            cell: 4,
+            player: Player::O,
+            round: 0,
+        };
+        host.send(Message::new(MessageType::Move, mv.into()));
+        assert_eq!(
+            receive_message(&mut host).message_type,
+            MessageType::Accepted
+        );
+        let forwarded = receive_message(&mut joiner);
+        assert_eq!(Move::try_from(forwarded.payload.as_slice()).unwrap(), mv);
+
+        // a player whose board turned out different after the move
+        joiner.send(Message::reply(true, 0));
+        assert_resynced(&mut joiner);
+
+        // the same cell again is refused as well
+        let taken = Move {
+            cell: 4,
+            player: Player::X,
+            round: 1,
+        };
+        joiner.send(Message::new(MessageType::Move, taken.into()));
+        assert_resynced(&mut joiner);
+
+        // someone else under the joiner's name doesn't get its seat
+        let impostor = Hello::new(None, "bob");
+        let (mut impostor, _) = Link::lobby(&server, "pizza", false, None, impostor).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !matches!(
+            impostor.poll(Duration::from_millis(50)),
+            Some(NetEvent::Closed)
+        ) {
+            assert!(Instant::now() < deadline, "impostor was never turned away");
+        }
+
+        joiner.send(Message::chat("good luck").unwrap());
+        assert_eq!(receive_message(&mut host).chat_text().unwrap(), "good luck");
+
+        joiner.goodbye();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            assert!(Instant::now() < deadline, "host never heard the goodbye");
+            if let Some(NetEvent::Closed) = host.poll(Duration::from_millis(50)) {
+                break;
+            }
+        }
+    }
+}
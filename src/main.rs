@@ -1,106 +1,589 @@
-use game::{Game, Mode, State};
-use network::{Message, MessageType, NetState};
+use anyhow::anyhow;
+use chat::Chat;
+use cli::{Action, Args};
+use clock::Clock;
+use config::Config;
+use game::{Game, Mode, Player, Rematch, Series, Takeback};
+use network::{
+    Hello, Link, Message, MessageType, Move, NetEvent, NetState, Role, FEATURE_CHAT, FEATURE_CLOCK,
+    FEATURE_REMATCH, FEATURE_TAKEBACK,
+};
+use record::{Outcome, Record};
+use replay::Replay;
+use secure::Secret;
+use stats::{GameResult, Stats};
 use std::{
-    env,
+    env, fs,
     io::{self, Read},
+    net::{TcpListener, UdpSocket},
+    path::Path,
+    process,
     sync::mpsc,
     thread, time,
 };
 
+mod ai;
+mod chat;
+mod cli;
+mod clock;
+mod config;
 mod game;
+mod history;
+mod input;
+mod lobby;
 mod network;
+mod rating;
+mod record;
+mod rendezvous;
+mod replay;
+mod rng;
+mod secure;
+mod stats;
 mod terminal;
+mod theme;
+mod udp;
+mod ultimate;
 
 fn main() -> anyhow::Result<()> {
-    let (game_mode, addr, is_host) = parse_args();
-    terminal::init();
+    let Args {
+        mode: game_mode,
+        addr,
+        is_host,
+        mut rules,
+        rules_given,
+        difficulty,
+        seed,
+        name,
+        transport,
+        loss,
+        rendezvous,
+        serve_rendezvous,
+        secret,
+        best_of,
+        clock,
+        save,
+        load,
+        delay,
+        theme,
+        lobby,
+        serve_lobby,
+        list_games,
+        show_stats,
+    } = match cli::parse(env::args().skip(1).collect()) {
+        Ok(Action::Run(args)) => *args,
+        Ok(Action::Help(help)) => {
+            print!("{}", help);
+            return Ok(());
+        }
+        Ok(Action::Version) => {
+            println!("tic-tac-term {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Err(reason) => {
+            eprintln!("tic-tac-term: {}", reason);
+            eprintln!("Run tic-tac-term --help to see what it takes");
+            process::exit(2);
+        }
+    };
+
+    let stats_path = stats::path();
+    if show_stats {
+        let path = stats_path
+            .ok_or_else(|| anyhow!("Set XDG_DATA_HOME or HOME to keep stats between games"))?;
+        print!("{}", Stats::load(&path)?.summary());
+        return Ok(());
+    }
+
+    if serve_rendezvous {
+        let socket = UdpSocket::bind(&addr)?;
+        println!("rendezvous server listening on {}", socket.local_addr()?);
+        return rendezvous::serve(socket);
+    }
+
+    if serve_lobby {
+        let listener = TcpListener::bind(&addr)?;
+        println!("lobby server listening on {}", listener.local_addr()?);
+        return lobby::serve(listener);
+    }
+
+    if list_games {
+        let games = lobby::list_games(&addr)?;
+        if games.is_empty() {
+            println!("No open games on {}", addr);
+        }
+        for open in games {
+            let best_of = open
+                .best_of
+                .map(|best_of| format!(", best of {}", best_of))
+                .unwrap_or_default();
+            println!(
+                "{}  hosted by {}, {}{}",
+                open.game,
+                open.host,
+                network::describe_rules(&open.rules),
+                best_of
+            );
+        }
+        return Ok(());
+    }
+
+    // a broken config is reported before connecting to anyone
+    let config = match config::path() {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+
+    // in lobby games the server stands in for the host, both players only join it
+    let hosting = is_host && lobby.is_none();
+
+    // read before connecting, the other player learns it in the handshake
+    let rating = match &stats_path {
+        Some(path) if matches!(game_mode, Mode::VsComputer | Mode::Network) => {
+            match Stats::load(path) {
+                Ok(stats) => Some(stats.rating),
+                // a broken stats file shouldn't keep anyone from playing
+                Err(reason) => {
+                    eprintln!(
+                        "tic-tac-term: can't read the stats, playing at {}: {}",
+                        rating::DEFAULT_RATING,
+                        reason
+                    );
+                    Some(rating::DEFAULT_RATING)
+                }
+            }
+        }
+        _ => None,
+    };
 
-    let (game_tx, game_rx) = mpsc::channel::<Message>();
-    let (net_tx, net_rx) = mpsc::channel::<Message>();
     let (term_tx, term_rx) = mpsc::channel::<u8>();
 
-    if game_mode == Mode::Network {
-        let (mut net_read, mut net_write) = network::connect(&addr, is_host)?;
-        thread::spawn(move || loop {
-            let incoming = network::read_stream(&mut net_read).unwrap();
-            net_tx.send(incoming).unwrap();
-            thread::sleep(time::Duration::from_millis(33));
-        });
+    let mut link = None;
+    let mut opponent_name = None;
+    let mut opponent_rating = None;
+    let mut chat = None;
+    let mut series = None;
+    let mut takebacks = false;
+    if game_mode == Mode::Network || game_mode == Mode::Spectator {
+        // the host decides the rules unless the joiner asked for specific ones
+        let mut hello = Hello::new((is_host || rules_given).then_some(rules), &name);
+        match game_mode {
+            Mode::Spectator => hello.role = Role::Spectator,
+            _ => hello.rating = rating,
+        }
+        let secret = secret.as_deref().map(Secret::new);
+        let (mut net_link, theirs) = match (&rendezvous, &lobby) {
+            (Some((server, code)), _) => {
+                Link::rendezvous(server, code, &addr, is_host, hello, secret)?
+            }
+            (None, Some((server, game))) => Link::lobby(server, game, is_host, best_of, hello)?,
+            (None, None) => Link::connect(&addr, is_host, transport, hello, secret)?,
+        };
+        net_link.simulate_loss(loss);
+        rules = net_link.rules().unwrap_or(rules);
+        opponent_name = Some(theirs.name);
+        opponent_rating = theirs.rating;
+        if game_mode == Mode::Network && theirs.features & FEATURE_CHAT != 0 {
+            chat = Some(Chat::default());
+        }
+        // the joiner's score is replaced by the host's as soon as it arrives
+        if game_mode == Mode::Network && theirs.features & FEATURE_REMATCH != 0 {
+            series = Some(Series::new(best_of));
+        }
+        takebacks = game_mode == Mode::Network && theirs.features & FEATURE_TAKEBACK != 0;
+        // the joiner's clock only shows what the host's says
+        if clock.is_some() && theirs.features & FEATURE_CLOCK == 0 {
+            net_link.goodbye();
+            return Err(anyhow!("Other player does not support clocks"));
+        }
+        link = Some(net_link);
+    }
 
-        thread::spawn(move || loop {
-            if let Ok(msg) = game_rx.recv() {
-                network::write_stream(&mut net_write, msg.into()).unwrap();
-                thread::sleep(time::Duration::from_millis(33));
+    let mut game = match game_mode {
+        Mode::VsComputer => Game::vs_computer(rules, difficulty, seed),
+        _ => Game::new(game_mode, hosting, rules),
+    };
+    if is_host && !hosting {
+        // whoever created a lobby game plays O
+        game.player = Player::O;
+    }
+    game.name = name;
+    game.keys = config.keys;
+    game.theme = theme::choose(theme.or(config.theme));
+    game.opponent_name = opponent_name;
+    game.rating = rating;
+    game.opponent_rating = game.opponent_rating.or(opponent_rating);
+    game.chat = chat;
+    game.series = series;
+    game.takebacks = takebacks;
+    game.clock = clock.map(Clock::new);
+    if let Some(record) = load {
+        match game.mode == Mode::Replay {
+            true => {
+                game.replay = Some(Replay::new(record, delay));
+                game.show_replay();
             }
-        });
+            false => game.load(&record)?,
+        }
     }
 
+    // only take over the terminal once connecting and loading can no longer fail
+    terminal::init();
+
     thread::spawn(move || loop {
         let mut buffer = [0; 1];
-        io::stdin().read_exact(&mut buffer).unwrap();
-        term_tx.send(buffer[0]).unwrap();
+        if io::stdin().read_exact(&mut buffer).is_err() || term_tx.send(buffer[0]).is_err() {
+            break;
+        }
     });
 
-    let mut game = Game::new(game_mode, is_host);
+    // the joiner always starts from the host's state
+    if let Some(link) = &mut link {
+        match hosting {
+            true => link.send(Message::new(MessageType::Payload, game.state_payload())),
+            false => game.net_state = NetState::Resuming,
+        }
+    }
+
+    // leave the terminal usable even if the game loop fails
+    let mut stats_error = None;
+    let result = run(
+        &mut game,
+        link,
+        &term_rx,
+        save.as_deref(),
+        stats_path.as_deref(),
+        &mut stats_error,
+    );
+    terminal::restore();
+    if let Some(reason) = stats_error {
+        eprintln!("tic-tac-term: can't update the stats: {}", reason);
+    }
+    result
+}
+
+fn run(
+    game: &mut Game,
+    mut link: Option<Link>,
+    term_rx: &mpsc::Receiver<u8>,
+    save: Option<&str>,
+    stats: Option<&Path>,
+    stats_error: &mut Option<anyhow::Error>,
+) -> anyhow::Result<()> {
+    // hash of the state spectators saw last
+    let mut watched_hash = None;
+    // score the host sent last
+    let mut sent_series = None;
+    // round and flag fall of the clock the host sent last
+    let mut sent_clock = None;
+    // record last written to the save file
+    let mut saved = None;
 
     loop {
         game.render()?;
 
-        let _ = terminal::process_input(&mut game, &term_rx);
-
-        if let Ok(recieved) = net_rx.recv_timeout(time::Duration::from_millis(33)) {
-            match recieved.message_type {
-                MessageType::Accepted => game.net_state = NetState::Waiting,
-                MessageType::Rejected => game.net_state = NetState::Active,
-                MessageType::Payload => {
-                    let recieved_state: State = recieved.payload.as_slice().try_into()?;
-                    if recieved_state.round > game.state.round {
-                        let validation_result = game.validate(recieved_state);
-                        let reply = match validation_result {
-                            Ok(_) => MessageType::Accepted,
-                            Err(reason) => {
-                                println!("{:?}", reason);
-                                MessageType::Rejected
-                            }
-                        };
-
-                        game_tx.send(Message {
-                            message_type: reply,
-                            payload_size: 0,
-                            payload: Vec::new(),
-                        })?;
-                        game.net_state = NetState::Active;
+        let round_before = game.status().round;
+        let rematch_before = game.rematch;
+        let takeback_before = game.takeback;
+        let _ = terminal::process_input(game, term_rx);
+
+        // rewritten whenever anything changed, so quitting or losing power keeps the game
+        if let Some(path) = save {
+            let record = Record::from_game(game).to_string();
+            if saved.as_ref() != Some(&record) {
+                fs::write(path, &record)
+                    .map_err(|reason| anyhow!("Can't save the game to {}: {}", path, reason))?;
+                saved = Some(record);
+            }
+        }
+
+        if game.quitting {
+            if let Some(link) = &mut link {
+                link.goodbye();
+            }
+            return Ok(());
+        }
+
+        if let Some(link) = &mut link {
+            // a mark was placed locally, tell the other player about just that move
+            if game.status().round > round_before {
+                if let Some(cell) = game.last_move {
+                    let mv = Move {
+                        cell: cell as u16,
+                        player: game.player,
+                        round: round_before,
+                    };
+                    link.send(Message::new(MessageType::Move, mv.into()));
+                    game.net_state = NetState::Waiting;
+                }
+            }
+
+            match (rematch_before, game.rematch) {
+                (Rematch::None, Rematch::Requested) => {
+                    link.send(Message::new(MessageType::RematchRequest, Vec::new()))
+                }
+                // accepting already started the next game
+                (Rematch::Offered, Rematch::None) => {
+                    link.send(Message::new(MessageType::RematchAccept, Vec::new()))
+                }
+                _ => (),
+            }
+
+            match (takeback_before, game.takeback) {
+                (Takeback::None, Takeback::Requested) => {
+                    if let Some(mv) = game.history.moves.last() {
+                        link.send(Message::new(MessageType::TakebackRequest, (*mv).into()))
                     }
                 }
+                // allowing it already took the move back, playing on would have added one
+                (Takeback::Offered, Takeback::None) if game.status().round < round_before => {
+                    if let Some(mv) = game.history.next_redo() {
+                        link.send(Message::new(MessageType::TakebackAccept, mv.into()))
+                    }
+                }
+                _ => (),
             }
-        }
 
-        game.check_state();
+            let outgoing = game.chat.as_mut().and_then(|chat| chat.outgoing.take());
+            if let Some(text) = outgoing {
+                // the chat input never lets a line get too long, so this always works
+                if let Ok(msg) = Message::chat(&text) {
+                    link.send(msg);
+                }
+            }
 
-        if game.mode == Mode::Network && game.net_state == NetState::Active {
-            let payload: Vec<u8> = (&game.state).into();
-            let message = Message {
-                message_type: MessageType::Payload,
-                payload_size: payload.len() as u16,
-                payload,
-            };
+            if let Some(event) = link.poll(time::Duration::from_millis(33)) {
+                handle_event(game, link, event);
+            }
 
-            game_tx.send(message)?;
+            game.spectators = link.spectator_count();
+            if game.spectators > 0 && watched_hash != Some(game.state_hash()) {
+                link.send_spectators(Message::new(MessageType::Payload, game.state_payload()));
+                watched_hash = Some(game.state_hash());
+            }
+
+            // the host keeps the score, both players and the spectators follow it
+            if game.is_host && game.series.is_some() && game.series != sent_series {
+                if let Some(msg) = score_message(game) {
+                    link.send(msg.clone());
+                    link.send_spectators(msg);
+                }
+                sent_series = game.series.clone();
+            }
+
+            // every move and flag fall changes the clock in a way the others can't predict
+            if let Some(clock) = &game.clock {
+                let clock_state = (game.status().round, clock.flagged);
+                if game.is_host && sent_clock != Some(clock_state) {
+                    if let Some(msg) = clock_message(game) {
+                        link.send(msg.clone());
+                        link.send_spectators(msg);
+                    }
+                    sent_clock = Some(clock_state);
+                }
+            }
         }
 
+        game.check_clock();
+        game.tick_replay();
+        game.check_state();
+        if let (Some(outcome), Some(path)) = (game.finished.take(), stats) {
+            // the game goes on without stats, the player hears why once it is over
+            if let Err(reason) = record_result(game, outcome, path) {
+                stats_error.get_or_insert(reason);
+            }
+        }
+        game.play_computer_turn();
+
         thread::sleep(time::Duration::from_millis(33));
     }
 }
 
-fn parse_args() -> (Mode, String, bool) {
-    let args: Vec<String> = env::args().collect();
+fn handle_event(game: &mut Game, link: &mut Link, event: NetEvent) {
+    match event {
+        NetEvent::Received(recieved) => handle_message(game, link, recieved),
+        NetEvent::Lost => game.net_state = NetState::Disconnected,
+        NetEvent::Closed => game.net_state = NetState::Closed,
+        NetEvent::SpectatorJoined => {
+            // everyone gets the state again, the newcomer needs it to see anything
+            link.send_spectators(Message::new(MessageType::Payload, game.state_payload()));
+            if let Some(msg) = score_message(game) {
+                link.send_spectators(msg);
+            }
+            if let Some(msg) = clock_message(game) {
+                link.send_spectators(msg);
+            }
+        }
+        NetEvent::FromSpectator(id, recieved) => {
+            let rejected = match recieved.message_type {
+                MessageType::Payload => game
+                    .validate_payload(&recieved.payload, Role::Spectator)
+                    .is_err(),
+                MessageType::Move => true,
+                _ => false,
+            };
+            if rejected {
+                link.send_spectator(id, Message::reply(false, game.state_hash()));
+            }
+        }
+        NetEvent::Reconnected => {
+            // the host's state is authoritative, the joiner picks up from there
+            if game.is_host {
+                link.send(Message::new(MessageType::Payload, game.state_payload()));
+                if let Some(msg) = score_message(game) {
+                    link.send(msg);
+                }
+                if let Some(msg) = clock_message(game) {
+                    link.send(msg);
+                }
+                game.sync_net_state();
+            } else {
+                game.net_state = NetState::Resuming;
+            }
+        }
+    }
+}
 
-    if args.len() == 1 {
-        return (Mode::Local, String::default(), false);
+// the host's state is the one that counts, a host that finds the players out of sync
+// sends it and the joiner waits for it. `tell` lets the other side know first.
+fn resync(game: &mut Game, link: &mut Link, tell: bool) {
+    if tell {
+        link.send(Message::reply(false, game.state_hash()));
+    }
+    match game.is_host {
+        true => link.send(Message::new(MessageType::Payload, game.state_payload())),
+        false => game.net_state = NetState::Desynced,
     }
+}
+
+fn score_message(game: &Game) -> Option<Message> {
+    let series = game.series.as_ref()?;
+    Some(Message::new(MessageType::Score, series.into()))
+}
+
+fn clock_message(game: &Game) -> Option<Message> {
+    let clock = game.clock.as_ref()?;
+    Some(Message::new(MessageType::Clock, clock.into()))
+}
 
-    let addr = args.get(2).expect("no address provided").clone();
-    let is_host = args.get(1).expect("missing argument") == &String::from("host");
+/// Adds a finished game to the stats, read again first in case another game finished meanwhile.
+/// Both ratings shown move by what the game was worth.
+fn record_result(game: &mut Game, outcome: Outcome, path: &Path) -> anyhow::Result<()> {
+    let Some(result) = GameResult::new(outcome, game.player) else {
+        return Ok(());
+    };
 
-    (Mode::Network, addr, is_host)
+    // only counted when one side is clearly the player, not in hot-seat games
+    let (o_name, x_name) = game.player_names();
+    let opponent = match (&game.mode, &game.computer, game.player) {
+        // every level has a rating of its own
+        (Mode::VsComputer, Some(computer), _) => {
+            format!("computer ({:?})", computer.difficulty).to_lowercase()
+        }
+        (Mode::Network, None, Player::O) => x_name,
+        (Mode::Network, None, Player::X) => o_name,
+        _ => return Ok(()),
+    };
+
+    let mut stats = Stats::load(path)?;
+    let rating_before = stats.rating;
+    stats.record(&game.mode, Some(&opponent), game.opponent_rating, result);
+    stats.save(path)?;
+
+    if let Some(opponent_rating) = game.opponent_rating {
+        game.rating = Some(stats.rating);
+        if game.computer.is_none() {
+            game.opponent_rating = Some(rating::updated(
+                opponent_rating,
+                rating_before,
+                result.for_opponent(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn handle_message(game: &mut Game, link: &mut Link, recieved: Message) {
+    match recieved.message_type {
+        // spectators only learn about the match from the score
+        // the host keeps the score, everyone else takes it from the host
+        MessageType::Score
+            if !game.is_host && (game.mode == Mode::Spectator || game.series.is_some()) =>
+        {
+            if let Ok(series) = Series::try_from(recieved.payload.as_slice()) {
+                game.series = Some(series);
+            }
+        }
+        MessageType::Clock if !game.is_host => {
+            if let Ok(clock) = Clock::try_from(recieved.payload.as_slice()) {
+                game.sync_clock(clock);
+            }
+        }
+        MessageType::Accepted | MessageType::Rejected => {
+            // both sides should have ended up with the same state after our move
+            let in_sync = recieved.message_type == MessageType::Accepted
+                && recieved.state_hash().ok() == Some(game.state_hash());
+            if !in_sync {
+                // a rejection needs no answer, the other side knows already
+                resync(game, link, recieved.message_type == MessageType::Accepted);
+            }
+        }
+        MessageType::Move => {
+            match Move::try_from(recieved.payload.as_slice()).and_then(|mv| game.apply_move(mv)) {
+                Ok(_) => {
+                    game.net_state = NetState::Active;
+                    link.send(Message::reply(true, game.state_hash()));
+                }
+                Err(_) => resync(game, link, true),
+            }
+        }
+        MessageType::Payload if game.mode == Mode::Spectator => {
+            if game.resume(&recieved.payload).is_err() {
+                game.net_state = NetState::Desynced;
+            }
+        }
+        // spectators are only told about the state, never asked
+        _ if game.mode == Mode::Spectator => (),
+        MessageType::Payload
+            if matches!(game.net_state, NetState::Resuming | NetState::Desynced) =>
+        {
+            if game.resume(&recieved.payload).is_err() {
+                game.net_state = NetState::Desynced;
+            }
+        }
+        MessageType::Payload => {
+            let validation_result = game.validate_payload(&recieved.payload, Role::Player);
+            if !matches!(validation_result, Ok(false)) {
+                link.send(Message::reply(validation_result.is_ok(), game.state_hash()));
+                game.net_state = NetState::Active;
+            }
+        }
+        MessageType::RematchRequest => game.rematch_requested(),
+        MessageType::RematchAccept => game.rematch_accepted(),
+        MessageType::TakebackRequest => {
+            if let Ok(mv) = Move::try_from(recieved.payload.as_slice()) {
+                game.takeback_requested(mv);
+            }
+        }
+        MessageType::TakebackAccept => {
+            if let Ok(mv) = Move::try_from(recieved.payload.as_slice()) {
+                game.takeback_accepted(mv);
+            }
+        }
+        // overlong or garbled lines are dropped
+        MessageType::Chat => {
+            let from = game.opponent_name.clone().unwrap_or_default();
+            if let (Some(chat), Ok(text)) = (&mut game.chat, recieved.chat_text()) {
+                chat.receive(&from, &text);
+            }
+        }
+        // greetings and lobby requests are exchanged while connecting, pings, goodbyes
+        // and sealed messages are handled by the link, only the host keeps score and time
+        MessageType::Hello
+        | MessageType::Ping
+        | MessageType::Goodbye
+        | MessageType::Sealed
+        | MessageType::Score
+        | MessageType::Clock
+        | MessageType::Lobby => (),
+    }
 }
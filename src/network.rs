@@ -1,15 +1,91 @@
 use std::{
-    io::{BufReader, BufWriter, Read, Write},
-    net::{TcpListener, TcpStream},
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    str::FromStr,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::anyhow;
 
-#[derive(Debug, PartialEq)]
+use crate::{
+    game::{Player, Rules, Variant},
+    lobby::{self, LobbyMessage},
+    rendezvous::{self, RENDEZVOUS_TIMEOUT},
+    secure::{self, Opener, Sealer, Secret, SessionKeys, NONCE_LEN, TOKEN_LEN},
+    udp::{UdpEndpoint, UdpStream},
+};
+
+/// Bumped whenever the wire format changes in a way older versions can't read
+pub const PROTOCOL_VERSION: u8 = 5;
+
+/// Optional protocol features, advertised as a bitset in `Hello`
+pub const FEATURE_MOVES: u32 = 1;
+pub const FEATURE_ULTIMATE: u32 = 1 << 1;
+pub const FEATURE_CHAT: u32 = 1 << 2;
+/// Not a capability, set when the sender plays with a `--secret` and only talks `Sealed`
+pub const FEATURE_SECRET: u32 = 1 << 3;
+pub const FEATURE_REMATCH: u32 = 1 << 4;
+pub const FEATURE_CLOCK: u32 = 1 << 5;
+pub const FEATURE_TAKEBACK: u32 = 1 << 6;
+
+/// Everything this build understands
+pub const SUPPORTED_FEATURES: u32 = FEATURE_MOVES
+    | FEATURE_ULTIMATE
+    | FEATURE_CHAT
+    | FEATURE_REMATCH
+    | FEATURE_CLOCK
+    | FEATURE_TAKEBACK;
+
+/// Longest display name in bytes
+pub const MAX_NAME_LEN: usize = 32;
+/// Longest chat message in bytes
+pub const MAX_CHAT_LEN: usize = 256;
+
+/// How often a ping is sent when nothing else has been
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long without hearing anything before the connection counts as lost
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the joiner has to come back after losing the connection
+pub const RECONNECT_GRACE: Duration = Duration::from_secs(60);
+/// How long to wait for the other side's `Hello`
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MessageType {
+    /// Move was applied, payload is the resulting state hash
     Accepted,
+    /// Move was refused, payload is the unchanged state hash
     Rejected,
+    /// Full game state
     Payload,
+    /// A single placement, see `Move`
+    Move,
+    /// Greeting sent by both players right after connecting, see `Hello`
+    Hello,
+    /// Keepalive, sent when nothing else has been sent for a while
+    Ping,
+    /// Other player is quitting on purpose, no reconnect will follow
+    Goodbye,
+    /// Line of chat, payload is the UTF-8 text
+    Chat,
+    /// Any other message, encrypted and authenticated with the session keys
+    Sealed,
+    /// Sender wants to play another game once this one is over
+    RematchRequest,
+    /// Sender agreed to the other player's rematch, the next game starts
+    RematchAccept,
+    /// Host's running score of the match, see `Series`
+    Score,
+    /// Request to or answer from a lobby server, see `LobbyMessage`
+    Lobby,
+    /// Host's chess clock, see `Clock`
+    Clock,
+    /// Sender wants to take back their latest move, payload is that `Move`
+    TakebackRequest,
+    /// Sender allowed taking back the `Move` in the payload, both players undo it
+    TakebackAccept,
 }
 
 impl From<MessageType> for u8 {
@@ -18,6 +94,19 @@ impl From<MessageType> for u8 {
             MessageType::Accepted => 0,
             MessageType::Rejected => 1,
             MessageType::Payload => 2,
+            MessageType::Move => 3,
+            MessageType::Hello => 4,
+            MessageType::Ping => 5,
+            MessageType::Goodbye => 6,
+            MessageType::Chat => 7,
+            MessageType::Sealed => 8,
+            MessageType::RematchRequest => 9,
+            MessageType::RematchAccept => 10,
+            MessageType::Score => 11,
+            MessageType::Lobby => 12,
+            MessageType::Clock => 13,
+            MessageType::TakebackRequest => 14,
+            MessageType::TakebackAccept => 15,
         }
     }
 }
@@ -28,6 +117,19 @@ impl TryFrom<u8> for MessageType {
             0 => Ok(MessageType::Accepted),
             1 => Ok(MessageType::Rejected),
             2 => Ok(MessageType::Payload),
+            3 => Ok(MessageType::Move),
+            4 => Ok(MessageType::Hello),
+            5 => Ok(MessageType::Ping),
+            6 => Ok(MessageType::Goodbye),
+            7 => Ok(MessageType::Chat),
+            8 => Ok(MessageType::Sealed),
+            9 => Ok(MessageType::RematchRequest),
+            10 => Ok(MessageType::RematchAccept),
+            11 => Ok(MessageType::Score),
+            12 => Ok(MessageType::Lobby),
+            13 => Ok(MessageType::Clock),
+            14 => Ok(MessageType::TakebackRequest),
+            15 => Ok(MessageType::TakebackAccept),
             _ => Err(anyhow!("Invalid byte value")),
         }
     }
@@ -35,13 +137,373 @@ impl TryFrom<u8> for MessageType {
     type Error = anyhow::Error;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Message {
     pub message_type: MessageType,
     pub payload_size: u16,
     pub payload: Vec<u8>,
 }
 
+impl Message {
+    pub fn new(message_type: MessageType, payload: Vec<u8>) -> Self {
+        Message {
+            message_type,
+            payload_size: payload.len() as u16,
+            payload,
+        }
+    }
+
+    /// Reply to a move, carrying the hash of the state after handling it
+    pub fn reply(accepted: bool, state_hash: u64) -> Self {
+        let message_type = match accepted {
+            true => MessageType::Accepted,
+            false => MessageType::Rejected,
+        };
+
+        Message::new(message_type, state_hash.to_be_bytes().to_vec())
+    }
+
+    /// State hash carried by an `Accepted` or `Rejected` reply
+    pub fn state_hash(&self) -> anyhow::Result<u64> {
+        let bytes: [u8; 8] = self
+            .payload
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow!("Reply must carry an 8 byte state hash"))?;
+
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Chat message carrying `text`, refused if longer than `MAX_CHAT_LEN`
+    pub fn chat(text: &str) -> anyhow::Result<Self> {
+        if text.len() > MAX_CHAT_LEN {
+            return Err(anyhow!(
+                "Chat message is {} bytes, at most {} are allowed",
+                text.len(),
+                MAX_CHAT_LEN
+            ));
+        }
+
+        Ok(Message::new(MessageType::Chat, text.as_bytes().to_vec()))
+    }
+
+    /// Text carried by a `Chat` message
+    pub fn chat_text(&self) -> anyhow::Result<String> {
+        if self.payload.len() > MAX_CHAT_LEN {
+            return Err(anyhow!("Chat message is too long"));
+        }
+
+        Ok(String::from_utf8(self.payload.clone())?)
+    }
+}
+
+/// A single placement sent to the other player, who applies it to their own
+/// copy of the state instead of receiving the whole state.
+/// `round` is the round the move was played in, before it was placed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Move {
+    pub cell: u16,
+    pub player: Player,
+    pub round: u16,
+}
+
+/// Binary format (5 bytes):
+/// - Bytes 0-1: Board index of the cell (u16, big endian)
+/// - Byte 2: Player ('X' or 'O')
+/// - Bytes 3-4: Round (u16, big endian)
+impl TryFrom<&[u8]> for Move {
+    fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() != 5 {
+            return Err(anyhow!("Move can only be deserialized from 5 bytes"));
+        }
+
+        let player = match bytes[2] {
+            b'X' => Player::X,
+            b'O' => Player::O,
+            _ => return Err(anyhow!("Invalid player in move")),
+        };
+
+        Ok(Move {
+            cell: u16::from_be_bytes([bytes[0], bytes[1]]),
+            player,
+            round: u16::from_be_bytes([bytes[3], bytes[4]]),
+        })
+    }
+
+    type Error = anyhow::Error;
+}
+
+impl From<Move> for Vec<u8> {
+    fn from(mv: Move) -> Self {
+        let mut bytes = Vec::with_capacity(5);
+        bytes.extend(mv.cell.to_be_bytes());
+        bytes.push(char::from(&mv.player) as u8);
+        bytes.extend(mv.round.to_be_bytes());
+        bytes
+    }
+}
+
+/// Whether someone connecting to the host wants to play or just watch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Player,
+    /// Gets the state streamed read-only, anything it sends is rejected
+    Spectator,
+}
+
+/// Greeting exchanged before the game starts so both sides know they speak
+/// the same protocol and play by the same rules
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub version: u8,
+    pub features: u32,
+    /// Rules the sender wants to play, None if the joiner will take whatever the host plays
+    pub rules: Option<Rules>,
+    pub role: Role,
+    /// Fresh for every connection, the session keys are derived from both sides' nonces
+    pub nonce: [u8; NONCE_LEN],
+    /// Same on every connection of a game, only the player who first greeted
+    /// with it can take their seat again after losing the connection
+    pub token: [u8; TOKEN_LEN],
+    pub name: String,
+    /// Elo rating of the sender, None for spectators and anyone else not playing rated
+    pub rating: Option<u16>,
+}
+
+impl Hello {
+    pub fn new(rules: Option<Rules>, name: &str) -> Self {
+        // keep names printable and short enough for the ui and the length byte
+        let mut clean_name = String::new();
+        for c in name.trim().chars().filter(|c| !c.is_control()) {
+            if clean_name.len() + c.len_utf8() > MAX_NAME_LEN {
+                break;
+            }
+            clean_name.push(c);
+        }
+
+        Hello {
+            version: PROTOCOL_VERSION,
+            features: SUPPORTED_FEATURES,
+            rules,
+            role: Role::Player,
+            nonce: [0; NONCE_LEN],
+            token: secure::new_token(),
+            name: clean_name,
+            rating: None,
+        }
+    }
+
+    /// Checks that the other side can play with us and returns the rules to play by,
+    /// the host's rules win if only one side asked for specific rules
+    pub fn agree(&self, theirs: &Hello) -> anyhow::Result<Rules> {
+        if theirs.version != self.version {
+            return Err(anyhow!(
+                "Other player uses protocol version {} but this is version {}, both need to run the same version of tic-tac-term",
+                theirs.version,
+                self.version
+            ));
+        }
+
+        match (
+            self.features & FEATURE_SECRET != 0,
+            theirs.features & FEATURE_SECRET != 0,
+        ) {
+            (true, false) => {
+                return Err(anyhow!(
+                    "Other player did not set a secret, both need to pass the same --secret"
+                ))
+            }
+            (false, true) => {
+                return Err(anyhow!(
+                    "Other player set a secret, pass the same --secret to play"
+                ))
+            }
+            _ => (),
+        }
+
+        let missing = self.features & !theirs.features;
+        if missing & FEATURE_MOVES != 0 {
+            return Err(anyhow!(
+                "Other player does not support move based network play"
+            ));
+        }
+
+        let rules = match (self.rules, theirs.rules) {
+            (Some(ours), Some(their)) if ours != their => {
+                return Err(anyhow!(
+                    "Rules don't match, you want {} but the other player wants {}",
+                    describe_rules(&ours),
+                    describe_rules(&their)
+                ))
+            }
+            (Some(rules), _) | (None, Some(rules)) => rules,
+            (None, None) => return Err(anyhow!("Neither player picked the rules")),
+        };
+
+        if rules.variant == Variant::Ultimate && theirs.features & FEATURE_ULTIMATE == 0 {
+            return Err(anyhow!(
+                "Other player does not support ultimate tic-tac-toe"
+            ));
+        }
+
+        Ok(rules)
+    }
+}
+
+pub fn describe_rules(rules: &Rules) -> String {
+    match rules.variant {
+        Variant::Ultimate => String::from("ultimate tic-tac-toe"),
+        Variant::Classic => format!("{0}x{0} with {1} in a row", rules.size, rules.win_length),
+    }
+}
+
+/// Rules in 3 bytes: board size (0 if none are requested), marks in a row
+/// needed to win and variant (0 = classic, 1 = ultimate)
+pub fn rules_to_bytes(rules: Option<Rules>) -> [u8; 3] {
+    match rules {
+        Some(rules) => [
+            rules.size,
+            rules.win_length,
+            match rules.variant {
+                Variant::Classic => 0,
+                Variant::Ultimate => 1,
+            },
+        ],
+        None => [0, 0, 0],
+    }
+}
+
+/// Reads rules written by `rules_to_bytes`
+pub fn rules_from_bytes(bytes: &[u8]) -> anyhow::Result<Option<Rules>> {
+    match bytes {
+        [0, _, _] => Ok(None),
+        [size, win_length, 0] => Ok(Some(Rules::new(*size, *win_length)?)),
+        [3, 3, 1] => Ok(Some(Rules::ultimate())),
+        _ => Err(anyhow!("Invalid rules")),
+    }
+}
+
+/// Binary format (44 bytes + name):
+/// - Byte 0: Protocol version, always first so any future version can still be detected
+/// - Bytes 1-4: Feature bitset (u32, big endian)
+/// - Byte 5: Board size, 0 if no rules are requested
+/// - Byte 6: Marks in a row needed to win
+/// - Byte 7: Variant (0 = classic, 1 = ultimate)
+/// - Byte 8: Role (0 = player, 1 = spectator)
+/// - Bytes 9-24: Nonce
+/// - Bytes 25-40: Rejoin token
+/// - Bytes 41-42: Rating (u16, big endian), 0 if there is none
+/// - Byte 43: Name length in bytes
+/// - Remaining bytes: Name (UTF-8)
+impl TryFrom<&[u8]> for Hello {
+    fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.is_empty() {
+            return Err(anyhow!("Hello is missing the protocol version"));
+        }
+
+        let version = bytes[0];
+        if version != PROTOCOL_VERSION {
+            // layout of the rest may differ, only the version can be trusted
+            return Ok(Hello {
+                version,
+                features: 0,
+                rules: None,
+                role: Role::Player,
+                nonce: [0; NONCE_LEN],
+                token: [0; TOKEN_LEN],
+                name: String::new(),
+                rating: None,
+            });
+        }
+
+        if bytes.len() < 44 || bytes.len() != 44 + bytes[43] as usize {
+            return Err(anyhow!("Hello has the wrong length"));
+        }
+
+        let features = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+
+        let rules = rules_from_bytes(&bytes[5..8])?;
+
+        let role = match bytes[8] {
+            0 => Role::Player,
+            1 => Role::Spectator,
+            _ => return Err(anyhow!("Invalid role in hello")),
+        };
+
+        let nonce = bytes[9..25].try_into()?;
+        let token = bytes[25..41].try_into()?;
+        let rating = match u16::from_be_bytes([bytes[41], bytes[42]]) {
+            0 => None,
+            rating => Some(rating),
+        };
+        let name = String::from_utf8(bytes[44..].to_vec())?;
+
+        Ok(Hello {
+            version,
+            features,
+            rules,
+            role,
+            nonce,
+            token,
+            name,
+            rating,
+        })
+    }
+
+    type Error = anyhow::Error;
+}
+
+impl From<&Hello> for Vec<u8> {
+    fn from(hello: &Hello) -> Self {
+        let mut bytes = vec![hello.version];
+        bytes.extend(hello.features.to_be_bytes());
+
+        bytes.extend(rules_to_bytes(hello.rules));
+
+        bytes.push(match hello.role {
+            Role::Player => 0,
+            Role::Spectator => 1,
+        });
+        bytes.extend(hello.nonce);
+        bytes.extend(hello.token);
+        bytes.extend(hello.rating.unwrap_or(0).to_be_bytes());
+        bytes.push(hello.name.len() as u8);
+        bytes.extend(hello.name.as_bytes());
+
+        bytes
+    }
+}
+
+/// Sends our `Hello`, waits for the other side's and checks that the two are compatible.
+/// Returns the other side's greeting and the rules both agreed on.
+pub fn handshake<R: Read, W: Write>(
+    reader: &mut BufReader<R>,
+    writer: &mut BufWriter<W>,
+    ours: &Hello,
+) -> anyhow::Result<(Hello, Rules)> {
+    write_stream(writer, Message::new(MessageType::Hello, ours.into()).into())?;
+
+    let reply = read_stream(reader)?;
+    if reply.message_type != MessageType::Hello {
+        return Err(anyhow!(
+            "Other player did not greet, it is probably running an older version of tic-tac-term"
+        ));
+    }
+
+    let theirs = Hello::try_from(reply.payload.as_slice())?;
+    let rules = ours.agree(&theirs)?;
+
+    Ok((theirs, rules))
+}
+
+/// FNV-1a hash of a serialized state, both players compare it after every move
+/// to detect if their copies of the game have drifted apart
+pub fn state_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
 impl From<Message> for Vec<u8> {
     fn from(msg: Message) -> Self {
         // first byte is msg type
@@ -57,20 +519,24 @@ impl From<Message> for Vec<u8> {
 
 impl TryFrom<&[u8]> for Message {
     fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
-        // TODO some check that length of bytes adds up before conversion
+        if bytes.len() < 3 {
+            return Err(anyhow!("Message is shorter than its 3 byte header"));
+        }
+
         let message_type: MessageType = bytes[0].try_into()?;
+        let payload_size = u16::from_be_bytes([bytes[1], bytes[2]]);
 
-        if message_type != MessageType::Payload {
-            return Ok(Message {
-                message_type,
-                payload_size: 0,
-                payload: Vec::new(),
-            });
+        if bytes.len() != 3 + payload_size as usize {
+            return Err(anyhow!(
+                "Message payload should be {} bytes but was {}",
+                payload_size,
+                bytes.len() - 3
+            ));
         }
 
         Ok(Message {
             message_type,
-            payload_size: u16::from_be_bytes([bytes[1], bytes[2]]),
+            payload_size,
             payload: bytes[3..].to_vec(),
         })
     }
@@ -82,24 +548,817 @@ impl TryFrom<&[u8]> for Message {
 pub enum NetState {
     Active,
     Waiting,
+    /// State hashes no longer match, or the other player rejected a move,
+    /// the joiner waits for the host's state
+    Desynced,
+    /// Connection dropped, waiting for the joiner to reconnect
+    Disconnected,
+    /// Reconnected, joiner is waiting for the host's state
+    Resuming,
+    /// Other player left or did not come back in time
+    Closed,
+}
+
+/// How messages travel between the players
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    Tcp,
+    /// Datagrams with our own sequencing and retransmission, see `UdpStream`
+    Udp,
+}
+
+impl FromStr for Transport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "tcp" => Ok(Transport::Tcp),
+            "udp" => Ok(Transport::Udp),
+            _ => Err(anyhow!("Unknown transport '{}', use tcp or udp", s)),
+        }
+    }
+}
+
+// a connection to the other player over either transport, both are reliable
+// ordered byte streams so the message framing is the same
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpStream),
+}
+
+impl Connection {
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Connection::Tcp(tcp_stream) => Ok(Connection::Tcp(tcp_stream.try_clone()?)),
+            Connection::Udp(udp_stream) => Ok(Connection::Udp(udp_stream.clone())),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(tcp_stream) => tcp_stream.set_read_timeout(timeout),
+            Connection::Udp(udp_stream) => udp_stream.set_read_timeout(timeout),
+        }
+    }
+
+    // also wakes up anyone blocked reading
+    fn shutdown(&self) {
+        match self {
+            Connection::Tcp(tcp_stream) => {
+                let _ = tcp_stream.shutdown(Shutdown::Both);
+            }
+            Connection::Udp(udp_stream) => udp_stream.shutdown(),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(tcp_stream) => tcp_stream.read(buf),
+            Connection::Udp(udp_stream) => udp_stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(tcp_stream) => tcp_stream.write(buf),
+            Connection::Udp(udp_stream) => udp_stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(tcp_stream) => tcp_stream.flush(),
+            Connection::Udp(udp_stream) => udp_stream.flush(),
+        }
+    }
 }
 
-pub fn connect(
-    address: &str,
-    is_host: bool,
-) -> anyhow::Result<(BufReader<TcpStream>, BufWriter<TcpStream>)> {
-    let tcp_stream = match is_host {
-        true => TcpListener::bind(address)?.accept()?.0,
-        false => TcpStream::connect(address)?,
+// where the host waits for the joiner and spectators
+enum Listener {
+    Tcp(TcpListener),
+    Udp(UdpEndpoint),
+}
+
+impl Listener {
+    fn bind(address: &str, transport: Transport) -> io::Result<Self> {
+        match transport {
+            Transport::Tcp => Ok(Listener::Tcp(TcpListener::bind(address)?)),
+            Transport::Udp => Ok(Listener::Udp(UdpEndpoint::bind(address)?)),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Listener::Tcp(listener) => Ok(Listener::Tcp(listener.try_clone()?)),
+            Listener::Udp(endpoint) => Ok(Listener::Udp(endpoint.clone())),
+        }
+    }
+
+    fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => Ok(Connection::Tcp(listener.accept()?.0)),
+            Listener::Udp(endpoint) => Ok(Connection::Udp(endpoint.accept(None)?)),
+        }
+    }
+}
+
+// how the joiner reaches the host, kept around for reconnects
+enum Dialer {
+    Address(String, Transport),
+    /// Host's public endpoint, from the socket that punched through our NAT
+    Punched(UdpEndpoint, SocketAddr),
+    /// Lobby server address and what to ask it for, the server plays the host's part
+    Lobby(String, LobbyMessage),
+}
+
+impl Dialer {
+    fn dial(&self) -> anyhow::Result<Connection> {
+        match self {
+            Dialer::Address(address, Transport::Tcp) => {
+                Ok(Connection::Tcp(TcpStream::connect(address)?))
+            }
+            Dialer::Address(address, Transport::Udp) => Ok(Connection::Udp(UdpStream::connect(
+                address,
+                HANDSHAKE_TIMEOUT,
+            )?)),
+            Dialer::Punched(endpoint, peer) => {
+                Ok(Connection::Udp(endpoint.connect(*peer, HANDSHAKE_TIMEOUT)?))
+            }
+            Dialer::Lobby(server, request) => {
+                let stream = TcpStream::connect(server)?;
+                // a new game waits for as long as it takes someone to join
+                let timeout = match request {
+                    LobbyMessage::Create { .. } => None,
+                    _ => Some(HANDSHAKE_TIMEOUT),
+                };
+                lobby::enter(&stream, request, timeout)?;
+                Ok(Connection::Tcp(stream))
+            }
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Dialer::Address(address, transport) => Ok(Dialer::Address(address.clone(), *transport)),
+            Dialer::Punched(endpoint, peer) => Ok(Dialer::Punched(endpoint.clone(), *peer)),
+            Dialer::Lobby(server, request) => Ok(Dialer::Lobby(server.clone(), request.clone())),
+        }
+    }
+}
+
+// which end of the connection we are
+enum Side {
+    Host(Listener),
+    Joiner(Dialer),
+}
+
+/// What happened on the connection since the last poll
+#[derive(Debug)]
+pub enum NetEvent {
+    Received(Message),
+    /// Connection dropped unexpectedly, a reconnect is attempted in the background
+    Lost,
+    /// Connection is back after being lost, the handshake is already done
+    Reconnected,
+    /// Other player said goodbye or did not reconnect in time
+    Closed,
+    /// Someone started watching the host's game
+    SpectatorJoined,
+    /// A spectator sent something, which it has no business doing
+    FromSpectator(u64, Message),
+}
+
+// events from the reader and reconnect threads, tagged with the connection
+// generation so a stale reader can't report on a newer connection
+enum LinkEvent {
+    Received(u64, Message),
+    ReadFailed(u64),
+    Reconnected(Connection, Option<SessionKeys>),
+    ReconnectFailed,
+    /// Someone connected to the host and got through the handshake
+    Joined(Connection, Hello, Option<SessionKeys>),
+    FromSpectator(u64, Message),
+    SpectatorFailed(u64),
+}
+
+// someone watching the host's game
+struct Spectator {
+    id: u64,
+    writer: BufWriter<Connection>,
+    sealer: Option<Sealer>,
+    last_received: Instant,
+    last_sent: Instant,
+}
+
+/// Connection to the other player that survives the joiner dropping out.
+/// The host keeps accepting connections, so the joiner can come back within
+/// `RECONNECT_GRACE` and spectators can join at any time.
+pub struct Link {
+    writer: Option<BufWriter<Connection>>,
+    /// Seals everything written while playing with a secret
+    sealer: Option<Sealer>,
+    generation: u64,
+    events: mpsc::Receiver<LinkEvent>,
+    events_tx: mpsc::Sender<LinkEvent>,
+    side: Side,
+    hello: Hello,
+    /// Token the opponent first greeted with, only they can take their seat again
+    opponent_token: [u8; TOKEN_LEN],
+    /// The opponent coming back while its old connection still looks alive, takes over once it isn't
+    rejoining: Option<(Connection, Option<SessionKeys>)>,
+    secret: Option<Secret>,
+    last_received: Instant,
+    last_sent: Instant,
+    closed: bool,
+    /// When the host lost the joiner
+    lost_at: Option<Instant>,
+    spectators: Vec<Spectator>,
+    next_spectator_id: u64,
+    /// Chance of dropping each outgoing udp packet, for trying out retransmission
+    loss: f64,
+    pub heartbeat_timeout: Duration,
+    pub reconnect_grace: Duration,
+}
+
+impl Link {
+    /// Hosts or joins a game at `address` and greets the other player.
+    /// Returns the link, the other player's `Hello` and the rules both agreed on.
+    pub fn connect(
+        address: &str,
+        is_host: bool,
+        transport: Transport,
+        hello: Hello,
+        secret: Option<Secret>,
+    ) -> anyhow::Result<(Self, Hello)> {
+        let side = match is_host {
+            true => Side::Host(Listener::bind(address, transport)?),
+            false => Side::Joiner(Dialer::Address(address.to_string(), transport)),
+        };
+
+        Self::start(side, hello, secret)
+    }
+
+    /// Hosts or joins a game over UDP by meeting the other player at a rendezvous `server`
+    /// under the same game `code`, then punching through both NATs.
+    /// `local_address` is the address to send from, usually 0.0.0.0:0.
+    pub fn rendezvous(
+        server: &str,
+        code: &str,
+        local_address: &str,
+        is_host: bool,
+        hello: Hello,
+        secret: Option<Secret>,
+    ) -> anyhow::Result<(Self, Hello)> {
+        let socket = UdpSocket::bind(local_address)?;
+        let peer = rendezvous::find_peer(&socket, server, code, is_host, RENDEZVOUS_TIMEOUT)?;
+
+        // the joiner's connect requests punch its side, the host has to punch its own
+        let endpoint = UdpEndpoint::from_socket(socket)?;
+        let side = match is_host {
+            true => {
+                endpoint.punch(peer, Instant::now() + HANDSHAKE_TIMEOUT)?;
+                Side::Host(Listener::Udp(endpoint))
+            }
+            false => Side::Joiner(Dialer::Punched(endpoint, peer)),
+        };
+
+        Self::start(side, hello, secret)
+    }
+
+    /// Creates or joins the game named `game` on the lobby server at `server`. Creating
+    /// waits until someone joins. The server referees the game and stands in for the
+    /// other player, whose name still comes back in the returned `Hello`.
+    pub fn lobby(
+        server: &str,
+        game: &str,
+        create: bool,
+        best_of: Option<u8>,
+        hello: Hello,
+    ) -> anyhow::Result<(Self, Hello)> {
+        let request = match create {
+            true => LobbyMessage::Create {
+                game: game.to_string(),
+                name: hello.name.clone(),
+                rules: hello.rules.unwrap_or_default(),
+                best_of,
+            },
+            false => LobbyMessage::Join {
+                game: game.to_string(),
+                name: hello.name.clone(),
+                rules: hello.rules,
+            },
+        };
+
+        let side = Side::Joiner(Dialer::Lobby(server.to_string(), request));
+        let (mut link, theirs) = Self::start(side, hello, None)?;
+
+        // coming back after losing the connection is a join for both players
+        let rejoin = LobbyMessage::Join {
+            game: game.to_string(),
+            name: link.hello.name.clone(),
+            rules: link.hello.rules,
+        };
+        link.side = Side::Joiner(Dialer::Lobby(server.to_string(), rejoin));
+
+        Ok((link, theirs))
+    }
+
+    fn start(side: Side, hello: Hello, secret: Option<Secret>) -> anyhow::Result<(Self, Hello)> {
+        // https://doc.rust-lang.org/book/ch21-01-single-threaded.html
+        // https://github.com/thepacketgeek/rust-tcpstream-demo/blob/master/protocol/README.md
+
+        let mut hello = hello;
+        let (connection, theirs, keys) = match &side {
+            Side::Host(listener) => loop {
+                // nothing to watch until the opponent is here
+                let connection = listener.accept()?;
+                match greet(&connection, &mut hello, secret.as_ref()) {
+                    Ok((theirs, keys)) if theirs.role == Role::Player => {
+                        break (connection, theirs, keys)
+                    }
+                    Ok((_, keys)) => refuse(connection, keys),
+                    // the player keeps waiting, but learns why nobody showed up
+                    Err(reason) => {
+                        eprintln!("tic-tac-term: turned away a player: {}", reason);
+                        refuse(connection, None);
+                    }
+                }
+            },
+            Side::Joiner(dialer) => {
+                let connection = dialer.dial()?;
+                let (theirs, keys) = greet(&connection, &mut hello, secret.as_ref())?;
+                (connection, theirs, keys)
+            }
+        };
+
+        let (events_tx, events) = mpsc::channel();
+        let mut link = Link {
+            writer: None,
+            sealer: None,
+            generation: 0,
+            events,
+            events_tx,
+            side,
+            hello,
+            opponent_token: theirs.token,
+            rejoining: None,
+            secret,
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+            closed: false,
+            lost_at: None,
+            spectators: Vec::new(),
+            next_spectator_id: 0,
+            loss: 0.0,
+            heartbeat_timeout: HEARTBEAT_TIMEOUT,
+            reconnect_grace: RECONNECT_GRACE,
+        };
+        link.attach(connection, keys)?;
+
+        if let Side::Host(listener) = &link.side {
+            let listener = listener.try_clone()?;
+            let events_tx = link.events_tx.clone();
+            let mut hello = link.hello.clone();
+            let secret = link.secret.clone();
+
+            // the opponent coming back and spectators all arrive here, the
+            // handshake is done before the game loop hears about them
+            thread::spawn(move || loop {
+                let Ok(connection) = listener.accept() else {
+                    break;
+                };
+                match greet(&connection, &mut hello, secret.as_ref()) {
+                    Ok((theirs, keys)) => {
+                        if events_tx
+                            .send(LinkEvent::Joined(connection, theirs, keys))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(_) => connection.shutdown(),
+                }
+            });
+        }
+
+        Ok((link, theirs))
+    }
+
+    /// Rules agreed on in the handshake
+    pub fn rules(&self) -> Option<Rules> {
+        self.hello.rules
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    pub fn spectator_count(&self) -> usize {
+        self.spectators.len()
+    }
+
+    /// Drops outgoing udp packets with probability `chance`, also after reconnecting
+    pub fn simulate_loss(&mut self, chance: f64) {
+        self.loss = chance;
+        if let Some(Connection::Udp(udp_stream)) = self.writer.as_ref().map(|w| w.get_ref()) {
+            udp_stream.set_loss(chance);
+        }
+    }
+
+    // start reading from a fresh connection
+    fn attach(&mut self, connection: Connection, keys: Option<SessionKeys>) -> anyhow::Result<()> {
+        self.generation += 1;
+        let generation = self.generation;
+        let events_tx = self.events_tx.clone();
+        let mut reader = BufReader::new(connection.try_clone()?);
+        if let Connection::Udp(udp_stream) = &connection {
+            udp_stream.set_loss(self.loss);
+        }
+        let (sealer, mut opener) = split_keys(keys);
+
+        thread::spawn(move || loop {
+            // a frame that fails authentication ends the connection like any other read error
+            match read_stream(&mut reader).and_then(|msg| open(opener.as_mut(), msg)) {
+                Ok(incoming) => {
+                    if events_tx
+                        .send(LinkEvent::Received(generation, incoming))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(_) => {
+                    let _ = events_tx.send(LinkEvent::ReadFailed(generation));
+                    break;
+                }
+            }
+        });
+
+        self.writer = Some(BufWriter::new(connection));
+        self.sealer = sealer;
+        self.last_received = Instant::now();
+        self.last_sent = Instant::now();
+
+        Ok(())
+    }
+
+    /// Sends a message, dropped if there is no connection right now
+    pub fn send(&mut self, msg: Message) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+
+        if write_stream(writer, seal(self.sealer.as_mut(), msg).into()).is_err() {
+            // the reader thread will notice as well and report it on the next poll
+            self.disconnect();
+        }
+        self.last_sent = Instant::now();
+    }
+
+    /// Sends a message to everyone watching
+    pub fn send_spectators(&mut self, msg: Message) {
+        self.spectators.retain_mut(|spectator| {
+            spectator.last_sent = Instant::now();
+            let sealed = seal(spectator.sealer.as_mut(), msg.clone());
+            match write_stream(&mut spectator.writer, sealed.into()) {
+                Ok(_) => true,
+                Err(_) => {
+                    spectator.writer.get_ref().shutdown();
+                    false
+                }
+            }
+        });
+    }
+
+    /// Sends a message to a single spectator, see `NetEvent::FromSpectator`
+    pub fn send_spectator(&mut self, id: u64, msg: Message) {
+        if let Some(spectator) = self.spectators.iter_mut().find(|s| s.id == id) {
+            spectator.last_sent = Instant::now();
+            let sealed = seal(spectator.sealer.as_mut(), msg);
+            let _ = write_stream(&mut spectator.writer, sealed.into());
+        }
+    }
+
+    /// Waits up to `timeout` for something to happen on the connection,
+    /// keeps the heartbeat going and notices when the other side went quiet
+    pub fn poll(&mut self, timeout: Duration) -> Option<NetEvent> {
+        if self.is_connected() {
+            if self.last_received.elapsed() >= self.heartbeat_timeout {
+                return Some(self.lose());
+            }
+
+            if self.last_sent.elapsed() >= HEARTBEAT_INTERVAL {
+                self.send(Message::new(MessageType::Ping, Vec::new()));
+            }
+        }
+
+        if let Some(lost_at) = self.lost_at {
+            if !self.closed && lost_at.elapsed() >= self.reconnect_grace {
+                self.closed = true;
+                return Some(NetEvent::Closed);
+            }
+        }
+
+        let heartbeat_timeout = self.heartbeat_timeout;
+        self.spectators.retain_mut(|spectator| {
+            if spectator.last_sent.elapsed() >= HEARTBEAT_INTERVAL {
+                spectator.last_sent = Instant::now();
+                let ping = Message::new(MessageType::Ping, Vec::new());
+                let sealed = seal(spectator.sealer.as_mut(), ping);
+                if write_stream(&mut spectator.writer, sealed.into()).is_err() {
+                    return false;
+                }
+            }
+
+            let alive = spectator.last_received.elapsed() < heartbeat_timeout;
+            if !alive {
+                spectator.writer.get_ref().shutdown();
+            }
+            alive
+        });
+
+        match self.events.recv_timeout(timeout).ok()? {
+            LinkEvent::Received(generation, _) if generation != self.generation => None,
+            LinkEvent::Received(_, incoming) => {
+                self.last_received = Instant::now();
+                match incoming.message_type {
+                    MessageType::Ping => None,
+                    MessageType::Goodbye => {
+                        self.disconnect();
+                        self.closed = true;
+                        Some(NetEvent::Closed)
+                    }
+                    _ => Some(NetEvent::Received(incoming)),
+                }
+            }
+            LinkEvent::ReadFailed(generation) if generation == self.generation => {
+                if self.closed {
+                    return None;
+                }
+                Some(self.lose())
+            }
+            LinkEvent::ReadFailed(_) => None,
+            LinkEvent::Reconnected(connection, keys) => {
+                if self.closed || self.attach(connection, keys).is_err() {
+                    return None;
+                }
+                Some(NetEvent::Reconnected)
+            }
+            LinkEvent::ReconnectFailed => {
+                self.closed = true;
+                Some(NetEvent::Closed)
+            }
+            LinkEvent::Joined(connection, theirs, keys) if theirs.role == Role::Spectator => {
+                self.watch(connection, keys).ok()?;
+                Some(NetEvent::SpectatorJoined)
+            }
+            LinkEvent::Joined(connection, theirs, keys) => {
+                // anyone but the opponent is turned away, whether the opponent is here or not
+                if self.closed || theirs.token != self.opponent_token {
+                    refuse(connection, keys);
+                    return None;
+                }
+                // a live connection is never replaced, the newcomer waits until the old one
+                // drops. Over udp that only happens once the opponent gave up resending.
+                if self.is_connected() {
+                    if let Some((waiting, _)) = self.rejoining.replace((connection, keys)) {
+                        waiting.shutdown();
+                    }
+                    return None;
+                }
+                if self.attach(connection, keys).is_err() {
+                    return Some(self.lose());
+                }
+                self.lost_at = None;
+                Some(NetEvent::Reconnected)
+            }
+            LinkEvent::FromSpectator(id, incoming) => {
+                let spectator = self.spectators.iter_mut().find(|s| s.id == id)?;
+                spectator.last_received = Instant::now();
+                match incoming.message_type {
+                    MessageType::Ping => None,
+                    MessageType::Goodbye => {
+                        self.spectators.retain(|s| s.id != id);
+                        None
+                    }
+                    _ => Some(NetEvent::FromSpectator(id, incoming)),
+                }
+            }
+            LinkEvent::SpectatorFailed(id) => {
+                self.spectators.retain(|s| s.id != id);
+                None
+            }
+        }
+    }
+
+    // start streaming to a new spectator and listening for what it sends
+    fn watch(&mut self, connection: Connection, keys: Option<SessionKeys>) -> anyhow::Result<()> {
+        self.next_spectator_id += 1;
+        let id = self.next_spectator_id;
+        let events_tx = self.events_tx.clone();
+        let mut reader = BufReader::new(connection.try_clone()?);
+        let (sealer, mut opener) = split_keys(keys);
+
+        thread::spawn(move || loop {
+            match read_stream(&mut reader).and_then(|msg| open(opener.as_mut(), msg)) {
+                Ok(incoming) => {
+                    if events_tx
+                        .send(LinkEvent::FromSpectator(id, incoming))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(_) => {
+                    let _ = events_tx.send(LinkEvent::SpectatorFailed(id));
+                    break;
+                }
+            }
+        });
+
+        self.spectators.push(Spectator {
+            id,
+            writer: BufWriter::new(connection),
+            sealer,
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+        });
+
+        Ok(())
+    }
+
+    /// Tells the other player we are leaving on purpose and closes the connection
+    pub fn goodbye(&mut self) {
+        self.send(Message::new(MessageType::Goodbye, Vec::new()));
+        self.send_spectators(Message::new(MessageType::Goodbye, Vec::new()));
+        for spectator in self.spectators.drain(..) {
+            spectator.writer.get_ref().shutdown();
+        }
+        if let Some((connection, keys)) = self.rejoining.take() {
+            refuse(connection, keys);
+        }
+        self.closed = true;
+        self.disconnect();
+    }
+
+    fn disconnect(&mut self) {
+        self.sealer = None;
+        if let Some(writer) = self.writer.take() {
+            // also wakes up the reader thread
+            writer.get_ref().shutdown();
+        }
+    }
+
+    // drop the connection and try to get it back, the host waits for the
+    // joiner to turn up again while the joiner goes looking for the host
+    fn lose(&mut self) -> NetEvent {
+        self.disconnect();
+        // connection generation moves on so the old reader's failure is ignored
+        self.generation += 1;
+
+        let dialer = match &self.side {
+            Side::Host(_) => {
+                if let Some((connection, keys)) = self.rejoining.take() {
+                    if self.attach(connection, keys).is_ok() {
+                        self.lost_at = None;
+                        return NetEvent::Reconnected;
+                    }
+                }
+                self.lost_at = Some(Instant::now());
+                return NetEvent::Lost;
+            }
+            Side::Joiner(dialer) => dialer.try_clone(),
+        };
+
+        let deadline = Instant::now() + self.reconnect_grace;
+        let events_tx = self.events_tx.clone();
+        let mut hello = self.hello.clone();
+        let secret = self.secret.clone();
+
+        thread::spawn(move || {
+            let reconnected = dialer
+                .ok()
+                .and_then(|dialer| connect_until(&dialer, &mut hello, secret.as_ref(), deadline));
+
+            let _ = events_tx.send(match reconnected {
+                Some((connection, keys)) => LinkEvent::Reconnected(connection, keys),
+                None => LinkEvent::ReconnectFailed,
+            });
+        });
+
+        NetEvent::Lost
+    }
+}
+
+// turns away someone the host has no room for
+fn refuse(connection: Connection, keys: Option<SessionKeys>) {
+    let (mut sealer, _) = split_keys(keys);
+    let mut writer = BufWriter::new(connection);
+    let goodbye = Message::new(MessageType::Goodbye, Vec::new());
+    let _ = write_stream(&mut writer, seal(sealer.as_mut(), goodbye).into());
+    writer.get_ref().shutdown();
+}
+
+// joiner side of a reconnect, keeps knocking until the host lets us back in
+fn connect_until(
+    dialer: &Dialer,
+    hello: &mut Hello,
+    secret: Option<&Secret>,
+    deadline: Instant,
+) -> Option<(Connection, Option<SessionKeys>)> {
+    while Instant::now() < deadline {
+        if let Ok(connection) = dialer.dial() {
+            if let Ok((_, keys)) = greet(&connection, hello, secret) {
+                return Some((connection, keys));
+            }
+            connection.shutdown();
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+
+    None
+}
+
+// handshake on a fresh connection, afterwards `hello` carries the agreed rules
+// so a reconnect has to match them exactly. With a secret it also returns the
+// keys everything on this connection is sealed with from now on.
+fn greet(
+    connection: &Connection,
+    hello: &mut Hello,
+    secret: Option<&Secret>,
+) -> anyhow::Result<(Hello, Option<SessionKeys>)> {
+    connection.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    // unbuffered, whatever the other side sends right after its hello
+    // belongs to the reader thread started afterwards
+    let mut reader = BufReader::with_capacity(0, connection.try_clone()?);
+    let mut writer = BufWriter::new(connection.try_clone()?);
+
+    hello.nonce = secure::new_nonce();
+    if secret.is_some() {
+        hello.features |= FEATURE_SECRET;
+    }
+    let (theirs, rules) = handshake(&mut reader, &mut writer, hello)?;
+    let keys = match secret {
+        Some(secret) => Some(confirm(&mut reader, &mut writer, secret, hello, &theirs)?),
+        None => None,
     };
+    connection.set_read_timeout(None)?;
 
-    // https://doc.rust-lang.org/book/ch21-01-single-threaded.html
-    // https://github.com/thepacketgeek/rust-tcpstream-demo/blob/master/protocol/README.md
+    hello.rules = Some(rules);
+    Ok((theirs, keys))
+}
+
+// both sides seal the two hellos as they saw them, which only opens on the other side
+// if both derived the same keys and nobody changed the hellos on the way
+fn confirm<R: Read, W: Write>(
+    reader: &mut BufReader<R>,
+    writer: &mut BufWriter<W>,
+    secret: &Secret,
+    ours: &Hello,
+    theirs: &Hello,
+) -> anyhow::Result<SessionKeys> {
+    let mut keys = secret.session_keys(&ours.nonce, &theirs.nonce);
+    let (ours, theirs): (Vec<u8>, Vec<u8>) = (ours.into(), theirs.into());
 
-    let reader = BufReader::new(tcp_stream.try_clone()?);
-    let writer = BufWriter::new(tcp_stream);
+    let transcript = Message::new(MessageType::Hello, [ours.as_slice(), &theirs].concat());
+    write_stream(writer, seal(Some(&mut keys.sealer), transcript).into())?;
+
+    let reply = open(Some(&mut keys.opener), read_stream(reader)?)
+        .map_err(|_| anyhow!("Secrets don't match, both players need to pass the same --secret"))?;
+    if reply.message_type != MessageType::Hello || reply.payload != [theirs, ours].concat() {
+        return Err(anyhow!("Handshake was tampered with"));
+    }
 
-    Ok((reader, writer))
+    Ok(keys)
+}
+
+fn split_keys(keys: Option<SessionKeys>) -> (Option<Sealer>, Option<Opener>) {
+    match keys {
+        Some(keys) => (Some(keys.sealer), Some(keys.opener)),
+        None => (None, None),
+    }
+}
+
+// the whole message, type and length included, travels inside a `Sealed` one
+fn seal(sealer: Option<&mut Sealer>, msg: Message) -> Message {
+    match sealer {
+        Some(sealer) => Message::new(MessageType::Sealed, sealer.seal(&Vec::from(msg))),
+        None => msg,
+    }
+}
+
+fn open(opener: Option<&mut Opener>, msg: Message) -> anyhow::Result<Message> {
+    match opener {
+        Some(opener) if msg.message_type == MessageType::Sealed => {
+            Message::try_from(opener.open(&msg.payload)?.as_slice())
+        }
+        Some(_) => Err(anyhow!("Message was not sealed with the secret")),
+        None => Ok(msg),
+    }
 }
 
 pub fn read_stream<R: Read>(stream: &mut BufReader<R>) -> anyhow::Result<Message> {
@@ -107,11 +1366,7 @@ pub fn read_stream<R: Read>(stream: &mut BufReader<R>) -> anyhow::Result<Message
     stream.read_exact(&mut mt_buf)?;
     let message_type: MessageType = mt_buf[0].try_into()?;
 
-    if message_type != MessageType::Payload {
-        let message: Message = mt_buf.as_slice().try_into()?;
-        return Ok(message);
-    }
-
+    // every message type is length prefixed, even if the payload is empty
     let mut payload_size_buf = [0; 2];
     stream.read_exact(&mut payload_size_buf)?;
 
@@ -146,14 +1401,52 @@ mod tests {
         assert_eq!(u8::from(MessageType::Accepted), 0);
         assert_eq!(u8::from(MessageType::Rejected), 1);
         assert_eq!(u8::from(MessageType::Payload), 2);
+        assert_eq!(u8::from(MessageType::Move), 3);
+        assert_eq!(u8::from(MessageType::Hello), 4);
+        assert_eq!(u8::from(MessageType::Ping), 5);
+        assert_eq!(u8::from(MessageType::Goodbye), 6);
+        assert_eq!(u8::from(MessageType::Chat), 7);
+        assert_eq!(u8::from(MessageType::Sealed), 8);
+        assert_eq!(u8::from(MessageType::RematchRequest), 9);
+        assert_eq!(u8::from(MessageType::RematchAccept), 10);
+        assert_eq!(u8::from(MessageType::Score), 11);
+        assert_eq!(u8::from(MessageType::Lobby), 12);
+        assert_eq!(u8::from(MessageType::Clock), 13);
+        assert_eq!(u8::from(MessageType::TakebackRequest), 14);
+        assert_eq!(u8::from(MessageType::TakebackAccept), 15);
 
         // u8 -> MessageType
         assert_eq!(MessageType::try_from(0).unwrap(), MessageType::Accepted);
         assert_eq!(MessageType::try_from(1).unwrap(), MessageType::Rejected);
         assert_eq!(MessageType::try_from(2).unwrap(), MessageType::Payload);
+        assert_eq!(MessageType::try_from(3).unwrap(), MessageType::Move);
+        assert_eq!(MessageType::try_from(4).unwrap(), MessageType::Hello);
+        assert_eq!(MessageType::try_from(5).unwrap(), MessageType::Ping);
+        assert_eq!(MessageType::try_from(6).unwrap(), MessageType::Goodbye);
+        assert_eq!(MessageType::try_from(7).unwrap(), MessageType::Chat);
+        assert_eq!(MessageType::try_from(8).unwrap(), MessageType::Sealed);
+        assert_eq!(
+            MessageType::try_from(9).unwrap(),
+            MessageType::RematchRequest
+        );
+        assert_eq!(
+            MessageType::try_from(10).unwrap(),
+            MessageType::RematchAccept
+        );
+        assert_eq!(MessageType::try_from(11).unwrap(), MessageType::Score);
+        assert_eq!(MessageType::try_from(12).unwrap(), MessageType::Lobby);
+        assert_eq!(MessageType::try_from(13).unwrap(), MessageType::Clock);
+        assert_eq!(
+            MessageType::try_from(14).unwrap(),
+            MessageType::TakebackRequest
+        );
+        assert_eq!(
+            MessageType::try_from(15).unwrap(),
+            MessageType::TakebackAccept
+        );
 
         // invalid conversion
-        assert!(MessageType::try_from(3).is_err());
+        assert!(MessageType::try_from(16).is_err());
     }
 
     #[test]
@@ -180,14 +1473,14 @@ mod tests {
     #[test]
     fn test_message_from_bytes() {
         // accepted message
-        let bytes = vec![0];
+        let bytes = vec![0, 0, 0];
         let msg = Message::try_from(bytes.as_slice()).unwrap();
         assert_eq!(msg.message_type, MessageType::Accepted);
         assert_eq!(msg.payload_size, 0);
         assert!(msg.payload.is_empty());
 
         // rejected message
-        let bytes = vec![1];
+        let bytes = vec![1, 0, 0];
         let msg = Message::try_from(bytes.as_slice()).unwrap();
         assert_eq!(msg.message_type, MessageType::Rejected);
 
@@ -202,7 +1495,7 @@ mod tests {
     #[test]
     fn test_read_stream() {
         // mock a simple accepted message
-        let mock_data = vec![0]; // MessageType::Accepted
+        let mock_data = vec![0, 0, 0]; // MessageType::Accepted
         let mut cursor = Cursor::new(mock_data);
         let mut reader = BufReader::new(&mut cursor);
 
@@ -242,10 +1535,14 @@ mod tests {
     #[test]
     fn test_error_handling() {
         // invalid message type
-        let invalid_data = vec![5]; // Invalid message type
+        let invalid_data = vec![42, 0, 0]; // Invalid message type
         let result = Message::try_from(invalid_data.as_slice());
         assert!(result.is_err());
 
+        // too short for a header, or payload size not adding up
+        assert!(Message::try_from([0].as_slice()).is_err());
+        assert!(Message::try_from([2, 0, 5, 1, 2].as_slice()).is_err());
+
         // incomplete payload message
         let incomplete_data = vec![2, 0, 5, 1, 2]; // Payload size 5 but only 2 bytes
         let mut cursor = Cursor::new(incomplete_data);
@@ -254,4 +1551,463 @@ mod tests {
         let result = read_stream(&mut reader);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_read_stream_sequence() {
+        // two replies back to back should be read as two messages
+        let mut data: Vec<u8> = Message::reply(true, 42).into();
+        data.extend(Vec::<u8>::from(Message::reply(false, 7)));
+        let mut cursor = Cursor::new(data);
+        let mut reader = BufReader::new(&mut cursor);
+
+        let first = read_stream(&mut reader).unwrap();
+        assert_eq!(first.message_type, MessageType::Accepted);
+        assert_eq!(first.state_hash().unwrap(), 42);
+
+        let second = read_stream(&mut reader).unwrap();
+        assert_eq!(second.message_type, MessageType::Rejected);
+        assert_eq!(second.state_hash().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_move_conversions() {
+        let mv = Move {
+            cell: 300,
+            player: Player::X,
+            round: 2,
+        };
+
+        let bytes: Vec<u8> = mv.into();
+        assert_eq!(bytes, vec![1, 44, b'X', 0, 2]);
+        assert_eq!(Move::try_from(bytes.as_slice()).unwrap(), mv);
+
+        // invalid player and wrong length
+        assert!(Move::try_from([0, 1, b'Z', 0, 2].as_slice()).is_err());
+        assert!(Move::try_from([0, 1, b'X'].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_state_hash() {
+        assert_eq!(state_hash(&[1, 2, 3]), state_hash(&[1, 2, 3]));
+        assert_ne!(state_hash(&[1, 2, 3]), state_hash(&[1, 2, 4]));
+
+        // replies without a hash
+        let msg = Message::new(MessageType::Accepted, Vec::new());
+        assert!(msg.state_hash().is_err());
+    }
+
+    #[test]
+    fn test_chat_messages() {
+        let msg = Message::chat("gg wp ✓").unwrap();
+        assert_eq!(msg.message_type, MessageType::Chat);
+        let bytes: Vec<u8> = msg.into();
+        let msg = Message::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(msg.chat_text().unwrap(), "gg wp ✓");
+
+        // overlong messages are refused on both ends
+        assert!(Message::chat(&"a".repeat(MAX_CHAT_LEN)).is_ok());
+        assert!(Message::chat(&"a".repeat(MAX_CHAT_LEN + 1)).is_err());
+        let msg = Message::new(MessageType::Chat, vec![b'a'; MAX_CHAT_LEN + 1]);
+        assert!(msg.chat_text().is_err());
+
+        // not UTF-8
+        let msg = Message::new(MessageType::Chat, vec![0xff, 0xfe]);
+        assert!(msg.chat_text().is_err());
+    }
+
+    #[test]
+    fn test_hello_conversions() {
+        let mut hello = Hello::new(Some(Rules::new(15, 5).unwrap()), "  alice\n ");
+        assert_eq!(hello.name, "alice");
+        hello.nonce = secure::new_nonce();
+
+        let bytes: Vec<u8> = (&hello).into();
+        assert_eq!(bytes.len(), 44 + 5);
+        assert_eq!(Hello::try_from(bytes.as_slice()).unwrap(), hello);
+
+        let mut rated = Hello::new(None, "dave");
+        rated.rating = Some(1216);
+        let bytes: Vec<u8> = (&rated).into();
+        assert_eq!(&bytes[25..41], &rated.token);
+        assert_eq!(&bytes[41..43], &1216u16.to_be_bytes());
+        assert_eq!(
+            Hello::try_from(bytes.as_slice()).unwrap().rating,
+            Some(1216)
+        );
+
+        let ultimate = Hello::new(Some(Rules::ultimate()), "bob");
+        let bytes: Vec<u8> = (&ultimate).into();
+        assert_eq!(Hello::try_from(bytes.as_slice()).unwrap(), ultimate);
+
+        let no_rules = Hello::new(None, "carol");
+        let bytes: Vec<u8> = (&no_rules).into();
+        assert_eq!(Hello::try_from(bytes.as_slice()).unwrap().rules, None);
+
+        // long names are cut at a character boundary
+        let long = Hello::new(None, &"å".repeat(40));
+        assert_eq!(long.name.len(), MAX_NAME_LEN);
+
+        // name length not adding up
+        let mut bytes = vec![PROTOCOL_VERSION, 0, 0, 0, 3, 3, 3, 0, 0];
+        bytes.extend([0; NONCE_LEN + TOKEN_LEN]);
+        bytes.extend([0, 0, 9, b'a']);
+        assert!(Hello::try_from(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_hello_agree() {
+        let host = Hello::new(Some(Rules::new(4, 4).unwrap()), "host");
+
+        // joiner without rules takes the host's
+        let joiner = Hello::new(None, "joiner");
+        assert_eq!(host.agree(&joiner).unwrap(), Rules::new(4, 4).unwrap());
+        assert_eq!(joiner.agree(&host).unwrap(), Rules::new(4, 4).unwrap());
+
+        // joiner asking for other rules
+        let picky = Hello::new(Some(Rules::default()), "picky");
+        assert!(host.agree(&picky).is_err());
+        assert!(picky.agree(&host).is_err());
+
+        // different protocol version
+        let mut old = Hello::new(None, "old");
+        old.version = PROTOCOL_VERSION + 1;
+        assert!(host.agree(&old).is_err());
+
+        // ultimate needs the feature on both sides
+        let ultimate_host = Hello::new(Some(Rules::ultimate()), "host");
+        let mut basic = Hello::new(None, "basic");
+        basic.features = FEATURE_MOVES;
+        assert!(ultimate_host.agree(&basic).is_err());
+
+        // only one side playing with a secret
+        let mut secretive = Hello::new(None, "secretive");
+        secretive.features |= FEATURE_SECRET;
+        assert!(host.agree(&secretive).is_err());
+        assert!(secretive.agree(&host).is_err());
+    }
+
+    #[test]
+    fn test_handshake() {
+        let host = Hello::new(Some(Rules::default()), "host");
+        let joiner = Hello::new(None, "joiner");
+
+        // joiner's greeting is already waiting in the stream
+        let incoming: Vec<u8> = Message::new(MessageType::Hello, (&joiner).into()).into();
+        let mut reader = BufReader::new(Cursor::new(incoming));
+        let mut sent = Vec::new();
+        let (theirs, rules) = {
+            let mut writer = BufWriter::new(&mut sent);
+            handshake(&mut reader, &mut writer, &host).unwrap()
+        };
+
+        assert_eq!(theirs.name, "joiner");
+        assert_eq!(rules, Rules::default());
+        assert_eq!(sent[0], u8::from(MessageType::Hello));
+
+        // peer that starts sending moves straight away
+        let incoming: Vec<u8> = Message::new(MessageType::Move, vec![0, 0, b'X', 0, 0]).into();
+        let mut reader = BufReader::new(Cursor::new(incoming));
+        let mut writer = BufWriter::new(Vec::new());
+        assert!(handshake(&mut reader, &mut writer, &host).is_err());
+    }
+
+    // host and joiner links over loopback on a free port, and the host's address
+    fn link_pair(transport: Transport, secret: Option<Secret>) -> (Link, Link, String) {
+        let (listener, address) = match transport {
+            Transport::Tcp => {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let address = listener.local_addr().unwrap();
+                (Listener::Tcp(listener), address)
+            }
+            Transport::Udp => {
+                let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+                let address = socket.local_addr().unwrap();
+                (
+                    Listener::Udp(UdpEndpoint::from_socket(socket).unwrap()),
+                    address,
+                )
+            }
+        };
+        let host_secret = secret.clone();
+        let host = thread::spawn(move || {
+            let hello = Hello::new(Some(Rules::default()), "host");
+            Link::start(Side::Host(listener), hello, host_secret)
+                .unwrap()
+                .0
+        });
+
+        let address = address.to_string();
+        let hello = Hello::new(None, "joiner");
+        let (joiner, theirs) = Link::connect(&address, false, transport, hello, secret).unwrap();
+        assert_eq!(theirs.name, "host");
+
+        (host.join().unwrap(), joiner, address)
+    }
+
+    fn poll_until(link: &mut Link, matches: fn(&NetEvent) -> bool) -> NetEvent {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(event) = link.poll(Duration::from_millis(20)) {
+                if matches(&event) {
+                    return event;
+                }
+            }
+        }
+        panic!("expected event never arrived");
+    }
+
+    #[test]
+    fn test_link_messages_and_goodbye() {
+        let (mut host, mut joiner, _) = link_pair(Transport::Tcp, None);
+        assert_eq!(joiner.rules(), Some(Rules::default()));
+
+        host.send(Message::reply(true, 1));
+        let event = poll_until(&mut joiner, |e| matches!(e, NetEvent::Received(_)));
+        let NetEvent::Received(msg) = event else {
+            unreachable!()
+        };
+        assert_eq!(msg.state_hash().unwrap(), 1);
+
+        joiner.goodbye();
+        poll_until(&mut host, |e| matches!(e, NetEvent::Closed));
+        assert!(!host.is_connected());
+    }
+
+    #[test]
+    fn test_link_reconnect() {
+        let (mut host, mut joiner, _) = link_pair(Transport::Tcp, None);
+
+        // joiner loses the connection and comes back on its own
+        joiner.lose();
+        poll_until(&mut host, |e| matches!(e, NetEvent::Lost));
+
+        poll_until(&mut joiner, |e| matches!(e, NetEvent::Reconnected));
+        poll_until(&mut host, |e| matches!(e, NetEvent::Reconnected));
+
+        host.send(Message::reply(true, 2));
+        poll_until(&mut joiner, |e| matches!(e, NetEvent::Received(_)));
+    }
+
+    // someone else joining as a player under the joiner's name
+    fn assert_impostor_turned_away(host: &mut Link, address: &str) {
+        let hello = Hello::new(None, "joiner");
+        let (mut impostor, _) = Link::connect(address, false, Transport::Tcp, hello, None).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !matches!(
+            impostor.poll(Duration::from_millis(20)),
+            Some(NetEvent::Closed)
+        ) {
+            assert!(host.poll(Duration::from_millis(20)).is_none());
+            assert!(Instant::now() < deadline, "impostor was never turned away");
+        }
+    }
+
+    #[test]
+    fn test_only_the_joiner_gets_its_seat_back() {
+        let (mut host, joiner, address) = link_pair(Transport::Tcp, None);
+        assert_impostor_turned_away(&mut host, &address);
+        assert!(host.is_connected());
+
+        // and not even once the joiner is gone
+        drop(joiner);
+        poll_until(&mut host, |e| matches!(e, NetEvent::Lost));
+        assert_impostor_turned_away(&mut host, &address);
+        assert!(!host.is_connected());
+    }
+
+    #[test]
+    fn test_rejoin_waits_for_the_old_connection() {
+        let (mut host, joiner, address) = link_pair(Transport::Tcp, None);
+
+        // the joiner's token again while its first connection is still up
+        let hello = joiner.hello.clone();
+        let (mut second, _) = Link::connect(&address, false, Transport::Tcp, hello, None).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(300);
+        while Instant::now() < deadline {
+            assert!(host.poll(Duration::from_millis(20)).is_none());
+        }
+
+        drop(joiner);
+        poll_until(&mut host, |e| matches!(e, NetEvent::Reconnected));
+        host.send(Message::reply(true, 2));
+        poll_until(&mut second, |e| matches!(e, NetEvent::Received(_)));
+    }
+
+    #[test]
+    fn test_link_heartbeat_timeout() {
+        let (mut host, joiner, _) = link_pair(Transport::Tcp, None);
+        host.heartbeat_timeout = Duration::from_millis(200);
+        host.reconnect_grace = Duration::from_millis(300);
+
+        // joiner is still connected but never polls, so it never sends pings
+        poll_until(&mut host, |e| matches!(e, NetEvent::Lost));
+        poll_until(&mut host, |e| matches!(e, NetEvent::Closed));
+        drop(joiner);
+    }
+
+    #[test]
+    fn test_transport_from_str() {
+        assert_eq!("tcp".parse::<Transport>().unwrap(), Transport::Tcp);
+        assert_eq!("udp".parse::<Transport>().unwrap(), Transport::Udp);
+        assert!("carrier pigeon".parse::<Transport>().is_err());
+    }
+
+    #[test]
+    fn test_udp_link_reconnect_and_goodbye() {
+        let (mut host, mut joiner, _) = link_pair(Transport::Udp, None);
+        assert_eq!(joiner.rules(), Some(Rules::default()));
+        host.simulate_loss(0.2);
+        joiner.simulate_loss(0.2);
+
+        // the joiner is usually back before the host hears the old connection close
+        joiner.lose();
+        poll_until(&mut joiner, |e| matches!(e, NetEvent::Reconnected));
+        poll_until(&mut host, |e| matches!(e, NetEvent::Reconnected));
+
+        host.send(Message::reply(true, 3));
+        poll_until(&mut joiner, |e| matches!(e, NetEvent::Received(_)));
+
+        joiner.goodbye();
+        poll_until(&mut host, |e| matches!(e, NetEvent::Closed));
+    }
+
+    #[test]
+    fn test_link_through_rendezvous() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_address = server.local_addr().unwrap().to_string();
+        thread::spawn(move || rendezvous::serve(server));
+
+        let host_server = server_address.clone();
+        let host = thread::spawn(move || {
+            let hello = Hello::new(Some(Rules::default()), "host");
+            Link::rendezvous(&host_server, "pizza", "127.0.0.1:0", true, hello, None)
+                .unwrap()
+                .0
+        });
+        let (mut joiner, theirs) = Link::rendezvous(
+            &server_address,
+            "pizza",
+            "127.0.0.1:0",
+            false,
+            Hello::new(None, "joiner"),
+            None,
+        )
+        .unwrap();
+        let mut host = host.join().unwrap();
+        assert_eq!(theirs.name, "host");
+
+        // the joiner comes back from the punched socket
+        joiner.lose();
+        poll_until(&mut joiner, |e| matches!(e, NetEvent::Reconnected));
+        poll_until(&mut host, |e| matches!(e, NetEvent::Reconnected));
+
+        joiner.send(Message::reply(true, 4));
+        poll_until(&mut host, |e| matches!(e, NetEvent::Received(_)));
+    }
+
+    #[test]
+    fn test_spectators() {
+        let (mut host, _joiner, address) = link_pair(Transport::Tcp, None);
+
+        let mut hello = Hello::new(None, "watcher");
+        hello.role = Role::Spectator;
+        let (mut spectator, theirs) =
+            Link::connect(&address, false, Transport::Tcp, hello, None).unwrap();
+        assert_eq!(theirs.name, "host");
+        poll_until(&mut host, |e| matches!(e, NetEvent::SpectatorJoined));
+        assert_eq!(host.spectator_count(), 1);
+
+        host.send_spectators(Message::reply(true, 5));
+        let event = poll_until(&mut spectator, |e| matches!(e, NetEvent::Received(_)));
+        let NetEvent::Received(msg) = event else {
+            unreachable!()
+        };
+        assert_eq!(msg.state_hash().unwrap(), 5);
+
+        // anything a spectator says goes to the game to be turned down
+        spectator.send(Message::new(MessageType::Payload, vec![0]));
+        poll_until(&mut host, |e| matches!(e, NetEvent::FromSpectator(_, _)));
+
+        // a third player is turned away while the opponent is still here
+        let intruder = Link::connect(
+            &address,
+            false,
+            Transport::Tcp,
+            Hello::new(None, "intruder"),
+            None,
+        );
+        let (mut intruder, _) = intruder.unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !matches!(
+            intruder.poll(Duration::from_millis(20)),
+            Some(NetEvent::Closed)
+        ) {
+            host.poll(Duration::from_millis(20));
+            assert!(Instant::now() < deadline, "intruder was never turned away");
+        }
+        assert!(host.is_connected());
+
+        spectator.goodbye();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while host.spectator_count() > 0 && Instant::now() < deadline {
+            host.poll(Duration::from_millis(20));
+        }
+        assert_eq!(host.spectator_count(), 0);
+    }
+
+    #[test]
+    fn test_link_with_secret() {
+        let secret = Secret::new("hunter2");
+        let (mut host, mut joiner, address) = link_pair(Transport::Tcp, Some(secret.clone()));
+
+        host.send(Message::reply(true, 6));
+        poll_until(&mut joiner, |e| matches!(e, NetEvent::Received(_)));
+
+        // a reconnect gets fresh keys
+        joiner.lose();
+        poll_until(&mut joiner, |e| matches!(e, NetEvent::Reconnected));
+        poll_until(&mut host, |e| matches!(e, NetEvent::Reconnected));
+        joiner.send(Message::reply(true, 7));
+        let event = poll_until(&mut host, |e| matches!(e, NetEvent::Received(_)));
+        let NetEvent::Received(msg) = event else {
+            unreachable!()
+        };
+        assert_eq!(msg.state_hash().unwrap(), 7);
+
+        // the host refuses to play with a different secret or none at all
+        for other in [Some(Secret::new("hunter3")), None] {
+            let result = Link::connect(
+                &address,
+                false,
+                Transport::Tcp,
+                Hello::new(None, "joiner"),
+                other,
+            );
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_sealed_messages() {
+        let secret = Secret::new("hunter2");
+        let (ours, theirs) = (secure::new_nonce(), secure::new_nonce());
+        let mut host = secret.session_keys(&ours, &theirs);
+        let mut joiner = secret.session_keys(&theirs, &ours);
+
+        let sealed = seal(Some(&mut host.sealer), Message::reply(true, 8));
+        assert_eq!(sealed.message_type, MessageType::Sealed);
+        let opened = open(Some(&mut joiner.opener), sealed.clone()).unwrap();
+        assert_eq!(opened.state_hash().unwrap(), 8);
+
+        // replayed, forged and unsealed frames are all turned away
+        assert!(open(Some(&mut joiner.opener), sealed).is_err());
+        let mut forged = seal(Some(&mut host.sealer), Message::reply(true, 9));
+        forged.payload[1] ^= 1;
+        assert!(open(Some(&mut joiner.opener), forged).is_err());
+        assert!(open(Some(&mut joiner.opener), Message::reply(true, 9)).is_err());
+
+        // without a secret messages go through as they are
+        let plain = open(None, seal(None, Message::reply(true, 10))).unwrap();
+        assert_eq!(plain.message_type, MessageType::Accepted);
+    }
 }
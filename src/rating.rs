@@ -0,0 +1,56 @@
+use crate::stats::GameResult;
+
+/// Rating of a player who hasn't finished a rated game yet
+pub const DEFAULT_RATING: u16 = 1200;
+
+/// Most a rating can move after a single game
+const K_FACTOR: f64 = 32.0;
+
+/// Chance of `rating` beating `opponent`, counting draws as half a win
+pub fn expected_score(rating: u16, opponent: u16) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent as f64 - rating as f64) / 400.0))
+}
+
+/// Rating after a game against `opponent`, standard Elo. Never 0, which means
+/// no rating on the wire.
+pub fn updated(rating: u16, opponent: u16, result: GameResult) -> u16 {
+    let score = match result {
+        GameResult::Win => 1.0,
+        GameResult::Draw => 0.5,
+        GameResult::Loss => 0.0,
+    };
+    let change = K_FACTOR * (score - expected_score(rating, opponent));
+    (rating as f64 + change).round().clamp(1.0, u16::MAX as f64) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_score() {
+        assert_eq!(expected_score(1200, 1200), 0.5);
+        // 400 points apart is ten to one
+        assert!((expected_score(1600, 1200) - 10.0 / 11.0).abs() < 1e-9);
+        assert!((expected_score(1200, 1600) + expected_score(1600, 1200) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_updated_rating() {
+        assert_eq!(updated(1200, 1200, GameResult::Win), 1216);
+        assert_eq!(updated(1200, 1200, GameResult::Loss), 1184);
+        assert_eq!(updated(1200, 1200, GameResult::Draw), 1200);
+
+        // beating a much stronger player is worth a lot more than beating a weaker one
+        assert_eq!(updated(1200, 1600, GameResult::Win), 1229);
+        assert_eq!(updated(1600, 1200, GameResult::Win), 1603);
+        assert_eq!(updated(1600, 1200, GameResult::Draw), 1587);
+
+        // both sides move by the same amount
+        let winner = updated(1350, 1500, GameResult::Win) - 1350;
+        let loser = 1500 - updated(1500, 1350, GameResult::Loss);
+        assert_eq!(winner, loser);
+
+        assert_eq!(updated(1, 2000, GameResult::Loss), 1);
+    }
+}
@@ -0,0 +1,335 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::anyhow;
+
+use crate::{
+    game::{Game, Mode, Player, Rules, Variant},
+    history,
+    network::Move,
+};
+
+/// Version written into every record, bumped when older builds can't read the format
+pub const RECORD_VERSION: u32 = 1;
+
+/// How a game ended, or that it hasn't yet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Unfinished,
+    Won(Player),
+    /// The other player ran out of time
+    WonOnTime(Player),
+    Draw,
+}
+
+/// Everything needed to replay a game, used to save one and pick it up again later
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub rules: Rules,
+    pub o_name: String,
+    pub x_name: String,
+    pub moves: Vec<Move>,
+    pub outcome: Outcome,
+}
+
+impl Record {
+    pub fn from_game(game: &Game) -> Self {
+        let (o_name, x_name) = game.player_names();
+        Record {
+            rules: game.state.rules,
+            o_name,
+            x_name,
+            moves: game.history.moves.clone(),
+            outcome: game.outcome(),
+        }
+    }
+}
+
+/// Text format, one item per line, blank lines and lines starting with `#` are skipped:
+/// - `tic-tac-term record 1`, always the first line, the number is the version
+/// - `rules 3 3` with the board size and marks in a row needed, or `rules ultimate`
+/// - `o alice` and `x bob`, names of the players
+/// - `1. O b2`, one line per move in order with the column from `a` on the left
+///   and the row from `1` at the top
+/// - `result o`, `result x`, `result draw` or `result unfinished`, wins on time
+///   add `on time` like `result o on time`
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "tic-tac-term record {}", RECORD_VERSION)?;
+        match self.rules.variant {
+            Variant::Classic => writeln!(f, "rules {} {}", self.rules.size, self.rules.win_length)?,
+            Variant::Ultimate => writeln!(f, "rules ultimate")?,
+        }
+        writeln!(f, "o {}", one_line(&self.o_name))?;
+        writeln!(f, "x {}", one_line(&self.x_name))?;
+
+        let board_size = board_size(&self.rules);
+        for (i, mv) in self.moves.iter().enumerate() {
+            writeln!(
+                f,
+                "{}. {} {}",
+                i + 1,
+                char::from(&mv.player),
+                history::coordinate(mv.cell, board_size)
+            )?;
+        }
+
+        let result = match self.outcome {
+            Outcome::Unfinished => String::from("unfinished"),
+            Outcome::Won(player) => player_name(player).to_string(),
+            Outcome::WonOnTime(player) => format!("{} on time", player_name(player)),
+            Outcome::Draw => String::from("draw"),
+        };
+        writeln!(f, "result {}", result)
+    }
+}
+
+/// Parses a record and replays its moves, so a record that loads is a game that can be played on
+impl FromStr for Record {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut lines = s
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        let version = lines
+            .next()
+            .and_then(|(_, line)| line.strip_prefix("tic-tac-term record "))
+            .ok_or_else(|| anyhow!("Not a tic-tac-term game record"))?;
+        match version.parse::<u32>() {
+            Ok(RECORD_VERSION) => (),
+            _ => return Err(anyhow!("Record version {} is not supported", version)),
+        }
+
+        let mut rules = None;
+        let mut o_name = None;
+        let mut x_name = None;
+        let mut moves = Vec::new();
+        let mut outcome = None;
+        for (index, line) in lines {
+            let at_line = |reason: &str| anyhow!("Line {}: {}", index + 1, reason);
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+
+            if outcome.is_some() {
+                return Err(at_line("nothing can follow the result"));
+            }
+
+            match key {
+                "rules" if rules.is_none() => {
+                    rules = Some(parse_rules(value).map_err(|reason| at_line(&reason))?)
+                }
+                "o" if o_name.is_none() => o_name = Some(value.to_string()),
+                "x" if x_name.is_none() => x_name = Some(value.to_string()),
+                "result" => {
+                    outcome = Some(parse_outcome(value).ok_or_else(|| at_line("unknown result"))?)
+                }
+                "rules" | "o" | "x" => return Err(at_line(&format!("{} is given twice", key))),
+                _ => {
+                    let rules = rules.ok_or_else(|| at_line("moves need the rules first"))?;
+                    let mv = parse_move(key, value, moves.len(), &rules)
+                        .ok_or_else(|| at_line(&format!("expected move {}", moves.len() + 1)))?;
+                    moves.push(mv);
+                }
+            }
+        }
+
+        let record = Record {
+            rules: rules.ok_or_else(|| anyhow!("Record has no rules"))?,
+            o_name: o_name.unwrap_or_default(),
+            x_name: x_name.unwrap_or_default(),
+            moves,
+            outcome: outcome.ok_or_else(|| anyhow!("Record has no result"))?,
+        };
+
+        let mut game = Game::new(Mode::Local, false, record.rules);
+        game.load(&record)?;
+        Ok(record)
+    }
+}
+
+// "3 3" or "ultimate"
+fn parse_rules(value: &str) -> Result<Rules, String> {
+    if value == "ultimate" {
+        return Ok(Rules::ultimate());
+    }
+
+    let numbers: Vec<u8> = value
+        .split(' ')
+        .map(|number| number.parse::<u8>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| String::from("rules need a board size and win length"))?;
+    match numbers[..] {
+        [size, win_length] => Rules::new(size, win_length).map_err(|reason| reason.to_string()),
+        _ => Err(String::from("rules need a board size and win length")),
+    }
+}
+
+// "1." and "O b2" for the first move
+fn parse_move(number: &str, value: &str, played: usize, rules: &Rules) -> Option<Move> {
+    if number.strip_suffix('.')?.parse::<usize>().ok()? != played + 1 {
+        return None;
+    }
+
+    let (player, cell) = value.split_once(' ')?;
+    let player = match player {
+        "O" => Player::O,
+        "X" => Player::X,
+        _ => return None,
+    };
+
+    Some(Move {
+        cell: history::parse_coordinate(cell, board_size(rules))?,
+        player,
+        round: played as u16,
+    })
+}
+
+fn parse_outcome(value: &str) -> Option<Outcome> {
+    let player = |name: &str| match name {
+        "o" => Some(Player::O),
+        "x" => Some(Player::X),
+        _ => None,
+    };
+
+    match value.split_once(' ') {
+        Some((name, "on time")) => player(name).map(Outcome::WonOnTime),
+        Some(_) => None,
+        None if value == "unfinished" => Some(Outcome::Unfinished),
+        None if value == "draw" => Some(Outcome::Draw),
+        None => player(value).map(Outcome::Won),
+    }
+}
+
+fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::O => "o",
+        Player::X => "x",
+    }
+}
+
+fn board_size(rules: &Rules) -> u8 {
+    match rules.variant {
+        Variant::Classic => rules.size,
+        Variant::Ultimate => 9,
+    }
+}
+
+/// Names end at the line break, control characters are dropped
+pub fn one_line(name: &str) -> String {
+    name.chars().filter(|c| !c.is_control()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORD: &str = "\
+tic-tac-term record 1
+rules 3 3
+o alice
+x bob
+1. O b2
+2. X a1
+3. O c3
+result unfinished
+";
+
+    #[test]
+    fn test_parse_record() {
+        let record: Record = RECORD.parse().unwrap();
+        assert_eq!(record.rules, Rules::default());
+        assert_eq!(record.o_name, "alice");
+        assert_eq!(record.x_name, "bob");
+        assert_eq!(record.moves.len(), 3);
+        assert_eq!(
+            record.moves[1],
+            Move {
+                cell: 0,
+                player: Player::X,
+                round: 1
+            }
+        );
+        assert_eq!(record.outcome, Outcome::Unfinished);
+
+        // written back the same way
+        assert_eq!(record.to_string(), RECORD);
+
+        // comments and blank lines are skipped
+        let commented = RECORD.replace("o alice\n", "\n# a comment\no alice\n");
+        assert_eq!(commented.parse::<Record>().unwrap(), record);
+    }
+
+    #[test]
+    fn test_record_errors() {
+        let broken = [
+            ("", "Not a tic-tac-term game record"),
+            (
+                "tic-tac-term record 2\n",
+                "Record version 2 is not supported",
+            ),
+            (
+                "tic-tac-term record 1\nresult draw\n",
+                "Record has no rules",
+            ),
+            ("tic-tac-term record 1\nrules 3 3\n", "Record has no result"),
+            ("tic-tac-term record 1\nrules 2 2\n", "Line 2: Board size"),
+            (
+                "tic-tac-term record 1\n1. O b2\n",
+                "Line 2: moves need the rules",
+            ),
+            (
+                "tic-tac-term record 1\nrules 3 3\n2. O b2\n",
+                "Line 3: expected move 1",
+            ),
+            (
+                "tic-tac-term record 1\nrules 3 3\n1. O d1\n",
+                "Line 3: expected move 1",
+            ),
+            (
+                "tic-tac-term record 1\nrules 3 3\nresult o\n1. O b2\n",
+                "Line 4: nothing",
+            ),
+        ];
+        for (text, reason) in broken {
+            let error = text.parse::<Record>().unwrap_err().to_string();
+            assert!(error.starts_with(reason), "{:?} gave {:?}", text, error);
+        }
+
+        // moves that break the rules and results that don't match the board
+        let taken = RECORD.replace("3. O c3", "3. O b2");
+        assert!(taken.parse::<Record>().is_err());
+        let twice = RECORD.replace("3. O c3", "3. X c3");
+        assert!(twice.parse::<Record>().is_err());
+        let won = RECORD.replace("unfinished", "o");
+        assert!(won.parse::<Record>().is_err());
+    }
+
+    #[test]
+    fn test_outcomes() {
+        for outcome in [
+            Outcome::Unfinished,
+            Outcome::Won(Player::X),
+            Outcome::WonOnTime(Player::O),
+            Outcome::Draw,
+        ] {
+            let mut game = Game::new(Mode::Local, false, Rules::ultimate());
+            let mut record = Record::from_game(&game);
+            record.outcome = outcome;
+            let text = record.to_string();
+            let parsed = match outcome {
+                // nobody has won on the empty board
+                Outcome::Won(_) | Outcome::Draw => {
+                    assert!(text.parse::<Record>().is_err());
+                    continue;
+                }
+                _ => text.parse::<Record>().unwrap(),
+            };
+            assert_eq!(parsed, record);
+
+            game.load(&parsed).unwrap();
+            assert_eq!(game.outcome(), outcome);
+        }
+    }
+}
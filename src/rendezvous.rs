@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+
+/// Longest game code in bytes
+pub const MAX_CODE_LEN: usize = 64;
+/// How often a registration is repeated until the server answers
+const REGISTER_INTERVAL: Duration = Duration::from_millis(500);
+/// How long to wait for the other player to register the same code
+pub const RENDEZVOUS_TIMEOUT: Duration = Duration::from_secs(300);
+/// Registrations nobody refreshed for this long are forgotten
+const REGISTRATION_EXPIRY: Duration = Duration::from_secs(600);
+
+/// Messages between players and the rendezvous server, all sent over UDP so the
+/// server sees the same public endpoint the other player will have to reach
+#[derive(Debug, PartialEq)]
+pub enum RendezvousMessage {
+    /// Player is looking for the other player with the same game code
+    Register { code: String, is_host: bool },
+    /// Server found the other player, this is their public endpoint
+    Peer(SocketAddr),
+}
+
+/// Binary format:
+/// - Byte 0: Message kind (0 = register, 1 = peer)
+///
+/// Register (3 bytes + code):
+/// - Byte 1: Role (0 = host, 1 = joiner)
+/// - Byte 2: Code length in bytes
+/// - Remaining bytes: Game code (UTF-8)
+///
+/// Peer (8 or 20 bytes):
+/// - Byte 1: IP version (4 or 6)
+/// - Next 4 or 16 bytes: IP address
+/// - Last 2 bytes: Port (u16, big endian)
+impl TryFrom<&[u8]> for RendezvousMessage {
+    fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
+        match bytes {
+            [0, role @ (0 | 1), len, code @ ..] if code.len() == *len as usize => {
+                Ok(RendezvousMessage::Register {
+                    code: String::from_utf8(code.to_vec())?,
+                    is_host: *role == 0,
+                })
+            }
+            [1, 4, ip @ .., port_hi, port_lo] if ip.len() == 4 => {
+                let ip: [u8; 4] = ip.try_into()?;
+                Ok(RendezvousMessage::Peer(SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::from(ip)),
+                    u16::from_be_bytes([*port_hi, *port_lo]),
+                )))
+            }
+            [1, 6, ip @ .., port_hi, port_lo] if ip.len() == 16 => {
+                let ip: [u8; 16] = ip.try_into()?;
+                Ok(RendezvousMessage::Peer(SocketAddr::new(
+                    IpAddr::V6(Ipv6Addr::from(ip)),
+                    u16::from_be_bytes([*port_hi, *port_lo]),
+                )))
+            }
+            _ => Err(anyhow!("Invalid rendezvous message")),
+        }
+    }
+
+    type Error = anyhow::Error;
+}
+
+impl From<&RendezvousMessage> for Vec<u8> {
+    fn from(msg: &RendezvousMessage) -> Self {
+        match msg {
+            RendezvousMessage::Register { code, is_host } => {
+                let mut bytes = vec![0, if *is_host { 0 } else { 1 }, code.len() as u8];
+                bytes.extend(code.as_bytes());
+                bytes
+            }
+            RendezvousMessage::Peer(address) => {
+                let mut bytes = vec![1];
+                match address.ip() {
+                    IpAddr::V4(ip) => {
+                        bytes.push(4);
+                        bytes.extend(ip.octets());
+                    }
+                    IpAddr::V6(ip) => {
+                        bytes.push(6);
+                        bytes.extend(ip.octets());
+                    }
+                }
+                bytes.extend(address.port().to_be_bytes());
+                bytes
+            }
+        }
+    }
+}
+
+// players registered under one game code
+#[derive(Default)]
+struct Registration {
+    host: Option<SocketAddr>,
+    joiner: Option<SocketAddr>,
+    updated: Option<Instant>,
+}
+
+/// Runs the rendezvous server on `socket` forever. Pairs up the host and joiner
+/// of each game code and tells both where to find the other.
+pub fn serve(socket: UdpSocket) -> anyhow::Result<()> {
+    socket.set_read_timeout(None)?;
+
+    let mut games: HashMap<String, Registration> = HashMap::new();
+    let mut buffer = [0; 512];
+
+    loop {
+        // one misbehaving player shouldn't take the server down for everyone
+        let Ok((len, from)) = socket.recv_from(&mut buffer) else {
+            continue;
+        };
+        let Ok(RendezvousMessage::Register { code, is_host }) =
+            RendezvousMessage::try_from(&buffer[..len])
+        else {
+            continue;
+        };
+
+        games.retain(|_, game| {
+            game.updated
+                .is_some_and(|t| t.elapsed() < REGISTRATION_EXPIRY)
+        });
+
+        let game = games.entry(code.clone()).or_default();
+        let slot = match is_host {
+            true => &mut game.host,
+            false => &mut game.joiner,
+        };
+        if *slot != Some(from) {
+            let role = if is_host { "host" } else { "joiner" };
+            println!("game {}: {} registered from {}", code, role, from);
+        }
+        *slot = Some(from);
+        game.updated = Some(Instant::now());
+
+        // answered every time, the players keep registering until they hear back
+        if let (Some(host), Some(joiner)) = (game.host, game.joiner) {
+            let _ = socket.send_to(&Vec::from(&RendezvousMessage::Peer(joiner)), host);
+            let _ = socket.send_to(&Vec::from(&RendezvousMessage::Peer(host)), joiner);
+        }
+    }
+}
+
+/// Registers `code` with the rendezvous server at `server` from `socket` and waits
+/// until the other player does the same. Returns the other player's public endpoint.
+pub fn find_peer(
+    socket: &UdpSocket,
+    server: &str,
+    code: &str,
+    is_host: bool,
+    timeout: Duration,
+) -> anyhow::Result<SocketAddr> {
+    if code.is_empty() || code.len() > MAX_CODE_LEN {
+        return Err(anyhow!(
+            "Game code must be between 1 and {} bytes",
+            MAX_CODE_LEN
+        ));
+    }
+
+    let server = server
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("Could not resolve rendezvous server {}", server))?;
+    let register: Vec<u8> = (&RendezvousMessage::Register {
+        code: code.to_string(),
+        is_host,
+    })
+        .into();
+
+    socket.set_read_timeout(Some(REGISTER_INTERVAL))?;
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0; 512];
+    while Instant::now() < deadline {
+        socket.send_to(&register, server)?;
+
+        // anything not from the server, like an eager peer, is ignored here
+        let resend_at = Instant::now() + REGISTER_INTERVAL;
+        while Instant::now() < resend_at {
+            let Ok((len, from)) = socket.recv_from(&mut buffer) else {
+                break;
+            };
+            if from != server {
+                continue;
+            }
+            if let Ok(RendezvousMessage::Peer(peer)) = RendezvousMessage::try_from(&buffer[..len]) {
+                return Ok(peer);
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "Nobody else registered game code '{}' in time",
+        code
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_message_conversions() {
+        let register = RendezvousMessage::Register {
+            code: String::from("pizza"),
+            is_host: false,
+        };
+        let bytes: Vec<u8> = (&register).into();
+        assert_eq!(bytes, vec![0, 1, 5, b'p', b'i', b'z', b'z', b'a']);
+        assert_eq!(
+            RendezvousMessage::try_from(bytes.as_slice()).unwrap(),
+            register
+        );
+
+        let peer = RendezvousMessage::Peer("192.168.1.20:1337".parse().unwrap());
+        let bytes: Vec<u8> = (&peer).into();
+        assert_eq!(bytes, vec![1, 4, 192, 168, 1, 20, 5, 57]);
+        assert_eq!(RendezvousMessage::try_from(bytes.as_slice()).unwrap(), peer);
+
+        let peer = RendezvousMessage::Peer("[::1]:80".parse().unwrap());
+        let bytes: Vec<u8> = (&peer).into();
+        assert_eq!(bytes.len(), 20);
+        assert_eq!(RendezvousMessage::try_from(bytes.as_slice()).unwrap(), peer);
+
+        // code length not adding up, unknown role and truncated address
+        assert!(RendezvousMessage::try_from([0, 0, 3, b'a'].as_slice()).is_err());
+        assert!(RendezvousMessage::try_from([0, 2, 1, b'a'].as_slice()).is_err());
+        assert!(RendezvousMessage::try_from([1, 4, 127, 0, 1].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_players_find_each_other() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_address = server.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(server));
+
+        let host = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let joiner = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (host_address, joiner_address) =
+            (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+
+        // someone else's game on the same server doesn't get in the way
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let other_server = server_address.clone();
+        thread::spawn(move || {
+            find_peer(&other, &other_server, "other", true, Duration::from_secs(5))
+        });
+
+        let host_server = server_address.clone();
+        let host_thread = thread::spawn(move || {
+            find_peer(&host, &host_server, "pizza", true, Duration::from_secs(5)).unwrap()
+        });
+        let found = find_peer(
+            &joiner,
+            &server_address,
+            "pizza",
+            false,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        assert_eq!(found, host_address);
+        assert_eq!(host_thread.join().unwrap(), joiner_address);
+    }
+
+    #[test]
+    fn test_nobody_else_registered() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_address = server.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(server));
+
+        let lonely = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let result = find_peer(
+            &lonely,
+            &server_address,
+            "lonely",
+            true,
+            Duration::from_millis(300),
+        );
+        assert!(result.is_err());
+
+        assert!(find_peer(
+            &lonely,
+            &server_address,
+            "",
+            true,
+            Duration::from_millis(300)
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    game::Player,
+    record::{Outcome, Record},
+};
+
+/// Fastest and slowest auto-play, `+` and `-` stay in between
+pub const MIN_DELAY: Duration = Duration::from_millis(100);
+pub const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Steps through a recorded game, the board shows its first `shown` moves
+#[derive(Debug)]
+pub struct Replay {
+    pub record: Record,
+    pub shown: usize,
+    /// Time between two moves while playing on its own
+    pub delay: Duration,
+    /// When auto-play last moved on, None while paused
+    stepped_at: Option<Instant>,
+}
+
+impl Replay {
+    /// Starts paused on the empty board
+    pub fn new(record: Record, delay: Duration) -> Self {
+        Replay {
+            record,
+            shown: 0,
+            delay,
+            stepped_at: None,
+        }
+    }
+
+    pub fn forward(&mut self) {
+        self.shown = (self.shown + 1).min(self.record.moves.len());
+    }
+
+    pub fn back(&mut self) {
+        self.shown = self.shown.saturating_sub(1);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.stepped_at.is_some()
+    }
+
+    /// Starts or pauses auto-play, starting it at the end plays the game again from the start
+    pub fn toggle_playing(&mut self) {
+        if self.is_playing() {
+            self.stepped_at = None;
+            return;
+        }
+
+        if self.shown == self.record.moves.len() {
+            self.shown = 0;
+        }
+        self.stepped_at = Some(Instant::now());
+    }
+
+    pub fn faster(&mut self) {
+        self.delay = (self.delay / 2).max(MIN_DELAY);
+    }
+
+    pub fn slower(&mut self) {
+        self.delay = (self.delay * 2).min(MAX_DELAY);
+    }
+
+    /// Moves on once auto-play is due, returns whether it did. Pauses after the last move.
+    pub fn tick(&mut self) -> bool {
+        match self.stepped_at {
+            Some(stepped_at) if stepped_at.elapsed() >= self.delay => (),
+            _ => return false,
+        }
+
+        self.forward();
+        self.stepped_at = match self.shown == self.record.moves.len() {
+            true => None,
+            false => Some(Instant::now()),
+        };
+        true
+    }
+
+    /// "Move 3 of 9", along with the result once the last move is shown
+    pub fn describe(&self) -> String {
+        let total = self.record.moves.len();
+        let position = format!("Move {} of {}", self.shown, total);
+        if self.shown < total {
+            return position;
+        }
+
+        let name = |player: Player| {
+            let name = match player {
+                Player::O => &self.record.o_name,
+                Player::X => &self.record.x_name,
+            };
+            match name.is_empty() {
+                true => format!("{:?}", player),
+                false => name.clone(),
+            }
+        };
+        let result = match self.record.outcome {
+            Outcome::Unfinished => String::from("unfinished"),
+            Outcome::Won(winner) => format!("{} won", name(winner)),
+            Outcome::WonOnTime(winner) => format!("{} won on time", name(winner)),
+            Outcome::Draw => String::from("draw"),
+        };
+        format!("{}, {}", position, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const RECORD: &str = "\
+tic-tac-term record 1
+rules 3 3
+o alice
+x bob
+1. O a1
+2. X b1
+3. O a2
+4. X b2
+5. O a3
+result o
+";
+
+    fn replay(delay: Duration) -> Replay {
+        Replay::new(RECORD.parse().unwrap(), delay)
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut replay = replay(Duration::from_secs(1));
+        assert_eq!(replay.describe(), "Move 0 of 5");
+
+        replay.back();
+        assert_eq!(replay.shown, 0);
+
+        for _ in 0..10 {
+            replay.forward();
+        }
+        assert_eq!(replay.shown, 5);
+        assert_eq!(replay.describe(), "Move 5 of 5, alice won");
+
+        replay.back();
+        assert_eq!(replay.describe(), "Move 4 of 5");
+
+        replay.record.o_name.clear();
+        replay.forward();
+        assert_eq!(replay.describe(), "Move 5 of 5, O won");
+    }
+
+    #[test]
+    fn test_auto_play() {
+        let mut replay = replay(MIN_DELAY);
+        assert!(!replay.tick());
+
+        replay.toggle_playing();
+        assert!(replay.is_playing());
+        assert!(!replay.tick());
+
+        thread::sleep(MIN_DELAY);
+        assert!(replay.tick());
+        assert_eq!(replay.shown, 1);
+
+        // pauses after the last move
+        replay.shown = 4;
+        thread::sleep(MIN_DELAY);
+        assert!(replay.tick());
+        assert_eq!(replay.shown, 5);
+        assert!(!replay.is_playing());
+
+        // and starts over from there
+        replay.toggle_playing();
+        assert_eq!(replay.shown, 0);
+        replay.toggle_playing();
+        assert!(!replay.is_playing());
+    }
+
+    #[test]
+    fn test_speed() {
+        let mut replay = replay(Duration::from_secs(1));
+        replay.faster();
+        assert_eq!(replay.delay, Duration::from_millis(500));
+
+        for _ in 0..10 {
+            replay.faster();
+        }
+        assert_eq!(replay.delay, MIN_DELAY);
+        for _ in 0..10 {
+            replay.slower();
+        }
+        assert_eq!(replay.delay, MAX_DELAY);
+    }
+}
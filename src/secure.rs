@@ -0,0 +1,184 @@
+use anyhow::anyhow;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Random bytes each side contributes to the keys of a connection
+pub const NONCE_LEN: usize = 16;
+
+/// Deliberately slow so guessing passphrases from a recorded game is expensive
+const PBKDF2_ROUNDS: u32 = 100_000;
+const PBKDF2_SALT: &[u8] = b"tic-tac-term secret";
+
+/// Key derived from the `--secret` passphrase both players have to know
+#[derive(Clone)]
+pub struct Secret([u8; 32]);
+
+impl Secret {
+    pub fn new(passphrase: &str) -> Self {
+        let mut key = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), PBKDF2_SALT, PBKDF2_ROUNDS, &mut key);
+        Secret(key)
+    }
+
+    /// Keys for a single connection. Each side sends with a key made from its own
+    /// nonce followed by the other side's, so the two directions never share a key
+    /// and frames recorded on an earlier connection can't be played back on this one.
+    pub fn session_keys(&self, ours: &[u8; NONCE_LEN], theirs: &[u8; NONCE_LEN]) -> SessionKeys {
+        SessionKeys {
+            sealer: Sealer {
+                cipher: self.cipher(ours, theirs),
+                counter: 0,
+            },
+            opener: Opener {
+                cipher: self.cipher(theirs, ours),
+                counter: 0,
+            },
+        }
+    }
+
+    fn cipher(&self, sender: &[u8; NONCE_LEN], receiver: &[u8; NONCE_LEN]) -> ChaCha20Poly1305 {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0).expect("any key length works");
+        mac.update(sender);
+        mac.update(receiver);
+        let key = mac.finalize().into_bytes();
+
+        ChaCha20Poly1305::new(Key::from_slice(&key))
+    }
+}
+
+pub fn new_nonce() -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Tells a udp connection's packets apart from an earlier one's between the same two sockets
+pub fn new_session_id() -> u32 {
+    OsRng.next_u32()
+}
+
+/// Random bytes a player greets with on every connection of a game, coming back
+/// to a seat takes the same ones the seat was first taken with
+pub const TOKEN_LEN: usize = 16;
+
+pub fn new_token() -> [u8; TOKEN_LEN] {
+    let mut token = [0; TOKEN_LEN];
+    OsRng.fill_bytes(&mut token);
+    token
+}
+
+pub struct SessionKeys {
+    pub sealer: Sealer,
+    pub opener: Opener,
+}
+
+// the nth message in a direction is sealed with n as its nonce, so the
+// receiver only accepts every frame once and in the order it was sent
+fn counter_nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    Nonce::from(nonce)
+}
+
+/// Encrypts and authenticates outgoing messages
+pub struct Sealer {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Sealer {
+    pub fn seal(&mut self, plain: &[u8]) -> Vec<u8> {
+        let sealed = self
+            .cipher
+            .encrypt(&counter_nonce(self.counter), plain)
+            .expect("messages are far below the size limit");
+        self.counter += 1;
+        sealed
+    }
+}
+
+/// Checks and decrypts incoming messages
+pub struct Opener {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Opener {
+    /// Fails for frames that were tampered with, replayed, reordered or sealed with another secret
+    pub fn open(&mut self, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let plain = self
+            .cipher
+            .decrypt(&counter_nonce(self.counter), sealed)
+            .map_err(|_| anyhow!("Message failed authentication"))?;
+        self.counter += 1;
+        Ok(plain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::OnceLock;
+
+    // deriving is slow on purpose, once is enough
+    fn test_secret() -> &'static Secret {
+        static SECRET: OnceLock<Secret> = OnceLock::new();
+        SECRET.get_or_init(|| Secret::new("hunter2"))
+    }
+
+    fn pair(host: &Secret, joiner: &Secret) -> (SessionKeys, SessionKeys) {
+        let (host_nonce, joiner_nonce) = (new_nonce(), new_nonce());
+        (
+            host.session_keys(&host_nonce, &joiner_nonce),
+            joiner.session_keys(&joiner_nonce, &host_nonce),
+        )
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let secret = test_secret();
+        let (mut host, mut joiner) = pair(secret, secret);
+
+        let sealed = host.sealer.seal(b"move");
+        // plus the 16 byte authentication tag
+        assert_eq!(sealed.len(), 4 + 16);
+        assert_ne!(&sealed[..4], b"move");
+        assert_eq!(joiner.opener.open(&sealed).unwrap(), b"move");
+
+        // and the other way around
+        let sealed = joiner.sealer.seal(b"reply");
+        assert_eq!(host.opener.open(&sealed).unwrap(), b"reply");
+    }
+
+    #[test]
+    fn test_tampered_and_replayed_frames_are_rejected() {
+        let secret = test_secret();
+        let (mut host, mut joiner) = pair(secret, secret);
+
+        let mut tampered = host.sealer.seal(b"move");
+        tampered[0] ^= 1;
+        assert!(joiner.opener.open(&tampered).is_err());
+
+        let (mut host, mut joiner) = pair(secret, secret);
+        let first = host.sealer.seal(b"first");
+        let second = host.sealer.seal(b"second");
+        assert!(joiner.opener.open(&second).is_err());
+        assert!(joiner.opener.open(&first).is_ok());
+        assert!(joiner.opener.open(&first).is_err());
+        assert!(joiner.opener.open(&second).is_ok());
+
+        // a frame from another connection
+        let (mut other_host, _) = pair(secret, secret);
+        assert!(joiner.opener.open(&other_host.sealer.seal(b"old")).is_err());
+    }
+
+    #[test]
+    fn test_different_secrets_dont_match() {
+        let (mut host, mut joiner) = pair(test_secret(), &Secret::new("hunter3"));
+        assert!(joiner.opener.open(&host.sealer.seal(b"move")).is_err());
+    }
+}
@@ -0,0 +1,459 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    config,
+    game::{Mode, Player},
+    rating::{self, DEFAULT_RATING},
+    record::{self, Outcome},
+};
+
+/// Version written into the stats file, bumped when older builds can't read the format
+pub const STATS_VERSION: u32 = 1;
+
+/// How a finished game went for the player at this terminal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameResult {
+    Win,
+    Loss,
+    Draw,
+}
+
+impl GameResult {
+    /// None while the game is still going
+    pub fn new(outcome: Outcome, player: Player) -> Option<Self> {
+        match outcome {
+            Outcome::Unfinished => None,
+            Outcome::Won(winner) | Outcome::WonOnTime(winner) if winner == player => {
+                Some(GameResult::Win)
+            }
+            Outcome::Won(_) | Outcome::WonOnTime(_) => Some(GameResult::Loss),
+            Outcome::Draw => Some(GameResult::Draw),
+        }
+    }
+
+    /// The same game from the other side of the board
+    pub fn for_opponent(self) -> Self {
+        match self {
+            GameResult::Win => GameResult::Loss,
+            GameResult::Loss => GameResult::Win,
+            GameResult::Draw => GameResult::Draw,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Tally {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Tally {
+    fn add(&mut self, result: GameResult) {
+        match result {
+            GameResult::Win => self.wins += 1,
+            GameResult::Loss => self.losses += 1,
+            GameResult::Draw => self.draws += 1,
+        }
+    }
+}
+
+/// Results of every game played on this machine, kept across sessions
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    pub modes: BTreeMap<String, Tally>,
+    pub opponents: BTreeMap<String, Tally>,
+    /// Wins in a row when positive, losses in a row when negative
+    pub streak: i32,
+    /// Most wins in a row so far
+    pub best_streak: u32,
+    /// Elo rating of the player at this terminal
+    pub rating: u16,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            modes: BTreeMap::new(),
+            opponents: BTreeMap::new(),
+            streak: 0,
+            best_streak: 0,
+            rating: DEFAULT_RATING,
+        }
+    }
+}
+
+impl Stats {
+    /// Counts a finished game, for the opponent too when there is one to name.
+    /// Games against a rated opponent update the rating.
+    pub fn record(
+        &mut self,
+        mode: &Mode,
+        opponent: Option<&str>,
+        opponent_rating: Option<u16>,
+        result: GameResult,
+    ) {
+        self.modes
+            .entry(mode_name(mode).to_string())
+            .or_default()
+            .add(result);
+        if let Some(opponent) = opponent {
+            self.opponents
+                .entry(record::one_line(opponent))
+                .or_default()
+                .add(result);
+        }
+
+        self.streak = match result {
+            GameResult::Win => self.streak.max(0).saturating_add(1),
+            GameResult::Loss => self.streak.min(0).saturating_sub(1),
+            GameResult::Draw => 0,
+        };
+        self.best_streak = self.best_streak.max(self.streak.max(0) as u32);
+
+        if let Some(opponent_rating) = opponent_rating {
+            self.rating = rating::updated(self.rating, opponent_rating, result);
+        }
+    }
+
+    /// Reads the stats file, no file yet means no games played
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => text
+                .parse()
+                .map_err(|reason| anyhow!("Can't read stats from {}: {}", path.display(), reason)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Stats::default()),
+            Err(error) => Err(anyhow!("Can't read {}: {}", path.display(), error)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+            .map_err(|reason| anyhow!("Can't save stats to {}: {}", path.display(), reason))
+    }
+
+    /// Table of results per mode and per opponent, followed by the streaks
+    pub fn summary(&self) -> String {
+        if self.modes.is_empty() {
+            return String::from("No games played yet\n");
+        }
+
+        // both tables line up
+        let width = self
+            .modes
+            .keys()
+            .chain(self.opponents.keys())
+            .map(|name| name.chars().count())
+            .chain(["Opponent".len()])
+            .max()
+            .unwrap_or_default();
+
+        let mut summary = table("Mode", &self.modes, width);
+        if !self.opponents.is_empty() {
+            summary.push('\n');
+            summary.push_str(&table("Opponent", &self.opponents, width));
+        }
+
+        let streak = match self.streak {
+            0 => String::from("none"),
+            1 => String::from("1 win"),
+            -1 => String::from("1 loss"),
+            wins if wins > 0 => format!("{} wins", wins),
+            losses => format!("{} losses", -losses),
+        };
+        summary.push_str(&format!(
+            "\nCurrent streak: {}, most wins in a row: {}\nRating: {}\n",
+            streak, self.best_streak, self.rating
+        ));
+        summary
+    }
+}
+
+// names on the left, one column per result
+fn table(title: &str, rows: &BTreeMap<String, Tally>, width: usize) -> String {
+    let mut table = format!(
+        "{:<width$}  {:>6}  {:>6}  {:>6}\n",
+        title,
+        "Wins",
+        "Losses",
+        "Draws",
+        width = width
+    );
+    for (name, tally) in rows {
+        table.push_str(&format!(
+            "{:<width$}  {:>6}  {:>6}  {:>6}\n",
+            name,
+            tally.wins,
+            tally.losses,
+            tally.draws,
+            width = width
+        ));
+    }
+    table
+}
+
+/// Name a mode is counted under
+pub fn mode_name(mode: &Mode) -> &'static str {
+    match mode {
+        Mode::Local => "local",
+        Mode::VsComputer => "computer",
+        Mode::Network => "network",
+        Mode::Spectator => "spectator",
+        Mode::Replay => "replay",
+    }
+}
+
+/// Where the stats are kept, None when there is neither `$XDG_DATA_HOME` nor `$HOME`
+pub fn path() -> Option<PathBuf> {
+    stats_path(env::var_os("XDG_DATA_HOME"), env::var_os("HOME"))
+}
+
+fn stats_path(data_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    let data_home = config::xdg_dir(data_home, home, ".local/share")?;
+    Some(data_home.join("tic-tac-term").join("stats.txt"))
+}
+
+/// Text format, one item per line, blank lines and lines starting with `#` are skipped:
+/// - `tic-tac-term stats 1`, always the first line, the number is the version
+/// - `streak 3` with wins in a row, or losses in a row when negative
+/// - `best-streak 5`, the most wins in a row
+/// - `rating 1216`, the Elo rating
+/// - `mode local 3 1 0` with wins, losses and draws in a mode
+/// - `opponent 2 1 1 bob` with wins, losses and draws against someone, the name goes
+///   last since it may contain spaces
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "tic-tac-term stats {}", STATS_VERSION)?;
+        writeln!(f, "streak {}", self.streak)?;
+        writeln!(f, "best-streak {}", self.best_streak)?;
+        writeln!(f, "rating {}", self.rating)?;
+        for (mode, tally) in &self.modes {
+            writeln!(
+                f,
+                "mode {} {} {} {}",
+                mode, tally.wins, tally.losses, tally.draws
+            )?;
+        }
+        for (name, tally) in &self.opponents {
+            writeln!(
+                f,
+                "opponent {} {} {} {}",
+                tally.wins, tally.losses, tally.draws, name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Stats {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut lines = s
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        let version = lines
+            .next()
+            .and_then(|(_, line)| line.strip_prefix("tic-tac-term stats "))
+            .ok_or_else(|| anyhow!("Not a tic-tac-term stats file"))?;
+        match version.parse::<u32>() {
+            Ok(STATS_VERSION) => (),
+            _ => return Err(anyhow!("Stats version {} is not supported", version)),
+        }
+
+        let mut stats = Stats::default();
+        for (index, line) in lines {
+            let at_line = |reason: &str| anyhow!("Line {}: {}", index + 1, reason);
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+
+            match key {
+                "streak" => {
+                    stats.streak = value
+                        .parse()
+                        .map_err(|_| at_line("streak is not a number"))?
+                }
+                "best-streak" => {
+                    stats.best_streak = value
+                        .parse()
+                        .map_err(|_| at_line("best streak is not a number"))?
+                }
+                "rating" => {
+                    stats.rating = value
+                        .parse()
+                        .ok()
+                        .filter(|rating| *rating > 0)
+                        .ok_or_else(|| at_line("rating is not a number"))?
+                }
+                "mode" => {
+                    let (mode, counts) = value.split_once(' ').unwrap_or((value, ""));
+                    let tally = parse_tally(counts).ok_or_else(|| at_line("expected 3 counts"))?;
+                    stats.modes.insert(mode.to_string(), tally);
+                }
+                "opponent" => {
+                    let mut parts = value.splitn(4, ' ');
+                    let counts: Vec<&str> = parts.by_ref().take(3).collect();
+                    let tally = parse_tally(&counts.join(" "))
+                        .ok_or_else(|| at_line("expected 3 counts"))?;
+                    let name = parts.next().unwrap_or_default();
+                    stats.opponents.insert(name.to_string(), tally);
+                }
+                _ => return Err(at_line(&format!("unknown item {}", key))),
+            }
+        }
+        Ok(stats)
+    }
+}
+
+// "3 1 0" as wins, losses and draws
+fn parse_tally(counts: &str) -> Option<Tally> {
+    let counts: Vec<u32> = counts
+        .split(' ')
+        .map(|count| count.parse().ok())
+        .collect::<Option<_>>()?;
+    match counts[..] {
+        [wins, losses, draws] => Some(Tally {
+            wins,
+            losses,
+            draws,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_results() {
+        let mut stats = Stats::default();
+        stats.record(&Mode::Network, Some("bob"), None, GameResult::Win);
+        stats.record(&Mode::Network, Some("bob"), None, GameResult::Win);
+        stats.record(&Mode::VsComputer, Some("computer"), None, GameResult::Draw);
+        stats.record(&Mode::Local, None, None, GameResult::Loss);
+
+        assert_eq!(
+            stats.modes["network"],
+            Tally {
+                wins: 2,
+                losses: 0,
+                draws: 0
+            }
+        );
+        assert_eq!(stats.modes["local"].losses, 1);
+        assert_eq!(stats.opponents.len(), 2);
+        assert_eq!(stats.opponents["computer"].draws, 1);
+        assert_eq!(stats.streak, -1);
+        assert_eq!(stats.best_streak, 2);
+
+        stats.record(&Mode::Network, Some("bob"), None, GameResult::Win);
+        assert_eq!(stats.streak, 1);
+        assert_eq!(stats.best_streak, 2);
+        // nobody was rated so far
+        assert_eq!(stats.rating, DEFAULT_RATING);
+
+        stats.record(
+            &Mode::VsComputer,
+            Some("computer"),
+            Some(1600),
+            GameResult::Win,
+        );
+        assert_eq!(stats.rating, 1229);
+    }
+
+    #[test]
+    fn test_result_of_outcome() {
+        let won = Outcome::WonOnTime(Player::X);
+        assert_eq!(GameResult::new(won, Player::X), Some(GameResult::Win));
+        assert_eq!(GameResult::new(won, Player::O), Some(GameResult::Loss));
+        let drawn = Outcome::Draw;
+        assert_eq!(GameResult::new(drawn, Player::O), Some(GameResult::Draw));
+        assert_eq!(GameResult::new(Outcome::Unfinished, Player::O), None);
+        assert_eq!(GameResult::Win.for_opponent(), GameResult::Loss);
+        assert_eq!(GameResult::Draw.for_opponent(), GameResult::Draw);
+    }
+
+    #[test]
+    fn test_stats_file() {
+        let mut stats = Stats::default();
+        stats.record(
+            &Mode::Network,
+            Some("bob smith"),
+            Some(1200),
+            GameResult::Win,
+        );
+        stats.record(&Mode::Local, None, None, GameResult::Draw);
+
+        let text = stats.to_string();
+        assert_eq!(
+            text,
+            "tic-tac-term stats 1\nstreak 0\nbest-streak 1\nrating 1216\nmode local 0 0 1\n\
+             mode network 1 0 0\nopponent 1 0 0 bob smith\n"
+        );
+        assert_eq!(text.parse::<Stats>().unwrap(), stats);
+
+        for broken in [
+            "",
+            "tic-tac-term stats 2\n",
+            "tic-tac-term stats 1\nmode local 1 2\n",
+            "tic-tac-term stats 1\nopponent 1 2 x bob\n",
+            "tic-tac-term stats 1\nstreak many\n",
+            "tic-tac-term stats 1\nelo 1200\n",
+            "tic-tac-term stats 1\nrating 0\n",
+        ] {
+            assert!(broken.parse::<Stats>().is_err(), "{:?}", broken);
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.summary(), "No games played yet\n");
+
+        stats.record(&Mode::Network, Some("bob"), None, GameResult::Loss);
+        stats.record(&Mode::Network, Some("bob"), None, GameResult::Loss);
+        assert_eq!(
+            stats.summary(),
+            "\
+Mode        Wins  Losses   Draws
+network        0       2       0
+
+Opponent    Wins  Losses   Draws
+bob            0       2       0
+
+Current streak: 2 losses, most wins in a row: 0
+Rating: 1200
+"
+        );
+    }
+
+    #[test]
+    fn test_stats_path() {
+        let path = stats_path(Some("/data".into()), Some("/home/alice".into()));
+        assert_eq!(path, Some(PathBuf::from("/data/tic-tac-term/stats.txt")));
+
+        let path = stats_path(Some("relative".into()), Some("/home/alice".into()));
+        assert_eq!(
+            path,
+            Some(PathBuf::from(
+                "/home/alice/.local/share/tic-tac-term/stats.txt"
+            ))
+        );
+        assert_eq!(stats_path(None, None), None);
+    }
+}
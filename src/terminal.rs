@@ -11,13 +11,21 @@ use std::{
     time::Duration,
 };
 
-use super::game;
+use super::{config::Action, game, input::Key};
 
 pub enum Ansi {
     HideCursor,           // "\x1B[?25l"
     ShowCursor,           // "\x1B[?25h"
     ClearScreen,          //  "\x1B[2J"
     MoveCursor(u16, u16), // "\x1B[%d;%dH" %d num
+    Reverse,              // "\x1B[7m"
+    Bold,                 // "\x1B[1m"
+    Underline,            // "\x1B[4m"
+    Blink,                // "\x1B[5m"
+    Foreground(Color),    // "\x1B[%dm" %d 31-37 or 91-97
+    ResetStyle,           // "\x1B[0m"
+    EnableMouse,          // "\x1B[?1000h\x1B[?1006h" clicks reported as SGR sequences
+    DisableMouse,         // "\x1B[?1000l\x1B[?1006l"
 }
 
 impl fmt::Display for Ansi {
@@ -27,6 +35,37 @@ impl fmt::Display for Ansi {
             Ansi::ShowCursor => write!(f, "\x1B[?25h"),
             Ansi::ClearScreen => write!(f, "\x1B[2J"),
             Ansi::MoveCursor(x, y) => write!(f, "\x1B[{};{}H", y, x),
+            Ansi::Reverse => write!(f, "\x1B[7m"),
+            Ansi::Bold => write!(f, "\x1B[1m"),
+            Ansi::Underline => write!(f, "\x1B[4m"),
+            Ansi::Blink => write!(f, "\x1B[5m"),
+            Ansi::Foreground(color) => write!(f, "\x1B[{}m", color.code()),
+            Ansi::ResetStyle => write!(f, "\x1B[0m"),
+            Ansi::EnableMouse => write!(f, "\x1B[?1000h\x1B[?1006h"),
+            Ansi::DisableMouse => write!(f, "\x1B[?1000l\x1B[?1006l"),
+        }
+    }
+}
+
+/// Terminal colours the themes use, the bright ones stand out more on dark backgrounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Red,
+    Green,
+    Cyan,
+    BrightYellow,
+    BrightCyan,
+}
+
+impl Color {
+    // SGR foreground codes, the bright colours are 60 above the normal ones
+    fn code(self) -> u8 {
+        match self {
+            Color::Red => 31,
+            Color::Green => 32,
+            Color::Cyan => 36,
+            Color::BrightYellow => 93,
+            Color::BrightCyan => 96,
         }
     }
 }
@@ -35,9 +74,11 @@ static ORIGINAL_TERM: OnceLock<Mutex<termios>> = OnceLock::new();
 
 pub fn init() {
     enable_raw_mode();
+    print!("{}", Ansi::EnableMouse);
+    let _ = io::stdout().flush();
     unsafe {
-        signal(SIGINT, handle_signal as usize);
-        signal(SIGTERM, handle_signal as usize);
+        signal(SIGINT, handle_signal as *const () as usize);
+        signal(SIGTERM, handle_signal as *const () as usize);
     }
 }
 
@@ -73,6 +114,11 @@ pub fn get_size() -> (u16, u16) {
         ioctl(STDOUT_FILENO, TIOCGWINSZ, &mut size);
     }
 
+    // not attached to a terminal (e.g. piped output), assume a classic 80x24
+    if size.ws_col == 0 || size.ws_row == 0 {
+        return (80, 24);
+    }
+
     (size.ws_col, size.ws_row)
 }
 
@@ -80,80 +126,209 @@ pub extern "C" fn handle_signal(_signal: c_int) {
     restore_and_exit();
 }
 
-fn restore_and_exit() {
-    // clean up and restore cursor
+pub fn restore() {
+    // clean up and restore cursor, the shell would otherwise get the clicks
+    print!("{}", Ansi::DisableMouse);
     print!("{}", Ansi::ClearScreen);
     print!("{}", Ansi::ShowCursor);
     print!("{}", Ansi::MoveCursor(1, 1));
-    std::io::stdout().flush().unwrap();
+    let _ = std::io::stdout().flush();
 
     disable_raw_mode();
+}
+
+fn restore_and_exit() {
+    restore();
 
     std::process::exit(0)
 }
 
-fn move_cursor(game: &mut game::Game, term_rx: &mpsc::Receiver<u8>) {
-    let Ok(first_byte) = term_rx.recv_timeout(Duration::from_millis(10)) else {
+fn move_cursor(game: &mut game::Game, action: Action) {
+    let (current_x, current_y) = game.cursor_pos;
+    let (max_x, max_y) = get_size();
+
+    if game.free_cursor {
+        game.cursor_pos = match action {
+            Action::MoveUp => (current_x, cmp::max(current_y - 1, 1)),
+            Action::MoveDown => (current_x, cmp::min(current_y + 1, max_y)),
+            Action::MoveRight => (cmp::min(current_x + 1, max_x), current_y),
+            Action::MoveLeft => (cmp::max(current_x - 1, 1), current_y),
+            Action::RowStart => (1, current_y),
+            Action::RowEnd => (max_x, current_y),
+            _ => return,
+        };
+    } else if let Some(index) = game.cursor_cell() {
+        let size = game.board_size() as usize;
+        let (row, col) = (index / size, index % size);
+        let (row, col) = match action {
+            Action::MoveUp => (row.saturating_sub(1), col),
+            Action::MoveDown => (cmp::min(row + 1, size - 1), col),
+            Action::MoveRight => (row, cmp::min(col + 1, size - 1)),
+            Action::MoveLeft => (row, col.saturating_sub(1)),
+            Action::RowStart => (row, 0),
+            Action::RowEnd => (row, size - 1),
+            _ => return,
+        };
+        game.cursor_pos = game.symbol_slots[row * size + col];
+    }
+}
+
+// moves the cursor to `cell` and places the current player's mark there
+fn place_at(game: &mut game::Game, cell: usize) {
+    game.cursor_pos = game.symbol_slots[cell];
+    game.attempt_placing(char::from(game.get_current_player()));
+}
+
+// clicking a cell places a mark like moving there and pressing space
+fn click(game: &mut game::Game, pos: (u16, u16)) {
+    match game.cell_at(pos) {
+        Some(cell) => place_at(game, cell),
+        None if game.free_cursor => game.cursor_pos = pos,
+        None => (),
+    }
+}
+
+// the digits are laid out like a numpad, 7 is the top left cell and 3 the bottom right.
+// On ultimate boards they pick a cell of the small board under the cursor.
+fn numpad_cell(game: &game::Game, digit: char) -> Option<usize> {
+    let digit = digit.to_digit(10)? as usize;
+    let (row, col) = (2 - (digit - 1) / 3, (digit - 1) % 3);
+    match (&game.ultimate, game.board_size()) {
+        (Some(_), size) => {
+            let index = game.cursor_cell()?;
+            let size = size as usize;
+            let (top, left) = (index / size / 3 * 3, index % size / 3 * 3);
+            Some((top + row) * size + left + col)
+        }
+        (None, 3) => Some(row * 3 + col),
+        (None, _) => None,
+    }
+}
+
+// while the chat input is open every key is text, except for these
+fn type_chat(game: &mut game::Game, key: Key) {
+    let name = game.name.clone();
+    let Some(chat) = &mut game.chat else {
         return;
     };
 
-    if first_byte != b'[' {
-        return;
+    match key {
+        Key::Enter => chat.submit(&name),
+        Key::Backspace => chat.backspace(),
+        Key::Escape => chat.cancel(),
+        Key::Char(c) => {
+            for byte in c.to_string().bytes() {
+                chat.type_byte(byte);
+            }
+        }
+        _ => (),
     }
+}
 
-    let Ok(second_byte) = term_rx.recv_timeout(Duration::from_millis(10)) else {
+// replays are read-only, the arrows step through the moves instead of moving the cursor
+fn step_replay(game: &mut game::Game, key: Key) {
+    let Some(replay) = &mut game.replay else {
         return;
     };
 
-    let (current_x, current_y) = game.cursor_pos;
-    let (max_x, max_y) = get_size();
-
-    if game.free_cursor {
-        match [first_byte, second_byte] {
-            [b'[', b'A'] => game.cursor_pos = (current_x, cmp::max(current_y - 1, 1)),
-            [b'[', b'B'] => game.cursor_pos = (current_x, cmp::min(current_y + 1, max_y)),
-            [b'[', b'C'] => game.cursor_pos = (cmp::min(current_x + 1, max_x), current_y),
-            [b'[', b'D'] => game.cursor_pos = (cmp::max(current_x - 1, 1), current_y),
-            _ => (),
-        }
-    } else if game.symbol_slots.contains(&game.cursor_pos) {
-        match [first_byte, second_byte] {
-            [b'[', b'A'] => game.cursor_pos = (current_x, cmp::max(current_y - 2, 2)),
-            [b'[', b'B'] => game.cursor_pos = (current_x, cmp::min(current_y + 2, 6)),
-            [b'[', b'C'] => game.cursor_pos = (cmp::min(current_x + 4, 11), current_y),
-            [b'[', b'D'] => game.cursor_pos = (cmp::max(current_x.saturating_sub(4), 3), current_y),
-            _ => (),
+    match game.keys.action(key) {
+        Some(Action::Quit) => {
+            game.quitting = true;
+            return;
         }
+        Some(Action::MoveRight) => replay.forward(),
+        Some(Action::MoveLeft) => replay.back(),
+        Some(Action::RowStart) => replay.shown = 0,
+        Some(Action::RowEnd) => replay.shown = replay.record.moves.len(),
+        Some(Action::Place) => replay.toggle_playing(),
+        Some(Action::Faster) => replay.faster(),
+        Some(Action::Slower) => replay.slower(),
+        _ => return,
     }
+    game.show_replay();
 }
 
 pub fn process_input(game: &mut game::Game, term_rx: &mpsc::Receiver<u8>) -> anyhow::Result<()> {
-    match term_rx.recv_timeout(Duration::from_millis(33))? {
-        b'q' => restore_and_exit(),
-        b's' => println!("{}", Ansi::ShowCursor),
-        b'h' => println!("{}", Ansi::HideCursor),
-        b'f' => game.free_cursor = !game.free_cursor,
-        b'r' => game.restart(),
-        b'x' => game.attempt_placing('X'),
-        b'o' => game.attempt_placing('O'),
-        b' ' => game.attempt_placing(char::from(game.get_current_player())),
-        b'\x1B' => move_cursor(game, term_rx),
-        _ => (),
+    let key = match term_rx.recv_timeout(Duration::from_millis(33)) {
+        Ok(byte) => game.input.read(byte, term_rx),
+        // the terminal went quiet, whatever was left half read is over
+        Err(_) if game.input.is_pending() => game.input.flush(),
+        Err(err) => return Err(err.into()),
+    };
+    let Some(key) = key else {
+        return Ok(());
+    };
+
+    if game.replay.is_some() {
+        step_replay(game, key);
+        return Ok(());
+    }
+    if game.chat.as_ref().is_some_and(|chat| chat.is_typing()) {
+        type_chat(game, key);
+        return Ok(());
     }
 
-    Ok(())
-}
+    let Some(action) = game.keys.action(key) else {
+        // digits and clicks pick a cell directly, those aren't bound
+        match key {
+            Key::Char(digit @ '1'..='9') => {
+                if let Some(cell) = numpad_cell(game, digit) {
+                    place_at(game, cell);
+                }
+            }
+            Key::Click(x, y) => click(game, (x, y)),
+            _ => (),
+        }
+        return Ok(());
+    };
 
-pub fn print_debug<T: fmt::Debug>(data: T) {
-    print!("{}", Ansi::MoveCursor(1, get_size().1 - 4));
-    println!("{:?}", data);
-    io::stdout().flush().unwrap();
+    match action {
+        // main loop says goodbye to the other player and restores the terminal
+        Action::Quit => game.quitting = true,
+        Action::HideCursor => {
+            game.cursor_hidden = !game.cursor_hidden;
+            match game.cursor_hidden {
+                true => println!("{}", Ansi::HideCursor),
+                false => println!("{}", Ansi::ShowCursor),
+            }
+        }
+        Action::FreeCursor => game.free_cursor = !game.free_cursor,
+        Action::Chat => {
+            if let Some(chat) = &mut game.chat {
+                chat.open();
+            }
+        }
+        Action::Restart => game.restart(),
+        Action::Undo => game.undo(),
+        Action::Redo => game.redo(),
+        Action::PlaceX => game.attempt_placing('X'),
+        Action::PlaceO => game.attempt_placing('O'),
+        Action::Place => game.attempt_placing(char::from(game.get_current_player())),
+        // scroll the move list
+        Action::ScrollUp => game.history.scroll_up(),
+        Action::ScrollDown => game.history.scroll_down(),
+        // only replays have a speed
+        Action::Faster | Action::Slower => (),
+        Action::MoveUp
+        | Action::MoveDown
+        | Action::MoveLeft
+        | Action::MoveRight
+        | Action::RowStart
+        | Action::RowEnd => move_cursor(game, action),
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::game::{Game, Mode, Player};
+    use crate::{
+        chat::Chat,
+        config::Config,
+        game::{Game, Mode, Player, Rules},
+        replay::Replay,
+    };
     use std::sync::mpsc;
 
     #[test]
@@ -162,59 +337,57 @@ mod tests {
         assert_eq!(format!("{}", Ansi::ShowCursor), "\x1B[?25h");
         assert_eq!(format!("{}", Ansi::ClearScreen), "\x1B[2J");
         assert_eq!(format!("{}", Ansi::MoveCursor(10, 20)), "\x1B[20;10H");
+        assert_eq!(format!("{}", Ansi::Reverse), "\x1B[7m");
+        assert_eq!(format!("{}", Ansi::Bold), "\x1B[1m");
+        assert_eq!(format!("{}", Ansi::Underline), "\x1B[4m");
+        assert_eq!(format!("{}", Ansi::Blink), "\x1B[5m");
+        assert_eq!(format!("{}", Ansi::Foreground(Color::Red)), "\x1B[31m");
+        assert_eq!(
+            format!("{}", Ansi::Foreground(Color::BrightCyan)),
+            "\x1B[96m"
+        );
+        assert_eq!(format!("{}", Ansi::ResetStyle), "\x1B[0m");
+        assert_eq!(format!("{}", Ansi::EnableMouse), "\x1B[?1000h\x1B[?1006h");
+        assert_eq!(format!("{}", Ansi::DisableMouse), "\x1B[?1000l\x1B[?1006l");
     }
 
     #[test]
     fn test_move_cursor_free_mode() {
-        let (tx, rx) = mpsc::channel();
-        let mut game = Game::new(Mode::Local, true);
+        let mut game = Game::new(Mode::Local, true, Rules::default());
         game.free_cursor = true;
         game.cursor_pos = (5, 5);
 
         // arrow up
-        tx.send(b'[').unwrap();
-        tx.send(b'A').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveUp);
         assert_eq!(game.cursor_pos, (5, 4));
 
         // arrow down
-        tx.send(b'[').unwrap();
-        tx.send(b'B').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveDown);
         assert_eq!(game.cursor_pos, (5, 5));
 
         // arrow right
-        tx.send(b'[').unwrap();
-        tx.send(b'C').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveRight);
         assert_eq!(game.cursor_pos, (6, 5));
 
         // arrow left
-        tx.send(b'[').unwrap();
-        tx.send(b'D').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveLeft);
         assert_eq!(game.cursor_pos, (5, 5));
 
         // boundary conditions
         game.cursor_pos = (1, 1);
-        tx.send(b'[').unwrap();
-        tx.send(b'D').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveLeft);
         assert_eq!(game.cursor_pos, (1, 1)); // should not go below 1
 
-        tx.send(b'[').unwrap();
-        tx.send(b'A').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveUp);
         assert_eq!(game.cursor_pos, (1, 1)); // should not go below 1
     }
 
     #[test]
     fn test_move_cursor_fixed_mode() {
-        let (tx, rx) = mpsc::channel();
-        let mut game = Game::new(Mode::Local, true);
+        let mut game = Game::new(Mode::Local, true, Rules::default());
 
         // set up symbol slots
-        game.symbol_slots = [
+        game.symbol_slots = vec![
             (3, 2),
             (7, 2),
             (11, 2),
@@ -230,34 +403,43 @@ mod tests {
         game.cursor_pos = (7, 4); // middle slot
 
         // arrow up
-        tx.send(b'[').unwrap();
-        tx.send(b'A').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveUp);
         assert_eq!(game.cursor_pos, (7, 2));
 
         // arrow down
-        tx.send(b'[').unwrap();
-        tx.send(b'B').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveDown);
         assert_eq!(game.cursor_pos, (7, 4));
 
         // arrow right
-        tx.send(b'[').unwrap();
-        tx.send(b'C').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveRight);
         assert_eq!(game.cursor_pos, (11, 4));
 
         // arrow left
-        tx.send(b'[').unwrap();
-        tx.send(b'D').unwrap();
-        move_cursor(&mut game, &rx);
+        move_cursor(&mut game, Action::MoveLeft);
         assert_eq!(game.cursor_pos, (7, 4));
     }
 
+    #[test]
+    fn test_move_cursor_fixed_mode_larger_board() {
+        let mut game = Game::new(Mode::Local, true, Rules::new(5, 4).unwrap());
+
+        // walk to the bottom right corner and try to go further
+        for _ in 0..6 {
+            move_cursor(&mut game, Action::MoveRight);
+            move_cursor(&mut game, Action::MoveDown);
+        }
+        assert_eq!(game.cursor_pos, game.symbol_slots[24]);
+
+        // one step back up and left
+        move_cursor(&mut game, Action::MoveUp);
+        move_cursor(&mut game, Action::MoveLeft);
+        assert_eq!(game.cursor_pos, game.symbol_slots[18]);
+    }
+
     #[test]
     fn test_process_input() {
         let (tx, rx) = mpsc::channel();
-        let mut game = Game::new(Mode::Local, true);
+        let mut game = Game::new(Mode::Local, true, Rules::default());
 
         // place X
         tx.send(b'x').unwrap();
@@ -289,10 +471,216 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_undo_keys() {
+        let (tx, rx) = mpsc::channel();
+        let mut game = Game::new(Mode::Local, true, Rules::default());
+
+        // place, undo with u, redo with ctrl-r
+        for byte in [b' ', b'u'] {
+            tx.send(byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        assert_eq!(game.state.round, 0);
+
+        tx.send(b'\x12').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert_eq!(game.state.board[0], 'O');
+
+        // page up scrolls the move list without moving the cursor
+        game.cursor_pos = game.symbol_slots[1];
+        game.attempt_placing('X');
+        for byte in b"\x1B[5~" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        assert_eq!(game.history.scroll, 1);
+        assert_eq!(game.cursor_pos, game.symbol_slots[1]);
+    }
+
+    #[test]
+    fn test_chat_input() {
+        let (tx, rx) = mpsc::channel();
+        let mut game = Game::new(Mode::Network, true, Rules::default());
+        game.name = String::from("alice");
+
+        // no chat unless both players support it
+        tx.send(b'c').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert!(game.chat.is_none());
+
+        game.chat = Some(Chat::default());
+        for byte in b"cq x\x7Fo\n" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        // typing q and x neither quits nor places a mark
+        assert!(!game.quitting);
+        assert_eq!(game.state.board[0], ' ');
+        let chat = game.chat.as_ref().unwrap();
+        assert!(!chat.is_typing());
+        assert_eq!(chat.outgoing.as_deref(), Some("q o"));
+
+        // escape closes the input without sending, arrow keys don't
+        for byte in b"chi\x1B[D" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        assert!(game.chat.as_ref().unwrap().is_typing());
+        tx.send(b'\x1B').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert!(!game.chat.as_ref().unwrap().is_typing());
+        assert_eq!(game.chat.as_ref().unwrap().lines.len(), 1);
+    }
+
+    #[test]
+    fn test_replay_keys() {
+        let (tx, rx) = mpsc::channel();
+        let mut game = Game::new(Mode::Replay, false, Rules::default());
+        let record = "tic-tac-term record 1\nrules 3 3\n1. O b2\n2. X a1\nresult unfinished\n";
+        game.replay = Some(Replay::new(record.parse().unwrap(), Duration::from_secs(1)));
+
+        // arrows step through the moves, marks can't be placed
+        for byte in b"\x1B[C\x1B[C\x1B[Dx +" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        assert_eq!(game.state.board[4], 'O');
+        assert_eq!(game.state.board[0], ' ');
+        assert_eq!(game.cursor_pos, game.symbol_slots[4]);
+        let replay = game.replay.as_ref().unwrap();
+        assert!(replay.is_playing());
+        assert_eq!(replay.delay, Duration::from_millis(500));
+
+        // the speed keys follow the config like the rest
+        game.keys.bind(Action::Slower, &[Key::Char('<')]);
+        for byte in b"-<" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        let replay = game.replay.as_ref().unwrap();
+        assert_eq!(replay.delay, Duration::from_secs(1));
+
+        tx.send(b'q').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert!(game.quitting);
+    }
+
+    #[test]
+    fn test_mouse_clicks() {
+        let (tx, rx) = mpsc::channel();
+        let mut game = Game::new(Mode::Local, true, Rules::default());
+
+        // the middle cell is drawn at (7, 4), its whole width can be clicked
+        assert_eq!(game.cell_at((7, 4)), Some(4));
+        assert_eq!(game.cell_at((8, 4)), Some(4));
+        assert_eq!(game.cell_at((9, 4)), None);
+        assert_eq!(game.cell_at((7, 3)), None);
+
+        // press then release places one mark, right clicks and borders are ignored
+        for byte in b"\x1B[<0;8;4M\x1B[<0;8;4m\x1B[<2;3;2M\x1B[<0;5;2M" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        assert_eq!(game.state.board[4], 'O');
+        assert_eq!(game.state.round, 1);
+        assert_eq!(game.cursor_pos, game.symbol_slots[4]);
+
+        // garbage doesn't leak into the keys, the q is dropped with it
+        for byte in b"\x1B[<0;q;2M\x1B[<0;11;6M" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        assert!(!game.quitting);
+        assert_eq!(game.state.board[8], 'X');
+
+        // scrolling the wheel over a cell does nothing
+        for byte in b"\x1B[<64;3;6M\x1B[<0;7;6M" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        assert_eq!(game.state.board[6], ' ');
+        assert_eq!(game.state.board[7], 'O');
+
+        // in free mode clicking next to the board moves the cursor there
+        game.free_cursor = true;
+        for byte in b"\x1B[<0;30;12M" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        assert_eq!(game.cursor_pos, (30, 12));
+    }
+
+    #[test]
+    fn test_movement_keys() {
+        let (tx, rx) = mpsc::channel();
+        let mut game = Game::new(Mode::Local, true, Rules::new(4, 3).unwrap());
+
+        // vim and WASD keys, home and end
+        for (keys, cell) in [
+            (&b"ljj"[..], 9),
+            (b"kddd", 7),
+            (b"\x1B[H", 4),
+            (b"s\x1B[4~", 11),
+        ] {
+            for byte in keys {
+                tx.send(*byte).unwrap();
+            }
+            while process_input(&mut game, &rx).is_ok() {}
+            assert_eq!(game.cursor_cell(), Some(cell));
+        }
+
+        // digits only place on 3x3 boards
+        tx.send(b'7').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert_eq!(game.state.round, 0);
+
+        // they are laid out like a numpad
+        let mut game = Game::new(Mode::Local, true, Rules::default());
+        for byte in b"73" {
+            tx.send(*byte).unwrap();
+        }
+        while process_input(&mut game, &rx).is_ok() {}
+        assert_eq!(game.state.board[0], 'O');
+        assert_eq!(game.state.board[8], 'X');
+        assert_eq!(game.cursor_cell(), Some(8));
+
+        // on ultimate boards within the small board under the cursor
+        let mut game = Game::new(Mode::Local, true, Rules::ultimate());
+        game.cursor_pos = game.symbol_slots[40];
+        tx.send(b'9').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert_eq!(game.cursor_cell(), Some(32));
+        assert_eq!(game.ultimate.as_ref().unwrap().cell(32), 'O');
+    }
+
+    #[test]
+    fn test_custom_keys() {
+        let (tx, rx) = mpsc::channel();
+        let mut game = Game::new(Mode::Local, true, Rules::default());
+        let config: Config = "[keys]\nplace = \"enter\"\nquit = \"esc\"".parse().unwrap();
+        game.keys = config.keys;
+
+        // space does nothing anymore, enter places and a lone escape quits
+        tx.send(b' ').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert_eq!(game.state.round, 0);
+        tx.send(b'\r').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert_eq!(game.state.board[0], 'O');
+
+        tx.send(b'q').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert!(!game.quitting);
+        tx.send(b'\x1B').unwrap();
+        process_input(&mut game, &rx).unwrap();
+        assert!(game.quitting);
+    }
+
     #[test]
     fn test_invalid_input() {
         let (tx, rx) = mpsc::channel();
-        let mut game = Game::new(Mode::Local, true);
+        let mut game = Game::new(Mode::Local, true, Rules::default());
 
         // timeout (no input)
         let result = process_input(&mut game, &rx);
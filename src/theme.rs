@@ -0,0 +1,237 @@
+use std::{env, ffi::OsString, fmt, str::FromStr};
+
+use anyhow::anyhow;
+
+use crate::terminal::{Ansi, Color};
+
+/// How a piece of text is drawn, the default is plain text
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Style {
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub underline: bool,
+    pub blink: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    /// Both styles at once, the colour of `other` wins if both have one
+    pub fn and(self, other: Style) -> Style {
+        Style {
+            color: other.color.or(self.color),
+            bold: self.bold || other.bold,
+            underline: self.underline || other.underline,
+            blink: self.blink || other.blink,
+            reverse: self.reverse || other.reverse,
+        }
+    }
+
+    /// `text` wrapped in the escape codes of this style, and nothing else when it is plain
+    pub fn paint(&self, text: impl fmt::Display) -> String {
+        let mut codes: Vec<Ansi> = [
+            (self.bold, Ansi::Bold),
+            (self.underline, Ansi::Underline),
+            (self.blink, Ansi::Blink),
+            (self.reverse, Ansi::Reverse),
+        ]
+        .into_iter()
+        .filter_map(|(set, code)| set.then_some(code))
+        .collect();
+        codes.extend(self.color.map(Ansi::Foreground));
+
+        if codes.is_empty() {
+            return text.to_string();
+        }
+        let codes: String = codes.iter().map(Ansi::to_string).collect();
+        format!("{}{}{}", codes, text, Ansi::ResetStyle)
+    }
+}
+
+/// Styles of everything on the board that is not plain text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub x: Style,
+    pub o: Style,
+    /// Cell under the cursor, or the last move in a replay
+    pub cursor: Style,
+    /// Marks of the line that won the game
+    pub winning: Style,
+}
+
+// the themes by name, the first is the default
+const THEMES: [(&str, Theme); 3] = [
+    ("classic", Theme::CLASSIC),
+    ("high-contrast", Theme::HIGH_CONTRAST),
+    ("monochrome", Theme::MONOCHROME),
+];
+
+const PLAIN: Style = Style {
+    color: None,
+    bold: false,
+    underline: false,
+    blink: false,
+    reverse: false,
+};
+
+const REVERSE: Style = Style {
+    reverse: true,
+    ..PLAIN
+};
+
+impl Theme {
+    pub const CLASSIC: Theme = Theme {
+        x: Style {
+            color: Some(Color::Red),
+            ..PLAIN
+        },
+        o: Style {
+            color: Some(Color::Cyan),
+            ..PLAIN
+        },
+        cursor: REVERSE,
+        winning: Style {
+            color: Some(Color::Green),
+            bold: true,
+            ..PLAIN
+        },
+    };
+
+    pub const HIGH_CONTRAST: Theme = Theme {
+        x: Style {
+            color: Some(Color::BrightYellow),
+            bold: true,
+            ..PLAIN
+        },
+        o: Style {
+            color: Some(Color::BrightCyan),
+            bold: true,
+            ..PLAIN
+        },
+        cursor: REVERSE,
+        winning: Style {
+            underline: true,
+            blink: true,
+            ..PLAIN
+        },
+    };
+
+    /// No colours at all, what `NO_COLOR` gets
+    pub const MONOCHROME: Theme = Theme {
+        x: Style {
+            bold: true,
+            ..PLAIN
+        },
+        o: PLAIN,
+        cursor: REVERSE,
+        winning: Style {
+            underline: true,
+            blink: true,
+            ..PLAIN
+        },
+    };
+
+    /// Style of a mark, empty cells are plain
+    pub fn mark(&self, symbol: char) -> Style {
+        match symbol {
+            'X' => self.x,
+            'O' => self.o,
+            _ => PLAIN,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        THEMES[0].1
+    }
+}
+
+impl FromStr for Theme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        THEMES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, theme)| *theme)
+            .ok_or_else(|| {
+                let names: Vec<&str> = THEMES.iter().map(|(name, _)| *name).collect();
+                anyhow!("Unknown theme {}, pick one of {}", s, names.join(", "))
+            })
+    }
+}
+
+/// The theme picked on the command line or in the config, otherwise the default
+/// one unless `NO_COLOR` asks for no colours
+pub fn choose(picked: Option<Theme>) -> Theme {
+    choose_theme(picked, env::var_os("NO_COLOR"))
+}
+
+// per no-color.org an empty NO_COLOR doesn't count, and picking a theme overrides it
+fn choose_theme(picked: Option<Theme>, no_color: Option<OsString>) -> Theme {
+    match (picked, no_color.is_some_and(|value| !value.is_empty())) {
+        (Some(theme), _) => theme,
+        (None, true) => Theme::MONOCHROME,
+        (None, false) => Theme::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint() {
+        assert_eq!(PLAIN.paint('X'), "X");
+        assert_eq!(REVERSE.paint(' '), "\x1B[7m \x1B[0m");
+        assert_eq!(Theme::CLASSIC.x.paint('X'), "\x1B[31mX\x1B[0m");
+
+        // the cursor on a winning mark, the winning colour replaces the mark's
+        let style = Theme::CLASSIC
+            .o
+            .and(Theme::CLASSIC.winning)
+            .and(Theme::CLASSIC.cursor);
+        assert_eq!(style.paint('O'), "\x1B[1m\x1B[7m\x1B[32mO\x1B[0m");
+        assert_eq!(
+            Theme::HIGH_CONTRAST
+                .x
+                .and(Theme::HIGH_CONTRAST.winning)
+                .paint('X'),
+            "\x1B[1m\x1B[4m\x1B[5m\x1B[93mX\x1B[0m"
+        );
+    }
+
+    #[test]
+    fn test_theme_names() {
+        assert_eq!("classic".parse::<Theme>().unwrap(), Theme::default());
+        assert_eq!("monochrome".parse::<Theme>().unwrap(), Theme::MONOCHROME);
+        assert_eq!(
+            "neon".parse::<Theme>().unwrap_err().to_string(),
+            "Unknown theme neon, pick one of classic, high-contrast, monochrome"
+        );
+
+        // none of them uses colour for the cursor, it has to work without
+        for (_, theme) in THEMES {
+            assert_eq!(theme.cursor, REVERSE);
+        }
+        for style in [
+            Theme::MONOCHROME.x,
+            Theme::MONOCHROME.o,
+            Theme::MONOCHROME.winning,
+        ] {
+            assert_eq!(style.color, None);
+        }
+    }
+
+    #[test]
+    fn test_no_color() {
+        let no_color = Some(OsString::from("1"));
+        assert_eq!(choose_theme(None, None), Theme::CLASSIC);
+        assert_eq!(choose_theme(None, no_color.clone()), Theme::MONOCHROME);
+        assert_eq!(choose_theme(None, Some(OsString::new())), Theme::CLASSIC);
+        assert_eq!(
+            choose_theme(Some(Theme::HIGH_CONTRAST), no_color),
+            Theme::HIGH_CONTRAST
+        );
+    }
+}
@@ -0,0 +1,820 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{rng::Rng, secure};
+
+/// Resend a data packet if it has not been acknowledged after this long
+const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(200);
+/// How often the joiner repeats its connect request while waiting for the host
+const CONNECT_INTERVAL: Duration = Duration::from_millis(200);
+/// First wait between punches, it doubles up to `CONNECT_INTERVAL`
+const PUNCH_INTERVAL: Duration = Duration::from_millis(25);
+/// Largest chunk of stream data put in a single datagram
+const MAX_CHUNK: usize = 1024;
+/// How long a closed stream keeps resending data the peer has not acknowledged yet
+const LINGER: Duration = Duration::from_secs(2);
+/// Close is never acknowledged, so it is sent a few times to get through some loss
+const CLOSE_REPEATS: usize = 3;
+/// Connect requests not repeated for this long are given up on
+const REQUEST_EXPIRY: Duration = Duration::from_secs(1);
+/// kind + session + seq
+const HEADER_LEN: usize = 9;
+/// Data this many packets or more ahead of the next one expected is dropped without
+/// an ack, the sender resends it once it fits, so a peer can't make us buffer without limit
+const RECEIVE_WINDOW: u32 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PacketKind {
+    Connect,
+    Accept,
+    Data,
+    Ack,
+    Close,
+    /// Opens a way through the sender's NAT towards the peer, otherwise ignored
+    Punch,
+}
+
+impl From<PacketKind> for u8 {
+    fn from(kind: PacketKind) -> Self {
+        match kind {
+            PacketKind::Connect => 0,
+            PacketKind::Accept => 1,
+            PacketKind::Data => 2,
+            PacketKind::Ack => 3,
+            PacketKind::Close => 4,
+            PacketKind::Punch => 5,
+        }
+    }
+}
+
+impl TryFrom<u8> for PacketKind {
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0 => Ok(PacketKind::Connect),
+            1 => Ok(PacketKind::Accept),
+            2 => Ok(PacketKind::Data),
+            3 => Ok(PacketKind::Ack),
+            4 => Ok(PacketKind::Close),
+            5 => Ok(PacketKind::Punch),
+            _ => Err(()),
+        }
+    }
+
+    type Error = ();
+}
+
+/// Binary format (9 bytes + data):
+/// - Byte 0: Packet kind
+/// - Bytes 1-4: Session id (u32, big endian), picked by the joiner for each connection
+/// - Bytes 5-8: Sequence number (u32, big endian), only used by data and ack packets
+/// - Remaining bytes: Stream data, data packets only
+#[derive(Debug, PartialEq)]
+struct Packet {
+    kind: PacketKind,
+    session: u32,
+    seq: u32,
+    data: Vec<u8>,
+}
+
+impl Packet {
+    fn new(kind: PacketKind, session: u32, seq: u32) -> Self {
+        Packet {
+            kind,
+            session,
+            seq,
+            data: Vec::new(),
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN {
+            return None;
+        }
+
+        Some(Packet {
+            kind: bytes[0].try_into().ok()?,
+            session: u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
+            seq: u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]),
+            data: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.data.len());
+        bytes.push(self.kind.into());
+        bytes.extend(self.session.to_be_bytes());
+        bytes.extend(self.seq.to_be_bytes());
+        bytes.extend(&self.data);
+        bytes
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    next_send_seq: u32,
+    /// Sent data waiting for an ack, with the time it was last sent
+    unacked: BTreeMap<u32, (Vec<u8>, Instant)>,
+    next_recv_seq: u32,
+    /// Data that arrived ahead of a missing packet
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+    /// Data ready to be read, in order
+    incoming: VecDeque<u8>,
+    /// Handshake is done, the host accepted the stream
+    connected: bool,
+    /// No more reading or writing, either side closed the stream
+    closed: bool,
+    /// Set when we closed the stream, pending data is still resent until then
+    linger_until: Option<Instant>,
+    /// Background thread is done with the stream
+    finished: bool,
+    read_timeout: Option<Duration>,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    session: u32,
+    /// Stream was accepted rather than connected, answers repeated connect requests
+    accepted: bool,
+    inner: Mutex<Inner>,
+    readable: Condvar,
+    /// Chance of dropping any outgoing packet, for testing over loopback
+    loss: Mutex<(f64, Rng)>,
+}
+
+impl Shared {
+    fn new(socket: UdpSocket, peer: SocketAddr, session: u32, accepted: bool) -> Self {
+        Shared {
+            socket,
+            peer,
+            session,
+            accepted,
+            inner: Mutex::new(Inner {
+                connected: accepted,
+                ..Inner::default()
+            }),
+            readable: Condvar::new(),
+            loss: Mutex::new((0.0, Rng::new(session as u64))),
+        }
+    }
+
+    fn send_packet(&self, packet: &Packet) {
+        if let Ok(mut loss) = self.loss.lock() {
+            let (chance, rng) = &mut *loss;
+            if *chance > 0.0 && rng.next_f64() < *chance {
+                return;
+            }
+        }
+
+        let _ = self.socket.send_to(&packet.to_bytes(), self.peer);
+    }
+
+    fn handle_packet(&self, packet: Packet) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+
+        match packet.kind {
+            PacketKind::Data => {
+                let behind = seq_before(packet.seq, inner.next_recv_seq);
+                if !behind && packet.seq.wrapping_sub(inner.next_recv_seq) >= RECEIVE_WINDOW {
+                    return;
+                }
+
+                // always ack, the previous ack might have been the one that got lost
+                self.send_packet(&Packet::new(PacketKind::Ack, self.session, packet.seq));
+
+                if behind {
+                    // duplicate of something already delivered
+                    return;
+                }
+
+                inner.out_of_order.insert(packet.seq, packet.data);
+                let inner = &mut *inner;
+                while let Some(data) = inner.out_of_order.remove(&inner.next_recv_seq) {
+                    inner.incoming.extend(data);
+                    inner.next_recv_seq = inner.next_recv_seq.wrapping_add(1);
+                }
+                self.readable.notify_all();
+            }
+            PacketKind::Ack => {
+                inner.unacked.remove(&packet.seq);
+            }
+            PacketKind::Connect if self.accepted => {
+                // our accept got lost, the joiner is still asking
+                self.send_packet(&Packet::new(PacketKind::Accept, self.session, 0));
+            }
+            PacketKind::Accept => {
+                inner.connected = true;
+                self.readable.notify_all();
+            }
+            PacketKind::Close => {
+                inner.closed = true;
+                inner.finished = true;
+                self.readable.notify_all();
+            }
+            PacketKind::Connect | PacketKind::Punch => (),
+        }
+    }
+
+    fn retransmit(&self) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+
+        for (seq, (data, sent_at)) in inner.unacked.iter_mut() {
+            if sent_at.elapsed() >= RETRANSMIT_INTERVAL {
+                let mut packet = Packet::new(PacketKind::Data, self.session, *seq);
+                packet.data = data.clone();
+                self.send_packet(&packet);
+                *sent_at = Instant::now();
+            }
+        }
+    }
+
+    fn close(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if !inner.closed {
+                inner.closed = true;
+                inner.linger_until = Some(Instant::now() + LINGER);
+            }
+        }
+
+        self.readable.notify_all();
+    }
+
+    // after we closed the stream, says goodbye once everything sent got through
+    // or the peer stopped answering
+    fn finish_lingering(&self) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+
+        let Some(linger_until) = inner.linger_until else {
+            return;
+        };
+
+        if !inner.finished && (inner.unacked.is_empty() || Instant::now() >= linger_until) {
+            for _ in 0..CLOSE_REPEATS {
+                self.send_packet(&Packet::new(PacketKind::Close, self.session, 0));
+            }
+            inner.finished = true;
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.inner
+            .lock()
+            .map(|inner| inner.finished)
+            .unwrap_or(true)
+    }
+}
+
+// a connect request nobody has accepted yet
+struct Request {
+    peer: SocketAddr,
+    session: u32,
+    last_heard: Instant,
+}
+
+struct Endpoint {
+    socket: UdpSocket,
+    /// Open streams by peer address and session
+    streams: Mutex<HashMap<(SocketAddr, u32), Arc<Shared>>>,
+    requests: Mutex<Vec<Request>>,
+    requested: Condvar,
+}
+
+impl Endpoint {
+    // receives every packet for the socket and hands it to its stream, resends
+    // unacknowledged data, and stops once nobody uses the endpoint anymore
+    fn run(self: Arc<Self>) {
+        let mut buffer = [0; HEADER_LEN + MAX_CHUNK];
+        loop {
+            if let Ok((len, from)) = self.socket.recv_from(&mut buffer) {
+                if let Some(packet) = Packet::parse(&buffer[..len]) {
+                    self.dispatch(packet, from);
+                }
+            }
+
+            let Ok(mut streams) = self.streams.lock() else {
+                return;
+            };
+            streams.retain(|_, shared| {
+                // only we hold it, so nobody can read or write it anymore
+                if Arc::strong_count(shared) == 1 {
+                    shared.close();
+                }
+                shared.retransmit();
+                shared.finish_lingering();
+                !shared.is_finished()
+            });
+
+            if streams.is_empty() && Arc::strong_count(&self) == 1 {
+                return;
+            }
+        }
+    }
+
+    fn dispatch(&self, packet: Packet, from: SocketAddr) {
+        let Ok(streams) = self.streams.lock() else {
+            return;
+        };
+
+        if let Some(shared) = streams.get(&(from, packet.session)) {
+            shared.handle_packet(packet);
+            return;
+        }
+
+        if packet.kind != PacketKind::Connect {
+            return;
+        }
+
+        let Ok(mut requests) = self.requests.lock() else {
+            return;
+        };
+        match requests
+            .iter_mut()
+            .find(|r| r.peer == from && r.session == packet.session)
+        {
+            Some(request) => request.last_heard = Instant::now(),
+            None => requests.push(Request {
+                peer: from,
+                session: packet.session,
+                last_heard: Instant::now(),
+            }),
+        }
+        self.requested.notify_all();
+    }
+
+    fn open(&self, peer: SocketAddr, session: u32, accepted: bool) -> io::Result<Arc<Shared>> {
+        let shared = Arc::new(Shared::new(
+            self.socket.try_clone()?,
+            peer,
+            session,
+            accepted,
+        ));
+        self.streams
+            .lock()
+            .map_err(|_| io::Error::other("udp endpoint lock poisoned"))?
+            .insert((peer, session), shared.clone());
+
+        Ok(shared)
+    }
+}
+
+/// One UDP socket shared by any number of streams, incoming packets are routed
+/// to their stream by sender address and session. The host keeps one around to
+/// accept its opponent, spectators, and reconnects all on the same port.
+#[derive(Clone)]
+pub struct UdpEndpoint {
+    endpoint: Arc<Endpoint>,
+}
+
+impl UdpEndpoint {
+    pub fn bind(address: &str) -> io::Result<Self> {
+        Self::from_socket(UdpSocket::bind(address)?)
+    }
+
+    /// Takes over a socket that is already bound, and maybe punched through a NAT
+    pub fn from_socket(socket: UdpSocket) -> io::Result<Self> {
+        socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+
+        let endpoint = Arc::new(Endpoint {
+            socket,
+            streams: Mutex::new(HashMap::new()),
+            requests: Mutex::new(Vec::new()),
+            requested: Condvar::new(),
+        });
+        let worker = endpoint.clone();
+        thread::spawn(move || worker.run());
+
+        Ok(UdpEndpoint { endpoint })
+    }
+
+    /// Joins the host at `peer`, giving up after `timeout`
+    pub fn connect(&self, peer: SocketAddr, timeout: Duration) -> io::Result<UdpStream> {
+        let shared = self.endpoint.open(peer, secure::new_session_id(), false)?;
+        let lock_error = || io::Error::other("udp stream lock poisoned");
+
+        let deadline = Instant::now() + timeout;
+        let mut inner = shared.inner.lock().map_err(|_| lock_error())?;
+        while !inner.connected {
+            if Instant::now() >= deadline {
+                // lets the endpoint forget about it
+                inner.closed = true;
+                inner.finished = true;
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "host did not answer over udp",
+                ));
+            }
+
+            shared.send_packet(&Packet::new(PacketKind::Connect, shared.session, 0));
+            inner = shared
+                .readable
+                .wait_timeout(inner, CONNECT_INTERVAL)
+                .map_err(|_| lock_error())?
+                .0;
+        }
+        drop(inner);
+
+        Ok(UdpStream { shared })
+    }
+
+    /// Waits for someone to connect, until `deadline` if there is one
+    pub fn accept(&self, deadline: Option<Instant>) -> io::Result<UdpStream> {
+        let lock_error = || io::Error::other("udp endpoint lock poisoned");
+        let mut requests = self.endpoint.requests.lock().map_err(|_| lock_error())?;
+
+        loop {
+            requests.retain(|request| request.last_heard.elapsed() < REQUEST_EXPIRY);
+
+            while !requests.is_empty() {
+                let request = requests.remove(0);
+                let key = (request.peer, request.session);
+                // a repeated request can sneak in while the stream is being opened
+                let already_open = self
+                    .endpoint
+                    .streams
+                    .lock()
+                    .map_err(|_| lock_error())?
+                    .contains_key(&key);
+                if already_open {
+                    continue;
+                }
+
+                drop(requests);
+                let shared = self.endpoint.open(request.peer, request.session, true)?;
+                shared.send_packet(&Packet::new(PacketKind::Accept, shared.session, 0));
+                return Ok(UdpStream { shared });
+            }
+
+            let wait = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) => left.min(CONNECT_INTERVAL),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "nobody joined over udp",
+                        ))
+                    }
+                },
+                None => CONNECT_INTERVAL,
+            };
+            requests = self
+                .endpoint
+                .requested
+                .wait_timeout(requests, wait)
+                .map_err(|_| lock_error())?
+                .0;
+        }
+    }
+
+    /// Sends packets to `peer` so our NAT lets its packets in, while the peer does
+    /// the same from its side with its connect requests. Keeps at it, less and less
+    /// often, until one of those requests gets through or `deadline` passes.
+    pub fn punch(&self, peer: SocketAddr, deadline: Instant) -> io::Result<()> {
+        let lock_error = || io::Error::other("udp endpoint lock poisoned");
+        let packet = Packet::new(PacketKind::Punch, 0, 0).to_bytes();
+        let mut interval = PUNCH_INTERVAL;
+        let mut requests = self.endpoint.requests.lock().map_err(|_| lock_error())?;
+
+        while !requests.iter().any(|request| request.peer == peer) {
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "could not get through to the other player",
+                ));
+            };
+
+            self.endpoint.socket.send_to(&packet, peer)?;
+            requests = self
+                .endpoint
+                .requested
+                .wait_timeout(requests, interval.min(left))
+                .map_err(|_| lock_error())?
+                .0;
+            interval = (interval * 2).min(CONNECT_INTERVAL);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reliable, ordered byte stream to one peer over UDP. Data is split into
+/// numbered packets that are acknowledged by the peer and resent until they are,
+/// the receiving side puts them back in order and drops duplicates.
+#[derive(Clone)]
+pub struct UdpStream {
+    shared: Arc<Shared>,
+}
+
+impl UdpStream {
+    /// Joins the host at `address` from a fresh socket, giving up after `timeout`
+    pub fn connect(address: &str, timeout: Duration) -> io::Result<Self> {
+        let peer = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))?;
+        let bind_address = match peer {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+
+        UdpEndpoint::bind(bind_address)?.connect(peer, timeout)
+    }
+
+    /// Drops each outgoing packet with probability `chance`, to test retransmission
+    pub fn set_loss(&self, chance: f64) {
+        if let Ok(mut loss) = self.shared.loss.lock() {
+            loss.0 = chance;
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if let Ok(mut inner) = self.shared.inner.lock() {
+            inner.read_timeout = timeout;
+        }
+        Ok(())
+    }
+
+    /// Closes the stream for both sides, pending reads return end of stream.
+    /// Data already written is still delivered unless the peer has gone away.
+    pub fn shutdown(&self) {
+        self.shared.close();
+    }
+}
+
+impl Read for UdpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let lock_error = || io::Error::other("udp stream lock poisoned");
+        let mut inner = self.shared.inner.lock().map_err(|_| lock_error())?;
+        let started = Instant::now();
+
+        while inner.incoming.is_empty() {
+            if inner.closed {
+                return Ok(0);
+            }
+
+            let wait = match inner.read_timeout {
+                Some(timeout) => match timeout.checked_sub(started.elapsed()) {
+                    Some(left) => left,
+                    None => return Err(io::Error::from(io::ErrorKind::TimedOut)),
+                },
+                None => Duration::from_millis(100),
+            };
+            inner = self
+                .shared
+                .readable
+                .wait_timeout(inner, wait)
+                .map_err(|_| lock_error())?
+                .0;
+        }
+
+        let len = buf.len().min(inner.incoming.len());
+        for (slot, byte) in buf.iter_mut().zip(inner.incoming.drain(..len)) {
+            *slot = byte;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Write for UdpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self
+            .shared
+            .inner
+            .lock()
+            .map_err(|_| io::Error::other("udp stream lock poisoned"))?;
+
+        if inner.closed {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+        }
+
+        for chunk in buf.chunks(MAX_CHUNK) {
+            let seq = inner.next_send_seq;
+            inner.next_send_seq = inner.next_send_seq.wrapping_add(1);
+            inner.unacked.insert(seq, (chunk.to_vec(), Instant::now()));
+
+            let mut packet = Packet::new(PacketKind::Data, self.shared.session, seq);
+            packet.data = chunk.to_vec();
+            self.shared.send_packet(&packet);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// whether sequence number `a` came before `b`, they wrap around after u32::MAX
+fn seq_before(a: u32, b: u32) -> bool {
+    a != b && b.wrapping_sub(a) < 1 << 31
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{read_stream, write_stream, Message, MessageType};
+    use std::io::{BufReader, BufWriter};
+
+    fn host_endpoint() -> (UdpEndpoint, String) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        (UdpEndpoint::from_socket(socket).unwrap(), address)
+    }
+
+    fn stream_pair() -> (UdpStream, UdpStream) {
+        let (endpoint, address) = host_endpoint();
+        let host = thread::spawn(move || endpoint.accept(None).unwrap());
+        let joiner = UdpStream::connect(&address, Duration::from_secs(5)).unwrap();
+        (host.join().unwrap(), joiner)
+    }
+
+    #[test]
+    fn test_packet_conversions() {
+        let mut packet = Packet::new(PacketKind::Data, 7, 300);
+        packet.data = vec![1, 2, 3];
+
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes, vec![2, 0, 0, 0, 7, 0, 0, 1, 44, 1, 2, 3]);
+        assert_eq!(Packet::parse(&bytes), Some(packet));
+
+        // too short and unknown kind
+        assert_eq!(Packet::parse(&[2, 0, 0]), None);
+        assert_eq!(Packet::parse(&[9, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_messages_over_lossy_udp() {
+        let (host, joiner) = stream_pair();
+        host.set_loss(0.3);
+        joiner.set_loss(0.3);
+
+        let mut writer = BufWriter::new(joiner.clone());
+        for i in 0..50u8 {
+            write_stream(
+                &mut writer,
+                Message::new(MessageType::Payload, vec![i; 3]).into(),
+            )
+            .unwrap();
+        }
+
+        // every message arrives exactly once and in order
+        let mut reader = BufReader::new(host.clone());
+        for i in 0..50u8 {
+            let msg = read_stream(&mut reader).unwrap();
+            assert_eq!(msg.payload, vec![i; 3]);
+        }
+
+        host.set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        assert!(read_stream(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_large_write_is_chunked() {
+        let (host, mut joiner) = stream_pair();
+        let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+        joiner.write_all(&data).unwrap();
+
+        let mut received = vec![0; data.len()];
+        let mut host_reader = host.clone();
+        host_reader.read_exact(&mut received).unwrap();
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn test_sequence_numbers_wrap_around() {
+        assert!(seq_before(1, 2));
+        assert!(seq_before(u32::MAX, 0));
+        assert!(!seq_before(0, u32::MAX));
+        assert!(!seq_before(5, 5));
+
+        let (host, mut joiner) = stream_pair();
+        joiner.shared.inner.lock().unwrap().next_send_seq = u32::MAX - 1;
+        host.shared.inner.lock().unwrap().next_recv_seq = u32::MAX - 1;
+        let data: Vec<u8> = (0..4000).map(|i| (i % 251) as u8).collect();
+        joiner.write_all(&data).unwrap();
+
+        let mut received = vec![0; data.len()];
+        host.clone().read_exact(&mut received).unwrap();
+        assert_eq!(received, data);
+        assert_eq!(host.shared.inner.lock().unwrap().next_recv_seq, 2);
+    }
+
+    #[test]
+    fn test_data_far_ahead_is_dropped() {
+        let (host, _joiner) = stream_pair();
+        let next = host.shared.inner.lock().unwrap().next_recv_seq;
+
+        for seq in [
+            next + 1,
+            next + RECEIVE_WINDOW,
+            next + RECEIVE_WINDOW + 1,
+            next.wrapping_sub(1),
+        ] {
+            let mut packet = Packet::new(PacketKind::Data, host.shared.session, seq);
+            packet.data = vec![1];
+            host.shared.handle_packet(packet);
+        }
+
+        // only the one inside the window is kept for later
+        let inner = host.shared.inner.lock().unwrap();
+        assert_eq!(
+            inner.out_of_order.keys().collect::<Vec<_>>(),
+            vec![&(next + 1)]
+        );
+        assert!(inner.incoming.is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_ends_stream_for_peer() {
+        let (mut host, joiner) = stream_pair();
+        joiner.shutdown();
+
+        let mut buffer = [0; 1];
+        assert_eq!(host.read(&mut buffer).unwrap(), 0);
+        assert!(joiner.clone().write(&[1]).is_err());
+    }
+
+    #[test]
+    fn test_connect_timeout() {
+        // nobody listening on this socket
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = silent.local_addr().unwrap().to_string();
+        assert!(UdpStream::connect(&address, Duration::from_millis(300)).is_err());
+    }
+
+    #[test]
+    fn test_punch_until_the_peer_gets_through() {
+        let (endpoint, _) = host_endpoint();
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_address = peer.local_addr().unwrap();
+
+        // the peer's NAT only lets it answer after a while
+        let punching = thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            endpoint.punch(peer_address, deadline).map(|_| endpoint)
+        });
+        let mut buffer = [0; HEADER_LEN];
+        let mut punches = 0;
+        while punches < 5 {
+            let (len, from) = peer.recv_from(&mut buffer).unwrap();
+            assert_eq!(
+                Packet::parse(&buffer[..len]).unwrap().kind,
+                PacketKind::Punch
+            );
+            punches += 1;
+            if punches == 5 {
+                let connect = Packet::new(PacketKind::Connect, 1, 0).to_bytes();
+                peer.send_to(&connect, from).unwrap();
+            }
+        }
+        let endpoint = punching.join().unwrap().unwrap();
+
+        // and one that never does
+        let deadline = Instant::now() + Duration::from_millis(300);
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let result = endpoint.punch(silent.local_addr().unwrap(), deadline);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_streams_share_an_endpoint() {
+        let (endpoint, address) = host_endpoint();
+        let accepting = endpoint.clone();
+        let host = thread::spawn(move || {
+            let first = accepting.accept(None).unwrap();
+            let second = accepting.accept(None).unwrap();
+            (first, second)
+        });
+
+        let mut first = UdpStream::connect(&address, Duration::from_secs(5)).unwrap();
+        let mut second = UdpStream::connect(&address, Duration::from_secs(5)).unwrap();
+        let (mut host_first, mut host_second) = host.join().unwrap();
+
+        // each stream only sees its own data
+        first.write_all(b"one").unwrap();
+        second.write_all(b"two").unwrap();
+        let mut buffer = [0; 3];
+        host_second.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"two");
+        host_first.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"one");
+
+        // nobody else waiting
+        let deadline = Instant::now() + Duration::from_millis(300);
+        assert!(endpoint.accept(Some(deadline)).is_err());
+    }
+}
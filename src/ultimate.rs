@@ -0,0 +1,288 @@
+use anyhow::anyhow;
+
+use crate::game::{Rules, State};
+
+/// Marks a finished sub-board that nobody won on the big board
+pub const DRAWN_BOARD: char = '#';
+
+/// Names of the sub-boards in board order, used in the ui
+pub const BOARD_NAMES: [&str; 9] = [
+    "top left",
+    "top",
+    "top right",
+    "left",
+    "center",
+    "right",
+    "bottom left",
+    "bottom",
+    "bottom right",
+];
+
+/// Ultimate tic-tac-toe, nine classic boards inside a big one.
+/// The cell a mark is placed in decides which sub-board the opponent has to play next,
+/// winning a sub-board claims that cell of the big board.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UltimateState {
+    /// The big board, each cell is the winner of that sub-board. Also keeps round,
+    /// current player and the overall result for the whole game
+    pub meta: State,
+    pub boards: Vec<State>,
+    /// Sub-board the current player has to play in, None if free to choose
+    pub forced_board: Option<usize>,
+}
+
+impl Default for UltimateState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UltimateState {
+    /// Width and height of the full grid
+    pub const SIZE: u8 = 9;
+
+    pub fn new() -> Self {
+        UltimateState {
+            meta: State::new(Rules::default()),
+            boards: (0..9).map(|_| State::new(Rules::default())).collect(),
+            forced_board: None,
+        }
+    }
+
+    pub fn restart(&mut self) {
+        *self = UltimateState::new();
+    }
+
+    /// Splits a row-major index into the 9x9 grid into (sub-board, cell in sub-board)
+    pub fn split(index: usize) -> (usize, usize) {
+        let (row, col) = (index / 9, index % 9);
+        ((row / 3) * 3 + col / 3, (row % 3) * 3 + col % 3)
+    }
+
+    /// Inverse of `split`
+    pub fn join(board: usize, cell: usize) -> usize {
+        let row = (board / 3) * 3 + cell / 3;
+        let col = (board % 3) * 3 + cell % 3;
+        row * 9 + col
+    }
+
+    /// Mark at a row-major index into the 9x9 grid
+    pub fn cell(&self, index: usize) -> char {
+        let (board, cell) = Self::split(index);
+        self.boards[board].board[cell]
+    }
+
+    /// Can the current player place marks in `board`
+    pub fn is_playable(&self, board: usize) -> bool {
+        self.meta.active
+            && self.boards[board].active
+            && self.forced_board.is_none_or(|forced| forced == board)
+    }
+
+    /// Places `symbol` at a row-major index into the 9x9 grid if the move is legal
+    pub fn place(&mut self, index: usize, symbol: char) -> bool {
+        let (board, cell) = Self::split(index);
+
+        if !self.is_playable(board)
+            || self.meta.current_player != symbol.into()
+            || self.boards[board].board[cell] != ' '
+        {
+            return false;
+        }
+
+        self.boards[board].board[cell] = symbol;
+        self.meta.round += 1;
+        self.meta.current_player = self.meta.current_player.toggle();
+        self.check_status();
+
+        // send the opponent to the board matching the cell, unless it is already decided
+        self.forced_board = match self.boards[cell].active {
+            true => Some(cell),
+            false => None,
+        };
+
+        true
+    }
+
+    /// Checks every sub-board and then the big board made up of their results
+    pub fn check_status(&mut self) {
+        for (board, sub_board) in self.boards.iter_mut().enumerate() {
+            sub_board.check_status();
+            self.meta.board[board] = match &sub_board.winner {
+                Some(player) => player.into(),
+                None if !sub_board.active => DRAWN_BOARD,
+                None => ' ',
+            };
+        }
+
+        self.meta.check_status();
+    }
+}
+
+/// Binary format (85 bytes):
+/// - Bytes 0-80: Cells ('X', 'O', or ' '), sub-board by sub-board, 9 cells each
+/// - Byte 81: Forced sub-board (0-8, or 255 if free to choose)
+/// - Bytes 82-83: Round count (u16, big endian)
+/// - Byte 84: Flag byte, same layout as the classic `State` flags
+///
+/// Sub-board results and the big board are recomputed from the cells.
+impl TryFrom<&[u8]> for UltimateState {
+    fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() != 85 {
+            return Err(anyhow!(
+                "Ultimate state can only be deserialized from 85 bytes"
+            ));
+        }
+
+        let mut state = UltimateState::new();
+        for (board, sub_board) in state.boards.iter_mut().enumerate() {
+            for (cell, c) in sub_board.board.iter_mut().enumerate() {
+                *c = match bytes[board * 9 + cell] {
+                    b'X' => 'X',
+                    b'O' => 'O',
+                    _ => ' ',
+                };
+            }
+        }
+
+        state.forced_board = match bytes[81] {
+            board @ 0..=8 => Some(board as usize),
+            255 => None,
+            _ => return Err(anyhow!("Invalid forced sub-board")),
+        };
+
+        state.check_status();
+
+        // reuse the classic flag decoding for round, current player, active and winner
+        let mut meta_bytes: Vec<u8> = (&state.meta).into();
+        let flags_at = meta_bytes.len() - 3;
+        meta_bytes[flags_at..].copy_from_slice(&bytes[82..85]);
+        let meta = State::try_from(meta_bytes.as_slice())?;
+
+        state.meta.round = meta.round;
+        state.meta.current_player = meta.current_player;
+        state.meta.active = meta.active;
+        state.meta.winner = meta.winner;
+
+        Ok(state)
+    }
+
+    type Error = anyhow::Error;
+}
+
+impl From<&UltimateState> for Vec<u8> {
+    fn from(state: &UltimateState) -> Self {
+        let mut bytes: Vec<u8> = Vec::with_capacity(85);
+
+        for sub_board in &state.boards {
+            for c in &sub_board.board {
+                bytes.push(match c {
+                    'X' => b'X',
+                    'O' => b'O',
+                    _ => b' ',
+                });
+            }
+        }
+
+        bytes.push(state.forced_board.map(|board| board as u8).unwrap_or(255));
+
+        // round and flags are the last 3 bytes of the classic format
+        let meta_bytes: Vec<u8> = (&state.meta).into();
+        bytes.extend(&meta_bytes[meta_bytes.len() - 3..]);
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Player;
+
+    #[test]
+    fn test_split_and_join() {
+        assert_eq!(UltimateState::split(0), (0, 0));
+        assert_eq!(UltimateState::split(8), (2, 2));
+        assert_eq!(UltimateState::split(9 * 4 + 4), (4, 4));
+        assert_eq!(UltimateState::split(80), (8, 8));
+
+        for index in 0..81 {
+            let (board, cell) = UltimateState::split(index);
+            assert_eq!(UltimateState::join(board, cell), index);
+        }
+    }
+
+    #[test]
+    fn test_send_to_board_rule() {
+        let mut state = UltimateState::new();
+
+        // O opens in the center board, top right cell
+        assert!(state.place(UltimateState::join(4, 2), 'O'));
+        assert_eq!(state.forced_board, Some(2));
+        assert_eq!(state.meta.current_player, Player::X);
+
+        // X has to play in the top right board
+        assert!(!state.place(UltimateState::join(5, 0), 'X'));
+        assert!(state.place(UltimateState::join(2, 4), 'X'));
+        assert_eq!(state.forced_board, Some(4));
+
+        // wrong player
+        assert!(!state.place(UltimateState::join(4, 0), 'X'));
+        // occupied cell
+        assert!(!state.place(UltimateState::join(4, 2), 'O'));
+    }
+
+    #[test]
+    fn test_finished_board_frees_choice() {
+        let mut state = UltimateState::new();
+        state.boards[0].board = "OOO      ".chars().collect();
+        state.check_status();
+        assert_eq!(state.meta.board[0], 'O');
+
+        // being sent to a won board means free choice
+        state.forced_board = Some(1);
+        assert!(state.place(UltimateState::join(1, 0), 'O'));
+        assert_eq!(state.forced_board, None);
+
+        // but a won board can't be played in
+        assert!(!state.place(UltimateState::join(0, 5), 'X'));
+        assert!(state.place(UltimateState::join(3, 3), 'X'));
+    }
+
+    #[test]
+    fn test_drawn_board_and_meta_win() {
+        let mut state = UltimateState::new();
+        state.boards[0].board = "XOXXOOOXX".chars().collect();
+        state.boards[1].board = "XXX      ".chars().collect();
+        state.boards[2].board = "X  X  X  ".chars().collect();
+        state.boards[4].board = "X   X   X".chars().collect();
+        state.check_status();
+
+        assert_eq!(state.meta.board[0], DRAWN_BOARD);
+        assert!(state.meta.active);
+
+        state.boards[5].board = "X  X  X  ".chars().collect();
+        state.boards[3].board = "X  X  X  ".chars().collect();
+        state.check_status();
+        assert_eq!(state.meta.winner, Some(Player::X));
+        assert!(!state.meta.active);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut state = UltimateState::new();
+        state.place(UltimateState::join(4, 2), 'O');
+        state.place(UltimateState::join(2, 4), 'X');
+        state.place(UltimateState::join(4, 8), 'O');
+
+        let bytes: Vec<u8> = (&state).into();
+        assert_eq!(bytes.len(), 85);
+
+        let deserialized = UltimateState::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(deserialized, state);
+
+        // classic states are not ultimate states
+        let classic: Vec<u8> = (&State::new(Rules::default())).into();
+        assert!(UltimateState::try_from(classic.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! A rendezvous server and two players, each in a process of its own on loopback,
+//! the players meet through the server and punch through to each other
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+// killed when the test is done with it, whether it passed or not
+struct Process(Child);
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+impl Process {
+    fn stdout(&mut self) -> ChildStdout {
+        self.0.stdout.take().unwrap()
+    }
+}
+
+// no terminal and a home without config or stats, so nothing of the user's is touched
+fn spawn(args: &[&str], home: &Path) -> Process {
+    let child = Command::new(env!("CARGO_BIN_EXE_tic-tac-term"))
+        .args(args)
+        .env("HOME", home)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_DATA_HOME")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    Process(child)
+}
+
+// reads the screen a player draws until `expected` shows up on it
+fn wait_for_screen(stdout: ChildStdout, expected: &str) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in BufReader::new(stdout).bytes() {
+            let Ok(byte) = byte else {
+                break;
+            };
+            if tx.send(byte).is_err() {
+                break;
+            }
+        }
+    });
+
+    // the screen is redrawn over and over, only the latest bytes are kept
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let mut screen = Vec::new();
+    while !screen.ends_with(expected.as_bytes()) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(left) {
+            Ok(byte) => screen.push(byte),
+            Err(_) => panic!(
+                "{} never showed up, the screen ended in {:?}",
+                expected,
+                String::from_utf8_lossy(&screen)
+            ),
+        }
+        if screen.len() > 4096 {
+            screen.drain(..2048);
+        }
+    }
+}
+
+#[test]
+fn test_players_meet_through_rendezvous() {
+    let home = env::temp_dir().join(format!("tic-tac-term-test-{}", std::process::id()));
+
+    let mut server = spawn(&["rendezvous", "127.0.0.1:0"], &home);
+    // kept open, the server logs every player it sees
+    let mut server_log = BufReader::new(server.stdout());
+    let mut line = String::new();
+    server_log.read_line(&mut line).unwrap();
+    let address = line
+        .trim()
+        .strip_prefix("rendezvous server listening on ")
+        .unwrap()
+        .to_string();
+
+    let meet = ["--rendezvous", &address, "--code", "pizza", "--name"];
+    let mut host = spawn(&[&["host"], &meet[..], &["alice"]].concat(), &home);
+    let mut joiner = spawn(&[&["join"], &meet[..], &["bob"]].concat(), &home);
+
+    // both only draw the names once the game is on
+    wait_for_screen(host.stdout(), "O: alice (1200)  X: bob (1200)");
+    wait_for_screen(joiner.stdout(), "O: alice (1200)  X: bob (1200)");
+}